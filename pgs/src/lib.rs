@@ -1,5 +1,5 @@
 use std::{
-    io::{Cursor, Read},
+    io::{Cursor, Read, Write},
     time::Duration,
 };
 
@@ -28,9 +28,22 @@ pub enum LastInSequenceFlag {
     Last,
     First,
     FirstAndLast,
+    /// neither the first nor the last fragment of an object split over more than two segments.
+    Middle,
+}
+
+impl LastInSequenceFlag {
+    pub fn is_first(&self) -> bool {
+        matches!(self, Self::First | Self::FirstAndLast)
+    }
+
+    pub fn is_last(&self) -> bool {
+        matches!(self, Self::Last | Self::FirstAndLast)
+    }
 }
 
 #[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
 pub enum Segment {
     PCS(PCS),
     WDS(WDS),
@@ -54,6 +67,9 @@ pub struct CompositionObject {
     pub horizontal_position: u16,
     pub vertical_position: u16,
     pub cropping: Option<CompositionObjectCropping>,
+    /// the object is shown even when the player has subtitles turned off, used for
+    /// translations of foreign dialogue.
+    pub forced: bool,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -96,27 +112,103 @@ impl PaletteEntry {
 
 #[derive(Debug, Clone, Copy)]
 pub struct Header {
-    /// presentation time stamp (measured in ticks of 90khz clock)
-    pub pts: u32,
+    /// presentation time stamp (measured in ticks of 90khz clock).
+    /// mpeg timestamps are 33 bit but [`DisplaySetReader`] unwraps them, so the value can be
+    /// larger when the stream wraps around.
+    pub pts: u64,
     /// decoding time stamp (measured in ticks of 90khz clock)
-    pub dts: u32,
+    pub dts: u64,
 }
 
 impl From<wire::SegmentHeader> for Header {
     fn from(value: wire::SegmentHeader) -> Self {
         Self {
-            pts: value.pts,
-            dts: value.dts,
+            pts: u64::from(value.pts),
+            dts: u64::from(value.dts),
         }
     }
 }
 
+/// ticks per second of the clock used by the pts and dts timestamps.
+pub const CLOCK_RATE: u64 = 90_000;
+/// mpeg timestamps are 33 bit and wrap around after about 26.5 hours.
+pub const TIMESTAMP_BITS: u32 = 33;
+/// the sup format only stores the lower 32 bits of the timestamps, so they wrap around after
+/// about 13.25 hours.
+pub const SUP_TIMESTAMP_BITS: u32 = 32;
+
+/// Turns timestamps that wrap around after reaching `2^bits` into an increasing timeline.
+#[derive(Debug, Clone)]
+pub struct TimestampUnwrapper {
+    bits: u32,
+    last: Option<u64>,
+    offset: u64,
+}
+
+impl TimestampUnwrapper {
+    pub fn new(bits: u32) -> Self {
+        Self {
+            bits,
+            last: None,
+            offset: 0,
+        }
+    }
+
+    fn period(&self) -> u64 {
+        1 << self.bits
+    }
+
+    /// number of times the timestamps wrapped around so far.
+    pub fn wraps(&self) -> u64 {
+        self.offset / self.period()
+    }
+
+    /// unwrap the next timestamp of the stream.
+    ///
+    /// a timestamp that is more than half the range smaller than the previous one is
+    /// considered to have wrapped around, one that is more than half the range larger is
+    /// considered to be from before the last wrap around.
+    pub fn unwrap(&mut self, timestamp: u64) -> u64 {
+        let period = self.period();
+        let timestamp = timestamp & (period - 1);
+        if let Some(last) = self.last {
+            if last > timestamp && last - timestamp > period / 2 {
+                self.offset += period;
+            } else if timestamp > last && timestamp - last > period / 2 {
+                return (timestamp + self.offset).saturating_sub(period);
+            }
+        }
+        self.last = Some(timestamp);
+        timestamp + self.offset
+    }
+
+    /// unwrap a timestamp so it is as close as possible to an already unwrapped timestamp,
+    /// like the timestamps of the other segments of a display set.
+    pub fn unwrap_near(&self, timestamp: u64, reference: u64) -> u64 {
+        let period = self.period();
+        let timestamp = timestamp & (period - 1);
+        let base = reference - reference % period;
+        [
+            base.checked_sub(period),
+            Some(base),
+            base.checked_add(period),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|base| base + timestamp)
+        .min_by_key(|candidate| candidate.abs_diff(reference))
+        .unwrap_or(timestamp)
+    }
+}
+
 /// Presentation Composition Segment
 #[derive(Debug, Clone)]
 pub struct PCS {
     pub header: Header,
     pub width: u16,
     pub height: u16,
+    /// always 0x10, see [`wire::FRAME_RATE`].
+    pub framerate: u8,
     /// identifies this Graphics Update in the current Display Segment.
     /// in range 0 - 15
     pub composition_number: u16,
@@ -143,7 +235,41 @@ pub struct PDS {
     pub palette_id: u8,
     /// version of the palette within the epoch
     pub palette_version: u8,
+    /// entries that were not present in the segment are left zeroed.
     pub entries: [PaletteEntry; 256],
+    /// which entries were present in the segment, one bit per entry id.
+    pub defined: [u64; 4],
+}
+
+impl PDS {
+    /// a palette without any entry.
+    pub fn new(header: Header, palette_id: u8, palette_version: u8) -> Self {
+        Self {
+            header,
+            palette_id,
+            palette_version,
+            entries: [PaletteEntry::default(); 256],
+            defined: [0; 4],
+        }
+    }
+
+    pub fn is_defined(&self, entry_id: u8) -> bool {
+        self.defined[usize::from(entry_id / 64)] & (1 << (entry_id % 64)) != 0
+    }
+
+    /// define the entry with the id of `entry`.
+    pub fn set_entry(&mut self, entry: PaletteEntry) {
+        let entry_id = entry.entry_id;
+        self.entries[usize::from(entry_id)] = entry;
+        self.defined[usize::from(entry_id / 64)] |= 1 << (entry_id % 64);
+    }
+
+    /// the entries present in the segment, by increasing id.
+    pub fn defined_entries(&self) -> impl Iterator<Item = &PaletteEntry> {
+        (0..=255)
+            .filter(|&entry_id| self.is_defined(entry_id))
+            .map(|entry_id| &self.entries[usize::from(entry_id)])
+    }
 }
 
 /// Object Definition Segment
@@ -156,15 +282,67 @@ pub struct ODS {
     pub object_id: u16,
     /// version of the object within the epoch
     pub object_version: u8,
+    /// objects too large for a single segment are split into a first fragment, zero or more
+    /// middle fragments and a last fragment.
     pub last_in_sequence: LastInSequenceFlag,
+    /// length of the rle data of all fragments plus 4, for the width and height.
+    /// only present in the first fragment, zero in the others.
+    pub object_data_length: u32,
     /// the width for an object id should always be the same for a given epoch.
+    /// only present in the first fragment, zero in the others.
     pub width: u16,
     /// the height for an object id should always be the same for a given epoch.
+    /// only present in the first fragment, zero in the others.
     pub height: u16,
-    /// vector with rle image data
+    /// vector with the rle image data of this fragment
     pub data: Vec<u8>,
 }
 
+impl ODS {
+    /// split the rle data of an object into as many segments as needed.
+    pub fn fragments(
+        header: Header,
+        object_id: u16,
+        object_version: u8,
+        width: u16,
+        height: u16,
+        data: &[u8],
+    ) -> Vec<ODS> {
+        // the segment size is 16 bits and includes the object id, version and flag fields.
+        // the first fragment also has the data length, width and height
+        const MAX_FIRST_DATA: usize = u16::MAX as usize - 11;
+        const MAX_DATA: usize = u16::MAX as usize - 4;
+
+        let first_len = data.len().min(MAX_FIRST_DATA);
+        let mut chunks = vec![&data[..first_len]];
+        chunks.extend(data[first_len..].chunks(MAX_DATA));
+        let last = chunks.len() - 1;
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(index, chunk)| {
+                let last_in_sequence = match (index == 0, index == last) {
+                    (true, true) => LastInSequenceFlag::FirstAndLast,
+                    (true, false) => LastInSequenceFlag::First,
+                    (false, true) => LastInSequenceFlag::Last,
+                    (false, false) => LastInSequenceFlag::Middle,
+                };
+                let first = index == 0;
+                ODS {
+                    header,
+                    object_id,
+                    object_version,
+                    last_in_sequence,
+                    object_data_length: if first { (data.len() + 4) as u32 } else { 0 },
+                    width: if first { width } else { 0 },
+                    height: if first { height } else { 0 },
+                    data: chunk.to_vec(),
+                }
+            })
+            .collect()
+    }
+}
+
 /// END of display set segment
 #[derive(Debug, Clone)]
 pub struct END {
@@ -195,10 +373,15 @@ pub fn decode_segment_reader<R: Read>(mut reader: R) -> std::io::Result<Segment>
         ));
     }
 
-    let mut buffer = Vec::new();
-    buffer.resize(header.segment_size as usize, 0);
+    let mut buffer = vec![0; header.segment_size as usize];
     reader.read_exact(&mut buffer)?;
-    let mut cursor = Cursor::new(&buffer);
+    decode_segment_body(header, &buffer)
+}
+
+fn decode_segment_body(header: wire::SegmentHeader, buffer: &[u8]) -> std::io::Result<Segment> {
+    use wire::Wire;
+
+    let mut cursor = Cursor::new(buffer);
 
     match header.segment_type {
         wire::SEGMENT_TYPE_PCS => {
@@ -207,20 +390,23 @@ pub fn decode_segment_reader<R: Read>(mut reader: R) -> std::io::Result<Segment>
 
             for _ in 0..pcs.number_of_composition_objects {
                 let object = wire::CompositionObject::read(&mut cursor)?;
-                let cropping = match object.object_cropped_flag {
+                let flags = object.object_cropped_flag;
+                if flags & !(wire::OBJECT_CROPPED_FLAG_CROPPED | wire::OBJECT_CROPPED_FLAG_FORCED)
+                    != 0
+                {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "invalid object cropped flag",
+                    ));
+                }
+                let cropping = match flags & wire::OBJECT_CROPPED_FLAG_CROPPED {
                     wire::OBJECT_CROPPED_FLAG_OFF => None,
-                    wire::OBJECT_CROPPED_FLAG_FORCE => Some(CompositionObjectCropping {
+                    _ => Some(CompositionObjectCropping {
                         width: object.object_cropping_width,
                         height: object.object_cropping_height,
                         horizontal_position: object.object_cropping_horizontal_position,
                         vertical_position: object.object_cropping_vertical_position,
                     }),
-                    _ => {
-                        return Err(std::io::Error::new(
-                            std::io::ErrorKind::InvalidData,
-                            "invalid object cropped flag",
-                        ))
-                    }
                 };
                 objects.push(CompositionObject {
                     object_id: object.object_id,
@@ -228,6 +414,7 @@ pub fn decode_segment_reader<R: Read>(mut reader: R) -> std::io::Result<Segment>
                     horizontal_position: object.object_horizontal_position,
                     vertical_position: object.object_vertical_position,
                     cropping,
+                    forced: flags & wire::OBJECT_CROPPED_FLAG_FORCED != 0,
                 });
             }
 
@@ -258,6 +445,7 @@ pub fn decode_segment_reader<R: Read>(mut reader: R) -> std::io::Result<Segment>
                 header: Header::from(header),
                 width: pcs.width,
                 height: pcs.height,
+                framerate: pcs.framerate,
                 composition_number: pcs.composition_number,
                 composition_state,
                 palette_update,
@@ -284,32 +472,34 @@ pub fn decode_segment_reader<R: Read>(mut reader: R) -> std::io::Result<Segment>
             }))
         }
         wire::SEGMENT_TYPE_PDS => {
-            let pds = wire::SegmentPDS::read(&mut cursor)?;
-            let mut entries: [PaletteEntry; 256] = unsafe { std::mem::zeroed() };
+            let segment = wire::SegmentPDS::read(&mut cursor)?;
+            let mut pds = PDS::new(
+                Header::from(header),
+                segment.palette_id,
+                segment.palette_version,
+            );
             while cursor.position() < buffer.len() as u64 {
                 let entry = wire::PaletteEntry::read(&mut cursor)?;
-                entries[entry.palette_entry_id as usize] = PaletteEntry {
+                pds.set_entry(PaletteEntry {
                     entry_id: entry.palette_entry_id,
                     luminance: entry.luminance,
                     color_diff_red: entry.color_diff_red,
                     color_diff_blue: entry.color_diff_blue,
                     transparency: entry.transparency,
-                };
+                });
             }
-            Ok(Segment::PDS(PDS {
-                header: Header::from(header),
-                palette_id: pds.palette_id,
-                palette_version: pds.palette_version,
-                entries,
-            }))
+            Ok(Segment::PDS(pds))
         }
         wire::SEGMENT_TYPE_ODS => {
             let ods = wire::SegmentODS::read(&mut cursor)?;
-            let mut data = vec![0u8; ods.object_data_length.saturating_sub(4) as usize];
-            cursor.read_exact(&mut data)?;
+            // the object data length is the length of the whole object, a fragment only has
+            // the data up to the end of the segment
+            let mut data = Vec::new();
+            cursor.read_to_end(&mut data)?;
 
             let flag = match ods.last_in_sequence_flag {
                 wire::LAST_IN_SEQUENCE_FLAG_FIRST_IN_SEQ => LastInSequenceFlag::First,
+                wire::LAST_IN_SEQUENCE_FLAG_MIDDLE_IN_SEQ => LastInSequenceFlag::Middle,
                 wire::LAST_IN_SEQUENCE_FLAG_LAST_IN_SEQ => LastInSequenceFlag::Last,
                 wire::LAST_IN_SEQUENCE_FLAG_FIRST_AND_LAST_IN_SEQ => {
                     LastInSequenceFlag::FirstAndLast
@@ -327,6 +517,7 @@ pub fn decode_segment_reader<R: Read>(mut reader: R) -> std::io::Result<Segment>
                 object_id: ods.object_id,
                 object_version: ods.object_version,
                 last_in_sequence: flag,
+                object_data_length: ods.object_data_length,
                 width: ods.width,
                 height: ods.height,
                 data,
@@ -335,12 +526,10 @@ pub fn decode_segment_reader<R: Read>(mut reader: R) -> std::io::Result<Segment>
         wire::SEGMENT_TYPE_END => Ok(Segment::END(END {
             header: Header::from(header),
         })),
-        _ => {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "invalid segment type",
-            ))
-        }
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "invalid segment type",
+        )),
     }
 }
 
@@ -391,16 +580,660 @@ pub fn decode_display_sets(data: &[u8]) -> std::io::Result<Vec<DisplaySet>> {
     decode_display_sets_reader(Cursor::new(data))
 }
 
-pub fn decode_display_sets_reader<R: Read>(mut reader: R) -> std::io::Result<Vec<DisplaySet>> {
-    let mut display_sets = Vec::default();
-    loop {
-        match decode_display_set_reader(&mut reader) {
-            Ok(display_set) => display_sets.push(display_set),
-            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
-            Err(err) => return Err(err),
+pub fn decode_display_sets_reader<R: Read>(reader: R) -> std::io::Result<Vec<DisplaySet>> {
+    DisplaySetReader::new(reader).collect()
+}
+
+/// How [`DisplaySetReader`] handles malformed data.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DecodeMode {
+    /// stop at the first error.
+    #[default]
+    Strict,
+    /// skip the display sets with invalid segments, resyncing on the next segment magic number
+    /// when the stream is corrupt, and report them as [`DecodeWarning`]s.
+    Resilient,
+}
+
+/// Malformed data skipped by a [`DisplaySetReader`] in [`DecodeMode::Resilient`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeWarning {
+    /// byte offset in the stream where the malformed data starts.
+    pub offset: u64,
+    /// number of bytes skipped to get back in sync, zero when whole segments were skipped.
+    pub skipped: u64,
+    pub reason: String,
+}
+
+impl std::fmt::Display for DecodeWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "at offset {}: {}", self.offset, self.reason)?;
+        if self.skipped > 0 {
+            write!(f, " ({} bytes skipped)", self.skipped)?;
         }
+        Ok(())
     }
-    Ok(display_sets)
+}
+
+/// segments of a display set read so far.
+struct PartialDisplaySet {
+    offset: u64,
+    pcs: PCS,
+    wds: Vec<WDS>,
+    pds: Vec<PDS>,
+    ods: Vec<ODS>,
+}
+
+type WarningCallback = Box<dyn FnMut(DecodeWarning) + Send>;
+
+/// Pull based decoder that reads one [`Segment`] at a time, together with its byte offset in
+/// the stream.
+///
+/// Unlike [`DisplaySetReader`] the timestamps are returned as stored in the stream. In
+/// [`DecodeMode::Resilient`] segments that fail to decode are skipped and reported as
+/// [`DecodeWarning`]s.
+pub struct SegmentReader<R> {
+    reader: R,
+    /// bytes given back while resyncing, in reverse order.
+    pushback: Vec<u8>,
+    position: u64,
+    mode: DecodeMode,
+    on_warning: Option<WarningCallback>,
+    done: bool,
+}
+
+impl<R> std::fmt::Debug for SegmentReader<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SegmentReader")
+            .field("position", &self.position)
+            .field("mode", &self.mode)
+            .field("done", &self.done)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<R: Read> SegmentReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            pushback: Vec::new(),
+            position: 0,
+            mode: DecodeMode::default(),
+            on_warning: None,
+            done: false,
+        }
+    }
+
+    /// reader in [`DecodeMode::Resilient`].
+    pub fn resilient(reader: R) -> Self {
+        Self::new(reader).mode(DecodeMode::Resilient)
+    }
+
+    pub fn mode(mut self, mode: DecodeMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// called for every piece of malformed data skipped in [`DecodeMode::Resilient`].
+    pub fn on_warning(mut self, on_warning: impl FnMut(DecodeWarning) + Send + 'static) -> Self {
+        self.on_warning = Some(Box::new(on_warning));
+        self
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// number of bytes consumed from the stream so far.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    fn warn(&mut self, offset: u64, skipped: u64, reason: impl Into<String>) {
+        let warning = DecodeWarning {
+            offset,
+            skipped,
+            reason: reason.into(),
+        };
+        if let Some(on_warning) = self.on_warning.as_mut() {
+            on_warning(warning);
+        }
+    }
+
+    fn read_exact(&mut self, buffer: &mut [u8]) -> std::io::Result<()> {
+        let from_pushback = buffer.len().min(self.pushback.len());
+        for byte in buffer[..from_pushback].iter_mut() {
+            *byte = self.pushback.pop().unwrap();
+        }
+        self.reader.read_exact(&mut buffer[from_pushback..])?;
+        self.position += buffer.len() as u64;
+        Ok(())
+    }
+
+    fn unread(&mut self, data: &[u8]) {
+        self.pushback.extend(data.iter().rev());
+        self.position -= data.len() as u64;
+    }
+
+    /// skip bytes until the next segment magic number.
+    fn resync(&mut self) -> std::io::Result<()> {
+        let magic = wire::MAGIC_NUMBER.to_be_bytes();
+        let mut byte = [0];
+        loop {
+            self.read_exact(&mut byte)?;
+            if byte[0] != magic[0] {
+                continue;
+            }
+            self.read_exact(&mut byte)?;
+            if byte[0] == magic[1] {
+                self.unread(&magic);
+                return Ok(());
+            }
+            self.unread(&byte);
+        }
+    }
+
+    /// read the next segment and the offset it starts at.
+    ///
+    /// the outer error is for failing to read the segment, the inner one for failing to decode
+    /// it. in resilient mode bytes that do not start with the magic number are skipped.
+    fn read_segment(&mut self) -> std::io::Result<(u64, std::io::Result<Segment>)> {
+        use wire::Wire;
+
+        let mut header = [0; wire::SEGMENT_HEADER_SIZE];
+        let mut offset = self.position;
+        self.read_exact(&mut header)?;
+        let mut segment_header = wire::SegmentHeader::read(header.as_slice())?;
+        while segment_header.magic_number != wire::MAGIC_NUMBER {
+            if self.mode == DecodeMode::Strict {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "invalid header magic value",
+                ));
+            }
+            self.unread(&header[1..]);
+            let resynced = self.resync();
+            let skipped = self.position - offset;
+            self.warn(offset, skipped, "invalid header magic value");
+            resynced?;
+
+            offset = self.position;
+            self.read_exact(&mut header)?;
+            segment_header = wire::SegmentHeader::read(header.as_slice())?;
+        }
+
+        let mut buffer = vec![0; segment_header.segment_size as usize];
+        self.read_exact(&mut buffer)?;
+        Ok((offset, decode_segment_body(segment_header, &buffer)))
+    }
+}
+
+impl<R: Read> Iterator for SegmentReader<R> {
+    type Item = std::io::Result<(u64, Segment)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            match self.read_segment() {
+                Ok((offset, Ok(segment))) => return Some(Ok((offset, segment))),
+                Ok((offset, Err(err))) if self.mode == DecodeMode::Resilient => {
+                    self.warn(offset, 0, format!("skipping segment, {err}"));
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => self.done = true,
+                Ok((_, Err(err))) | Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+        None
+    }
+}
+
+impl<R: Read> std::iter::FusedIterator for SegmentReader<R> {}
+
+/// Pull based decoder that reads one [`DisplaySet`] at a time from the underlying reader.
+///
+/// The iterator ends when the reader reaches end of file and, in [`DecodeMode::Strict`], stops
+/// after the first error. The reader is read in small chunks so wrapping it in a
+/// [`std::io::BufReader`] is recommended.
+///
+/// The timestamps of the sup format wrap around after [`SUP_TIMESTAMP_BITS`], the reader
+/// unwraps them so they keep increasing across the whole stream.
+#[derive(Debug)]
+pub struct DisplaySetReader<R> {
+    segments: SegmentReader<R>,
+    display_set_offset: u64,
+    done: bool,
+    timestamps: TimestampUnwrapper,
+}
+
+impl<R: Read> DisplaySetReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            segments: SegmentReader::new(reader),
+            display_set_offset: 0,
+            done: false,
+            timestamps: TimestampUnwrapper::new(SUP_TIMESTAMP_BITS),
+        }
+    }
+
+    /// reader in [`DecodeMode::Resilient`].
+    pub fn resilient(reader: R) -> Self {
+        Self::new(reader).mode(DecodeMode::Resilient)
+    }
+
+    pub fn mode(mut self, mode: DecodeMode) -> Self {
+        self.segments = self.segments.mode(mode);
+        self
+    }
+
+    /// called for every piece of malformed data skipped in [`DecodeMode::Resilient`].
+    pub fn on_warning(mut self, on_warning: impl FnMut(DecodeWarning) + Send + 'static) -> Self {
+        self.segments = self.segments.on_warning(on_warning);
+        self
+    }
+
+    pub fn into_inner(self) -> R {
+        self.segments.into_inner()
+    }
+
+    /// number of bytes consumed from the stream so far.
+    pub fn position(&self) -> u64 {
+        self.segments.position()
+    }
+
+    /// byte offset of the last display set returned.
+    pub fn display_set_offset(&self) -> u64 {
+        self.display_set_offset
+    }
+
+    /// number of times the timestamps wrapped around so far.
+    pub fn timestamp_wraps(&self) -> u64 {
+        self.timestamps.wraps()
+    }
+
+    fn read_display_set(&mut self) -> std::io::Result<DisplaySet> {
+        let invalid = |reason| std::io::Error::new(std::io::ErrorKind::InvalidData, reason);
+        let mut partial: Option<PartialDisplaySet> = None;
+        // in resilient mode the segments of an invalid display set are dropped up to the next PCS
+        let mut skipping = false;
+
+        loop {
+            let (offset, segment) = match self.segments.read_segment() {
+                Ok(segment) => segment,
+                Err(err) => {
+                    if let (DecodeMode::Resilient, Some(partial)) = (self.segments.mode, &partial) {
+                        let offset = partial.offset;
+                        self.segments.warn(
+                            offset,
+                            0,
+                            "display set truncated by the end of the stream",
+                        );
+                    }
+                    return Err(err);
+                }
+            };
+            let segment = match segment {
+                Ok(segment) => segment,
+                Err(err) if self.segments.mode == DecodeMode::Strict => return Err(err),
+                Err(err) => {
+                    self.segments
+                        .warn(offset, 0, format!("skipping display set, {err}"));
+                    partial = None;
+                    skipping = true;
+                    continue;
+                }
+            };
+
+            if let Segment::PCS(pcs) = segment {
+                if let Some(previous) = partial.take() {
+                    if self.segments.mode == DecodeMode::Strict {
+                        return Err(invalid("found PCS in the middle of display set"));
+                    }
+                    self.segments.warn(
+                        previous.offset,
+                        0,
+                        "skipping display set without END segment",
+                    );
+                }
+                skipping = false;
+                partial = Some(PartialDisplaySet {
+                    offset,
+                    pcs,
+                    wds: Vec::new(),
+                    pds: Vec::new(),
+                    ods: Vec::new(),
+                });
+                continue;
+            }
+
+            let Some(current) = partial.as_mut() else {
+                if self.segments.mode == DecodeMode::Strict {
+                    return Err(invalid("expected pcs as first segment in display set"));
+                }
+                if !skipping {
+                    self.segments
+                        .warn(offset, 0, "skipping segments outside of a display set");
+                    skipping = true;
+                }
+                continue;
+            };
+            match segment {
+                Segment::PCS(_) => unreachable!(),
+                Segment::WDS(wds) => current.wds.push(wds),
+                Segment::PDS(pds) => current.pds.push(pds),
+                Segment::ODS(ods) => current.ods.push(ods),
+                Segment::END(end) => {
+                    let current = partial.take().unwrap();
+                    self.display_set_offset = current.offset;
+                    return Ok(DisplaySet {
+                        pcs: current.pcs,
+                        wds: current.wds,
+                        pds: current.pds,
+                        ods: current.ods,
+                        end,
+                    });
+                }
+            }
+        }
+    }
+
+    fn unwrap_timestamps(&mut self, display_set: &mut DisplaySet) {
+        let pts = self.timestamps.unwrap(display_set.pcs.header.pts);
+
+        let timestamps = &self.timestamps;
+        let unwrap = |header: &mut Header| {
+            header.pts = timestamps.unwrap_near(header.pts, pts);
+            // the dts is usually left as zero
+            if header.dts != 0 {
+                header.dts = timestamps.unwrap_near(header.dts, pts);
+            }
+        };
+        unwrap(&mut display_set.pcs.header);
+        display_set
+            .wds
+            .iter_mut()
+            .for_each(|wds| unwrap(&mut wds.header));
+        display_set
+            .pds
+            .iter_mut()
+            .for_each(|pds| unwrap(&mut pds.header));
+        display_set
+            .ods
+            .iter_mut()
+            .for_each(|ods| unwrap(&mut ods.header));
+        unwrap(&mut display_set.end.header);
+    }
+}
+
+impl<R: Read> Iterator for DisplaySetReader<R> {
+    type Item = std::io::Result<DisplaySet>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.read_display_set() {
+            Ok(mut display_set) => {
+                self.unwrap_timestamps(&mut display_set);
+                Some(Ok(display_set))
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+                self.done = true;
+                None
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+impl<R: Read> std::iter::FusedIterator for DisplaySetReader<R> {}
+
+impl Segment {
+    pub fn header(&self) -> &Header {
+        match self {
+            Segment::PCS(pcs) => &pcs.header,
+            Segment::WDS(wds) => &wds.header,
+            Segment::PDS(pds) => &pds.header,
+            Segment::ODS(ods) => &ods.header,
+            Segment::END(end) => &end.header,
+        }
+    }
+
+    pub fn header_mut(&mut self) -> &mut Header {
+        match self {
+            Segment::PCS(pcs) => &mut pcs.header,
+            Segment::WDS(wds) => &mut wds.header,
+            Segment::PDS(pds) => &mut pds.header,
+            Segment::ODS(ods) => &mut ods.header,
+            Segment::END(end) => &mut end.header,
+        }
+    }
+}
+
+/// Containers like Matroska and MPEG transport streams store segments without the magic number
+/// and timestamps, only the segment type and size, and keep the timestamps themselves.
+///
+/// writes the segments of `data` to `writer` in the sup format with the given timestamps,
+/// truncated to the 32 bits of the format.
+pub fn write_sup_segments<W: Write>(
+    mut writer: W,
+    mut data: &[u8],
+    pts: u64,
+    dts: u64,
+) -> std::io::Result<()> {
+    use wire::Wire;
+
+    while !data.is_empty() {
+        let [segment_type, s0, s1, ..] = *data else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "truncated segment header",
+            ));
+        };
+        let segment_size = u16::from_be_bytes([s0, s1]);
+        let body = data.get(3..3 + usize::from(segment_size)).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, "truncated segment")
+        })?;
+        wire::SegmentHeader {
+            magic_number: wire::MAGIC_NUMBER,
+            pts: pts as u32,
+            dts: dts as u32,
+            segment_type,
+            segment_size,
+        }
+        .write(&mut writer)?;
+        writer.write_all(body)?;
+        data = &data[3 + body.len()..];
+    }
+    Ok(())
+}
+
+pub fn encode_segment(segment: &Segment) -> std::io::Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    encode_segment_writer(&mut buffer, segment)?;
+    Ok(buffer)
+}
+
+pub fn encode_segment_writer<W: Write>(mut writer: W, segment: &Segment) -> std::io::Result<()> {
+    use wire::Wire;
+
+    let mut body = Vec::new();
+    let segment_type = match segment {
+        Segment::PCS(pcs) => {
+            let number_of_composition_objects = u8::try_from(pcs.composition_objects.len())
+                .map_err(|_| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "too many composition objects",
+                    )
+                })?;
+            wire::SegmentPCS {
+                width: pcs.width,
+                height: pcs.height,
+                framerate: pcs.framerate,
+                composition_number: pcs.composition_number,
+                composition_state: match pcs.composition_state {
+                    CompositionState::Normal => wire::COMPOSITION_STATE_NORMAL,
+                    CompositionState::AcquisitionPoint => wire::COMPOSITION_STATE_ACQUISITION_POINT,
+                    CompositionState::EpochStart => wire::COMPOSITION_STATE_EPOCH_START,
+                },
+                palette_update_flag: match pcs.palette_update {
+                    true => wire::PALETTE_UPDATE_FLAG_TRUE,
+                    false => wire::PALETTE_UPDATE_FLAG_FALSE,
+                },
+                palette_id: pcs.palette_id,
+                number_of_composition_objects,
+            }
+            .write(&mut body)?;
+
+            for object in pcs.composition_objects.iter() {
+                let cropping = object.cropping.unwrap_or(CompositionObjectCropping {
+                    width: 0,
+                    height: 0,
+                    horizontal_position: 0,
+                    vertical_position: 0,
+                });
+                wire::CompositionObject {
+                    object_id: object.object_id,
+                    window_id: object.window_id,
+                    object_cropped_flag: match object.cropping {
+                        Some(_) => wire::OBJECT_CROPPED_FLAG_CROPPED,
+                        None => wire::OBJECT_CROPPED_FLAG_OFF,
+                    } | match object.forced {
+                        true => wire::OBJECT_CROPPED_FLAG_FORCED,
+                        false => wire::OBJECT_CROPPED_FLAG_OFF,
+                    },
+                    object_horizontal_position: object.horizontal_position,
+                    object_vertical_position: object.vertical_position,
+                    object_cropping_horizontal_position: cropping.horizontal_position,
+                    object_cropping_vertical_position: cropping.vertical_position,
+                    object_cropping_width: cropping.width,
+                    object_cropping_height: cropping.height,
+                }
+                .write(&mut body)?;
+            }
+            wire::SEGMENT_TYPE_PCS
+        }
+        Segment::WDS(wds) => {
+            let number_of_windows = u8::try_from(wds.windows.len()).map_err(|_| {
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, "too many windows")
+            })?;
+            wire::SegmentWDS { number_of_windows }.write(&mut body)?;
+            for window in wds.windows.iter() {
+                wire::Window {
+                    window_id: window.window_id,
+                    window_horizontal_position: window.horizontal_position,
+                    window_vertical_position: window.vertical_position,
+                    window_width: window.width,
+                    window_height: window.height,
+                }
+                .write(&mut body)?;
+            }
+            wire::SEGMENT_TYPE_WDS
+        }
+        Segment::PDS(pds) => {
+            wire::SegmentPDS {
+                palette_id: pds.palette_id,
+                palette_version: pds.palette_version,
+            }
+            .write(&mut body)?;
+            for entry in pds.defined_entries() {
+                wire::PaletteEntry {
+                    palette_entry_id: entry.entry_id,
+                    luminance: entry.luminance,
+                    color_diff_red: entry.color_diff_red,
+                    color_diff_blue: entry.color_diff_blue,
+                    transparency: entry.transparency,
+                }
+                .write(&mut body)?;
+            }
+            wire::SEGMENT_TYPE_PDS
+        }
+        Segment::ODS(ods) => {
+            wire::SegmentODS {
+                object_id: ods.object_id,
+                object_version: ods.object_version,
+                last_in_sequence_flag: match ods.last_in_sequence {
+                    LastInSequenceFlag::Last => wire::LAST_IN_SEQUENCE_FLAG_LAST_IN_SEQ,
+                    LastInSequenceFlag::First => wire::LAST_IN_SEQUENCE_FLAG_FIRST_IN_SEQ,
+                    LastInSequenceFlag::FirstAndLast => {
+                        wire::LAST_IN_SEQUENCE_FLAG_FIRST_AND_LAST_IN_SEQ
+                    }
+                    LastInSequenceFlag::Middle => wire::LAST_IN_SEQUENCE_FLAG_MIDDLE_IN_SEQ,
+                },
+                object_data_length: ods.object_data_length,
+                width: ods.width,
+                height: ods.height,
+            }
+            .write(&mut body)?;
+            body.extend(&ods.data);
+            wire::SEGMENT_TYPE_ODS
+        }
+        Segment::END(_) => wire::SEGMENT_TYPE_END,
+    };
+
+    let segment_size = u16::try_from(body.len()).map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("segment size {} does not fit in 16 bits", body.len()),
+        )
+    })?;
+    let header = segment.header();
+    wire::SegmentHeader {
+        magic_number: wire::MAGIC_NUMBER,
+        // the sup format only keeps the lower 32 bits
+        pts: header.pts as u32,
+        dts: header.dts as u32,
+        segment_type,
+        segment_size,
+    }
+    .write(&mut writer)?;
+    writer.write_all(&body)
+}
+
+pub fn encode_display_set(display_set: &DisplaySet) -> std::io::Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    encode_display_set_writer(&mut buffer, display_set)?;
+    Ok(buffer)
+}
+
+/// the segments are written in the order PCS, WDS, PDS, ODS, END.
+pub fn encode_display_set_writer<W: Write>(
+    mut writer: W,
+    display_set: &DisplaySet,
+) -> std::io::Result<()> {
+    // segments are cloned into the enum, this is cheap compared to the io
+    encode_segment_writer(&mut writer, &Segment::PCS(display_set.pcs.clone()))?;
+    for wds in display_set.wds.iter() {
+        encode_segment_writer(&mut writer, &Segment::WDS(wds.clone()))?;
+    }
+    for pds in display_set.pds.iter() {
+        encode_segment_writer(&mut writer, &Segment::PDS(pds.clone()))?;
+    }
+    for ods in display_set.ods.iter() {
+        encode_segment_writer(&mut writer, &Segment::ODS(ods.clone()))?;
+    }
+    encode_segment_writer(&mut writer, &Segment::END(display_set.end.clone()))
+}
+
+pub fn encode_display_sets(display_sets: &[DisplaySet]) -> std::io::Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    encode_display_sets_writer(&mut buffer, display_sets)?;
+    Ok(buffer)
+}
+
+pub fn encode_display_sets_writer<W: Write>(
+    mut writer: W,
+    display_sets: &[DisplaySet],
+) -> std::io::Result<()> {
+    for display_set in display_sets {
+        encode_display_set_writer(&mut writer, display_set)?;
+    }
+    Ok(())
 }
 
 pub fn ycbcr_to_rgb(luminance: u8, cr: u8, cb: u8) -> (u8, u8, u8) {
@@ -422,12 +1255,20 @@ pub fn ycbcr_to_rgb(luminance: u8, cr: u8, cb: u8) -> (u8, u8, u8) {
 }
 
 /// convert timestamp in the 90khz clock to a [`std::time::Duration`].
-pub fn clock_to_duration(timestamp: u32) -> Duration {
-    let seconds = timestamp / 90_000;
-    let remain = timestamp % 90_000;
-    let nanos_per_tick = 1_000_000_000 / 90_000;
-    let nanos = remain * nanos_per_tick;
-    Duration::new(u64::from(seconds), nanos)
+///
+/// a tick is not a whole number of nanoseconds so the result is rounded down to the
+/// nanosecond.
+pub fn clock_to_duration(timestamp: u64) -> Duration {
+    let seconds = timestamp / CLOCK_RATE;
+    let remain = timestamp % CLOCK_RATE;
+    let nanos = remain * 1_000_000_000 / CLOCK_RATE;
+    Duration::new(seconds, nanos as u32)
+}
+
+/// convert a [`std::time::Duration`] to the closest timestamp in the 90khz clock.
+pub fn duration_to_clock(duration: Duration) -> u64 {
+    let nanos = duration.as_nanos() * u128::from(CLOCK_RATE);
+    ((nanos + 500_000_000) / 1_000_000_000) as u64
 }
 
 /// decode the rle image data into a vector containing the pixels of the image.
@@ -456,15 +1297,231 @@ pub fn decode_rle_data(data: &[u8], width: u16, height: u16) -> std::io::Result<
     Ok(pixels)
 }
 
+/// encode the pixels, given as indices into the color palette, into rle image data.
+/// this is the inverse of [`decode_rle_data`].
+pub fn encode_rle_data(pixels: &[u8], width: u16, height: u16) -> std::io::Result<Vec<u8>> {
+    let expected_pixel_count = width as usize * height as usize;
+    if pixels.len() != expected_pixel_count {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "got {} pixels, expected {} ({}x{})",
+                pixels.len(),
+                expected_pixel_count,
+                width,
+                height,
+            ),
+        ));
+    }
+    if width == 0 {
+        return Ok(Vec::new());
+    }
+    Ok(wire::encode_image_data(pixels.chunks(width as usize)))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
-    const PGS: &'static [u8] = include_bytes!("../subtitle.sup");
+    const PGS: &[u8] = include_bytes!("../subtitle.sup");
 
     #[test]
     fn decode_subtitles() {
         let display_sets = decode_display_sets(PGS).unwrap();
         insta::assert_compact_debug_snapshot!(display_sets);
     }
+
+    #[test]
+    fn encode_subtitles_round_trip() {
+        let display_sets = decode_display_sets(PGS).unwrap();
+        let encoded = encode_display_sets(&display_sets).unwrap();
+        assert_eq!(encoded, PGS);
+
+        for ods in display_sets.iter().flat_map(|ds| ds.ods.iter()) {
+            let pixels = decode_rle_data(&ods.data, ods.width, ods.height).unwrap();
+            let rle = encode_rle_data(&pixels, ods.width, ods.height).unwrap();
+            assert_eq!(
+                decode_rle_data(&rle, ods.width, ods.height).unwrap(),
+                pixels
+            );
+        }
+
+        // entries defined as all zeros are kept
+        let mut ds = display_sets[0].clone();
+        ds.pds[0] = PDS::new(ds.pds[0].header, 0, 0);
+        ds.pds[0].set_entry(PaletteEntry {
+            entry_id: 7,
+            ..Default::default()
+        });
+        let encoded = encode_display_set(&ds).unwrap();
+        let decoded = decode_display_set(&encoded).unwrap();
+        let entries = decoded.pds[0].defined_entries().collect::<Vec<_>>();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].entry_id, 7);
+    }
+
+    #[test]
+    fn encode_forced_flag() {
+        // the forced and cropped flags share a byte
+        let mut ds = decode_display_set(PGS).unwrap();
+        let object = &mut ds.pcs.composition_objects[0];
+        object.forced = true;
+        object.cropping = Some(CompositionObjectCropping {
+            width: 10,
+            height: 10,
+            horizontal_position: 0,
+            vertical_position: 0,
+        });
+        let encoded = encode_display_set(&ds).unwrap();
+        assert_eq!(encoded[13 + 11 + 3], 0xC0);
+        let decoded = decode_display_set(&encoded).unwrap();
+        let object = &decoded.pcs.composition_objects[0];
+        assert!(object.forced);
+        assert_eq!(object.cropping.unwrap().width, 10);
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(clock_to_duration(90_000 * 3 + 9), Duration::new(3, 100_000));
+        assert_eq!(clock_to_duration(1), Duration::from_nanos(11_111));
+        assert_eq!(duration_to_clock(Duration::new(3, 100_000)), 90_000 * 3 + 9);
+        // the largest 33 bit timestamp, about 26.5 hours
+        assert_eq!(
+            clock_to_duration((1 << 33) - 1).as_millis(),
+            ((1u128 << 33) - 1) / 90
+        );
+
+        // a stream starting close to the 32 bit limit
+        let mut display_sets = decode_display_sets(PGS).unwrap();
+        let first = display_sets[0].pcs.header.pts;
+        let start = (1 << 32) - 90_000 * 60;
+        for ds in display_sets.iter_mut() {
+            let pts = start + ds.pcs.header.pts - first;
+            ds.pcs.header.pts = pts;
+            ds.end.header.pts = pts;
+            ds.wds.iter_mut().for_each(|wds| wds.header.pts = pts);
+            ds.pds.iter_mut().for_each(|pds| pds.header.pts = pts);
+            ds.ods.iter_mut().for_each(|ods| ods.header.pts = pts);
+        }
+        let encoded = encode_display_sets(&display_sets).unwrap();
+        let mut reader = DisplaySetReader::new(encoded.as_slice());
+        let decoded = reader
+            .by_ref()
+            .collect::<std::io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(reader.timestamp_wraps(), 1);
+        for (decoded, ds) in decoded.iter().zip(display_sets.iter()) {
+            assert_eq!(decoded.pcs.header.pts, ds.pcs.header.pts);
+            assert_eq!(decoded.end.header.pts, ds.pcs.header.pts);
+        }
+    }
+
+    #[test]
+    fn split_object_round_trip() {
+        let header = Header {
+            pts: 90_000,
+            dts: 0,
+        };
+        let (width, height) = (1920u16, 1080u16);
+        // a noisy image so the rle data needs three segments
+        let pixels = (0..usize::from(width) * usize::from(height))
+            .map(|i| (i % 7 + i / 1920 % 3) as u8)
+            .collect::<Vec<_>>();
+        let data = encode_rle_data(&pixels, width, height).unwrap();
+
+        let fragments = ODS::fragments(header, 1, 0, width, height, &data);
+        let flags = fragments
+            .iter()
+            .map(|ods| ods.last_in_sequence)
+            .collect::<Vec<_>>();
+        assert!(flags.len() > 2);
+        assert_eq!(flags[0], LastInSequenceFlag::First);
+        assert_eq!(flags[flags.len() - 1], LastInSequenceFlag::Last);
+        assert!(flags[1..flags.len() - 1]
+            .iter()
+            .all(|flag| *flag == LastInSequenceFlag::Middle));
+
+        let mut encoded = Vec::new();
+        for ods in fragments.iter() {
+            encode_segment_writer(&mut encoded, &Segment::ODS(ods.clone())).unwrap();
+        }
+        let mut reader = encoded.as_slice();
+        let mut reassembled = Vec::new();
+        for fragment in fragments.iter() {
+            let ods = match decode_segment_reader(&mut reader).unwrap() {
+                Segment::ODS(ods) => ods,
+                segment => panic!("expected ods, got {segment:?}"),
+            };
+            assert_eq!(ods.last_in_sequence, fragment.last_in_sequence);
+            assert_eq!(ods.object_data_length, fragment.object_data_length);
+            assert_eq!((ods.width, ods.height), (fragment.width, fragment.height));
+            reassembled.extend(ods.data);
+        }
+        assert!(reader.is_empty());
+        assert_eq!(
+            reassembled.len() + 4,
+            fragments[0].object_data_length as usize
+        );
+        assert_eq!(
+            decode_rle_data(&reassembled, width, height).unwrap(),
+            pixels
+        );
+    }
+
+    #[test]
+    fn resilient_decoding() {
+        let display_sets = decode_display_sets(PGS).unwrap();
+        let mut corrupted = Vec::new();
+        let mut garbage_offset = 0;
+        let mut invalid_offset = 0;
+        for (i, display_set) in display_sets.iter().enumerate() {
+            let mut encoded = encode_display_set(display_set).unwrap();
+            if i == 1 {
+                garbage_offset = corrupted.len() as u64;
+                corrupted.extend_from_slice(b"garbage!");
+            }
+            if i == 3 {
+                invalid_offset = corrupted.len() as u64;
+                // composition state of the PCS
+                encoded[wire::SEGMENT_HEADER_SIZE + 7] = 0x42;
+            }
+            corrupted.extend(encoded);
+        }
+
+        assert!(decode_display_sets(&corrupted).is_err());
+
+        let warnings = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let decoded = DisplaySetReader::resilient(corrupted.as_slice())
+            .on_warning({
+                let warnings = warnings.clone();
+                move |warning| warnings.lock().unwrap().push(warning)
+            })
+            .collect::<std::io::Result<Vec<_>>>()
+            .unwrap();
+
+        let pts = |display_sets: &[DisplaySet]| {
+            display_sets
+                .iter()
+                .map(|ds| ds.pcs.header.pts)
+                .collect::<Vec<_>>()
+        };
+        let mut expected = pts(&display_sets);
+        expected.remove(3);
+        assert_eq!(pts(&decoded), expected);
+        assert_eq!(
+            *warnings.lock().unwrap(),
+            [
+                DecodeWarning {
+                    offset: garbage_offset,
+                    skipped: 8,
+                    reason: "invalid header magic value".to_string(),
+                },
+                DecodeWarning {
+                    offset: invalid_offset,
+                    skipped: 0,
+                    reason: "skipping display set, invalid composition state".to_string(),
+                },
+            ]
+        );
+    }
 }
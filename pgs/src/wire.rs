@@ -1,8 +1,9 @@
-use std::io::Read;
+use std::io::{Read, Write};
 
 // https://blog.thescorpius.com/index.php/2017/07/15/presentation-graphic-stream-sup-files-bluray-subtitle-format/
 
 pub const MAGIC_NUMBER: u16 = 0x5047; // b"PG"
+pub const SEGMENT_HEADER_SIZE: usize = 13;
 
 pub const SEGMENT_TYPE_PDS: u8 = 0x14;
 pub const SEGMENT_TYPE_ODS: u8 = 0x15;
@@ -20,8 +21,13 @@ pub const PALETTE_UPDATE_FLAG_FALSE: u8 = 0x00;
 pub const PALETTE_UPDATE_FLAG_TRUE: u8 = 0x80;
 
 pub const OBJECT_CROPPED_FLAG_OFF: u8 = 0x00;
-pub const OBJECT_CROPPED_FLAG_FORCE: u8 = 0x40;
+pub const OBJECT_CROPPED_FLAG_CROPPED: u8 = 0x40;
+#[deprecated(note = "use OBJECT_CROPPED_FLAG_CROPPED, 0x40 is the cropped flag, not forced")]
+pub const OBJECT_CROPPED_FLAG_FORCE: u8 = OBJECT_CROPPED_FLAG_CROPPED;
+/// forced display flag, in the same byte as [`OBJECT_CROPPED_FLAG_CROPPED`].
+pub const OBJECT_CROPPED_FLAG_FORCED: u8 = 0x80;
 
+pub const LAST_IN_SEQUENCE_FLAG_MIDDLE_IN_SEQ: u8 = 0x00;
 pub const LAST_IN_SEQUENCE_FLAG_LAST_IN_SEQ: u8 = 0x40;
 pub const LAST_IN_SEQUENCE_FLAG_FIRST_IN_SEQ: u8 = 0x80;
 pub const LAST_IN_SEQUENCE_FLAG_FIRST_AND_LAST_IN_SEQ: u8 =
@@ -29,6 +35,7 @@ pub const LAST_IN_SEQUENCE_FLAG_FIRST_AND_LAST_IN_SEQ: u8 =
 
 pub trait Wire: Sized {
     fn read<R: Read>(reader: R) -> std::io::Result<Self>;
+    fn write<W: Write>(&self, writer: W) -> std::io::Result<()>;
 }
 
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
@@ -98,16 +105,27 @@ pub struct PaletteEntry {
 }
 
 /// Object Definition Segment
+///
+/// an object too large for a single segment is split into fragments. only the first fragment,
+/// the one with [`LAST_IN_SEQUENCE_FLAG_FIRST_IN_SEQ`] set, has the object data length, width
+/// and height fields. they are left zeroed in the other fragments.
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub struct SegmentODS {
     pub object_id: u16,
     pub object_version: u8,
     pub last_in_sequence_flag: u8,
+    /// length of the object data of all fragments plus the 4 bytes of the width and height.
     pub object_data_length: u32,
     pub width: u16,
     pub height: u16,
 }
 
+impl SegmentODS {
+    pub fn is_first_in_sequence(&self) -> bool {
+        self.last_in_sequence_flag & LAST_IN_SEQUENCE_FLAG_FIRST_IN_SEQ != 0
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ImageDataCode {
     Color { color: u8, count: u16 },
@@ -228,6 +246,57 @@ pub fn decode_image_data(buf: &[u8]) -> impl Iterator<Item = std::io::Result<Ima
     ImageDataDecoder::new(buf)
 }
 
+/// the largest run length that can be encoded in a single code.
+pub const IMAGE_DATA_MAX_RUN: u16 = 0x3FFF;
+
+/// encode a single rle code using the shortest representation.
+/// this is the inverse of [`decode_image_data_code`].
+pub fn encode_image_data_code(code: ImageDataCode, buf: &mut Vec<u8>) -> std::io::Result<()> {
+    let (color, count) = match code {
+        ImageDataCode::EndOfLine => {
+            buf.extend([0, 0]);
+            return Ok(());
+        }
+        ImageDataCode::Color { color, count } => (color, count),
+    };
+
+    if count == 0 || count > IMAGE_DATA_MAX_RUN {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("invalid rle run length {count}"),
+        ));
+    }
+
+    match (color, count) {
+        (0, 1..=63) => buf.extend([0, count as u8]),
+        (0, _) => buf.extend([0, 0b01000000 | (count >> 8) as u8, count as u8]),
+        (c, 1..=2) => buf.extend(std::iter::repeat_n(c, count as usize)),
+        (c, 3..=63) => buf.extend([0, 0b10000000 | count as u8, c]),
+        (c, _) => buf.extend([0, 0b11000000 | (count >> 8) as u8, count as u8, c]),
+    }
+    Ok(())
+}
+
+/// encode the lines of indexed pixels into rle image data.
+/// this is the inverse of [`decode_image_data`].
+pub fn encode_image_data<'a>(lines: impl IntoIterator<Item = &'a [u8]>) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for line in lines {
+        let mut pixels = line.iter().copied().peekable();
+        while let Some(color) = pixels.next() {
+            let mut count = 1;
+            while count < IMAGE_DATA_MAX_RUN && pixels.next_if_eq(&color).is_some() {
+                count += 1;
+            }
+            encode_image_data_code(ImageDataCode::Color { color, count }, &mut buf)
+                .expect("run length is always valid");
+        }
+        encode_image_data_code(ImageDataCode::EndOfLine, &mut buf)
+            .expect("end of line is always valid");
+    }
+    buf
+}
+
 fn read_u8<R: Read>(reader: &mut R) -> std::io::Result<u8> {
     let mut buf = [0u8; 1];
     reader.read_exact(&mut buf)?;
@@ -252,6 +321,28 @@ fn read_u32<R: Read>(reader: &mut R) -> std::io::Result<u32> {
     Ok(u32::from_be_bytes(buf))
 }
 
+fn write_u8<W: Write>(writer: &mut W, value: u8) -> std::io::Result<()> {
+    writer.write_all(&[value])
+}
+
+fn write_u16<W: Write>(writer: &mut W, value: u16) -> std::io::Result<()> {
+    writer.write_all(&value.to_be_bytes())
+}
+
+fn write_u24<W: Write>(writer: &mut W, value: u32) -> std::io::Result<()> {
+    if value > 0x00FF_FFFF {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "value does not fit in 24 bits",
+        ));
+    }
+    writer.write_all(&value.to_be_bytes()[1..])
+}
+
+fn write_u32<W: Write>(writer: &mut W, value: u32) -> std::io::Result<()> {
+    writer.write_all(&value.to_be_bytes())
+}
+
 impl Wire for SegmentHeader {
     fn read<R: Read>(mut reader: R) -> std::io::Result<Self> {
         Ok(Self {
@@ -262,6 +353,14 @@ impl Wire for SegmentHeader {
             segment_size: read_u16(&mut reader)?,
         })
     }
+
+    fn write<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        write_u16(&mut writer, self.magic_number)?;
+        write_u32(&mut writer, self.pts)?;
+        write_u32(&mut writer, self.dts)?;
+        write_u8(&mut writer, self.segment_type)?;
+        write_u16(&mut writer, self.segment_size)
+    }
 }
 
 impl Wire for SegmentPCS {
@@ -277,6 +376,17 @@ impl Wire for SegmentPCS {
             number_of_composition_objects: read_u8(&mut reader)?,
         })
     }
+
+    fn write<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        write_u16(&mut writer, self.width)?;
+        write_u16(&mut writer, self.height)?;
+        write_u8(&mut writer, self.framerate)?;
+        write_u16(&mut writer, self.composition_number)?;
+        write_u8(&mut writer, self.composition_state)?;
+        write_u8(&mut writer, self.palette_update_flag)?;
+        write_u8(&mut writer, self.palette_id)?;
+        write_u8(&mut writer, self.number_of_composition_objects)
+    }
 }
 
 impl Wire for CompositionObject {
@@ -287,7 +397,7 @@ impl Wire for CompositionObject {
         s.object_cropped_flag = read_u8(&mut reader)?;
         s.object_horizontal_position = read_u16(&mut reader)?;
         s.object_vertical_position = read_u16(&mut reader)?;
-        if s.object_cropped_flag == OBJECT_CROPPED_FLAG_FORCE {
+        if s.object_cropped_flag & OBJECT_CROPPED_FLAG_CROPPED != 0 {
             s.object_cropping_horizontal_position = read_u16(&mut reader)?;
             s.object_cropping_vertical_position = read_u16(&mut reader)?;
             s.object_cropping_width = read_u16(&mut reader)?;
@@ -295,6 +405,21 @@ impl Wire for CompositionObject {
         }
         Ok(s)
     }
+
+    fn write<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        write_u16(&mut writer, self.object_id)?;
+        write_u8(&mut writer, self.window_id)?;
+        write_u8(&mut writer, self.object_cropped_flag)?;
+        write_u16(&mut writer, self.object_horizontal_position)?;
+        write_u16(&mut writer, self.object_vertical_position)?;
+        if self.object_cropped_flag & OBJECT_CROPPED_FLAG_CROPPED != 0 {
+            write_u16(&mut writer, self.object_cropping_horizontal_position)?;
+            write_u16(&mut writer, self.object_cropping_vertical_position)?;
+            write_u16(&mut writer, self.object_cropping_width)?;
+            write_u16(&mut writer, self.object_cropping_height)?;
+        }
+        Ok(())
+    }
 }
 
 impl Wire for SegmentWDS {
@@ -303,6 +428,10 @@ impl Wire for SegmentWDS {
             number_of_windows: read_u8(&mut reader)?,
         })
     }
+
+    fn write<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        write_u8(&mut writer, self.number_of_windows)
+    }
 }
 
 impl Wire for Window {
@@ -315,6 +444,14 @@ impl Wire for Window {
             window_height: read_u16(&mut reader)?,
         })
     }
+
+    fn write<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        write_u8(&mut writer, self.window_id)?;
+        write_u16(&mut writer, self.window_horizontal_position)?;
+        write_u16(&mut writer, self.window_vertical_position)?;
+        write_u16(&mut writer, self.window_width)?;
+        write_u16(&mut writer, self.window_height)
+    }
 }
 
 impl Wire for SegmentPDS {
@@ -324,6 +461,11 @@ impl Wire for SegmentPDS {
             palette_version: read_u8(&mut reader)?,
         })
     }
+
+    fn write<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        write_u8(&mut writer, self.palette_id)?;
+        write_u8(&mut writer, self.palette_version)
+    }
 }
 
 impl Wire for PaletteEntry {
@@ -336,17 +478,41 @@ impl Wire for PaletteEntry {
             transparency: read_u8(&mut reader)?,
         })
     }
+
+    fn write<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        write_u8(&mut writer, self.palette_entry_id)?;
+        write_u8(&mut writer, self.luminance)?;
+        write_u8(&mut writer, self.color_diff_red)?;
+        write_u8(&mut writer, self.color_diff_blue)?;
+        write_u8(&mut writer, self.transparency)
+    }
 }
 
 impl Wire for SegmentODS {
     fn read<R: Read>(mut reader: R) -> std::io::Result<Self> {
-        Ok(Self {
+        let mut ods = Self {
             object_id: read_u16(&mut reader)?,
             object_version: read_u8(&mut reader)?,
             last_in_sequence_flag: read_u8(&mut reader)?,
-            object_data_length: read_u24(&mut reader)?,
-            width: read_u16(&mut reader)?,
-            height: read_u16(&mut reader)?,
-        })
+            ..Default::default()
+        };
+        if ods.is_first_in_sequence() {
+            ods.object_data_length = read_u24(&mut reader)?;
+            ods.width = read_u16(&mut reader)?;
+            ods.height = read_u16(&mut reader)?;
+        }
+        Ok(ods)
+    }
+
+    fn write<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        write_u16(&mut writer, self.object_id)?;
+        write_u8(&mut writer, self.object_version)?;
+        write_u8(&mut writer, self.last_in_sequence_flag)?;
+        if self.is_first_in_sequence() {
+            write_u24(&mut writer, self.object_data_length)?;
+            write_u16(&mut writer, self.width)?;
+            write_u16(&mut writer, self.height)?;
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,225 @@
+use std::{io::Write, time::Duration};
+
+use crate::{
+    italic::{ITALIC_CLOSE, ITALIC_OPEN},
+    Placement, SubtitleWriter, TextSubtitle,
+};
+
+/// screen size used when the subtitles do not carry a placement.
+const DEFAULT_PLAY_RES: (u32, u32) = (1920, 1080);
+
+pub fn ass_duration_display(duration: Duration) -> impl std::fmt::Display {
+    struct AssDurationDisplay(Duration);
+
+    impl std::fmt::Display for AssDurationDisplay {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let total_secs = self.0.as_secs();
+            let hours = total_secs / 3600;
+            let minutes = (total_secs / 60) % 60;
+            let seconds = total_secs % 60;
+            let centis = self.0.subsec_millis() / 10;
+            write!(f, "{hours}:{minutes:02}:{seconds:02}.{centis:02}")
+        }
+    }
+
+    AssDurationDisplay(duration)
+}
+
+/// override tags placing the event where the bitmap was shown, anchored as told by
+/// [`Placement::anchored_at_bottom`].
+fn ass_position_tags(placement: &Placement) -> Option<String> {
+    if !placement.is_known() {
+        return None;
+    }
+    let center_x = placement.x + placement.width / 2;
+    let top = placement.y;
+    let bottom = placement.y + placement.height;
+    if placement.anchored_at_bottom() {
+        Some(format!("\\an2\\pos({center_x},{bottom})"))
+    } else {
+        Some(format!("\\an8\\pos({center_x},{top})"))
+    }
+}
+
+/// colors are written as `&HBBGGRR&`.
+fn ass_color_tag((r, g, b): (u8, u8, u8)) -> String {
+    format!("\\c&H{b:02X}{g:02X}{r:02X}&")
+}
+
+/// newlines become hard line breaks, italic tags become `\\i` overrides and braces are escaped so
+/// they do not start override blocks.
+fn ass_escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        if !escaped.is_empty() {
+            escaped.push_str("\\N");
+        }
+        let mut rest = line;
+        while let Some(c) = rest.chars().next() {
+            if let Some(after) = rest.strip_prefix(ITALIC_OPEN) {
+                escaped.push_str("{\\i1}");
+                rest = after;
+                continue;
+            }
+            if let Some(after) = rest.strip_prefix(ITALIC_CLOSE) {
+                escaped.push_str("{\\i0}");
+                rest = after;
+                continue;
+            }
+            rest = &rest[c.len_utf8()..];
+            match c {
+                '{' => escaped.push_str("\\{"),
+                '}' => escaped.push_str("\\}"),
+                c => escaped.push(c),
+            }
+        }
+    }
+    escaped
+}
+
+/// Writes text subtitles in the Advanced SubStation Alpha format as they are pushed.
+///
+/// Unlike the srt and vtt writers overlapping subtitles are not merged, each subtitle becomes
+/// its own event since ASS renderers handle overlapping events at different positions.
+pub struct AssWriter<W> {
+    writer: W,
+    header_written: bool,
+}
+
+impl<W: Write> AssWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            header_written: false,
+        }
+    }
+
+    /// the play resolution comes from the first subtitle so positions can be used as is.
+    fn write_header(&mut self, placement: Option<&Placement>) -> std::io::Result<()> {
+        if self.header_written {
+            return Ok(());
+        }
+        self.header_written = true;
+
+        let (play_res_x, play_res_y) = match placement {
+            Some(placement) if placement.is_known() => {
+                (placement.screen_width, placement.screen_height)
+            }
+            _ => DEFAULT_PLAY_RES,
+        };
+        let font_size = play_res_y / 18;
+        let margin_v = play_res_y / 20;
+
+        writeln!(self.writer, "[Script Info]")?;
+        writeln!(self.writer, "; Script generated by sup-to-srt")?;
+        writeln!(self.writer, "ScriptType: v4.00+")?;
+        writeln!(self.writer, "PlayResX: {play_res_x}")?;
+        writeln!(self.writer, "PlayResY: {play_res_y}")?;
+        writeln!(self.writer, "ScaledBorderAndShadow: yes")?;
+        writeln!(self.writer)?;
+        writeln!(self.writer, "[V4+ Styles]")?;
+        writeln!(
+            self.writer,
+            "Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, \
+             BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, \
+             BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding"
+        )?;
+        writeln!(
+            self.writer,
+            "Style: Default,Arial,{font_size},&H00FFFFFF,&H000000FF,&H00000000,&H00000000,\
+             0,0,0,0,100,100,0,0,1,2,0,2,10,10,{margin_v},1"
+        )?;
+        writeln!(self.writer)?;
+        writeln!(self.writer, "[Events]")?;
+        writeln!(
+            self.writer,
+            "Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text"
+        )?;
+        Ok(())
+    }
+}
+
+impl<W: Write> SubtitleWriter for AssWriter<W> {
+    fn push(&mut self, subtitle: TextSubtitle) -> std::io::Result<()> {
+        self.write_header(Some(&subtitle.placement))?;
+
+        let text = ass_escape_text(subtitle.text.trim());
+        if text.is_empty() {
+            return Ok(());
+        }
+
+        // a subtitle that ends before it begins stays on screen until the end
+        let end = match subtitle.range.end >= subtitle.range.begin {
+            true => subtitle.range.end,
+            false => Duration::MAX,
+        };
+
+        let mut overrides = String::new();
+        if let Some(tags) = ass_position_tags(&subtitle.placement) {
+            overrides.push_str(&tags);
+        }
+        if let Some(color) = subtitle.color {
+            overrides.push_str(&ass_color_tag(color));
+        }
+
+        write!(
+            self.writer,
+            "Dialogue: 0,{},{},Default,,0,0,0,,",
+            ass_duration_display(subtitle.range.begin),
+            ass_duration_display(end),
+        )?;
+        if !overrides.is_empty() {
+            write!(self.writer, "{{{overrides}}}")?;
+        }
+        writeln!(self.writer, "{text}")
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.write_header(None)?;
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::TimeRange;
+
+    #[test]
+    fn test_ass_writer() {
+        let subtitle = |begin: u64, end: u64, text: &str, y: u32| TextSubtitle {
+            range: TimeRange::new(Duration::from_millis(begin), Duration::from_millis(end)),
+            text: text.to_string(),
+            placement: Placement {
+                x: 760,
+                y,
+                width: 400,
+                height: 60,
+                screen_width: 1920,
+                screen_height: 1080,
+            },
+            color: Some((255, 255, 0)),
+            forced: false,
+            confidence: None,
+            words: Vec::new(),
+        };
+
+        let mut ass = Vec::new();
+        let mut writer = AssWriter::new(&mut ass);
+        writer.push(subtitle(1000, 2500, "{Sign}\n", 100)).unwrap();
+        writer
+            .push(subtitle(2000, 3000, "<i>Hello</i>\nthere", 900))
+            .unwrap();
+        writer.finish().unwrap();
+
+        let ass = String::from_utf8(ass).unwrap();
+        assert!(ass.contains("PlayResX: 1920\nPlayResY: 1080\n"));
+        let events = ass.split("[Events]\n").nth(1).unwrap();
+        assert_eq!(
+            events,
+            "Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n\
+            Dialogue: 0,0:00:01.00,0:00:02.50,Default,,0,0,0,,{\\an8\\pos(960,100)\\c&H00FFFF&}\\{Sign\\}\n\
+            Dialogue: 0,0:00:02.00,0:00:03.00,Default,,0,0,0,,{\\an2\\pos(960,960)\\c&H00FFFF&}{\\i1}Hello{\\i0}\\Nthere\n"
+        );
+    }
+}
@@ -0,0 +1,405 @@
+//! Export bitmap subtitles as a PNG sequence described by a BDN XML file.
+//!
+//! BDN XML is the interchange format used by Blu-ray authoring tools and BDSup2Sub. Each event
+//! references a png file, placed next to the xml, together with its position on screen and the
+//! in/out timecodes.
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use crate::{BitmapSubtitle, FileWriter, Result};
+
+/// name of the index file written by [`BdnExporter::finish`].
+pub const BDN_FILE_NAME: &str = "bdn.xml";
+
+/// Video frame rate as a fraction, used to convert times into timecodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameRate {
+    pub numerator: u32,
+    pub denominator: u32,
+}
+
+impl FrameRate {
+    pub const FPS_23_976: FrameRate = FrameRate::new(24000, 1001);
+    pub const FPS_24: FrameRate = FrameRate::new(24, 1);
+    pub const FPS_25: FrameRate = FrameRate::new(25, 1);
+    pub const FPS_29_97: FrameRate = FrameRate::new(30000, 1001);
+    pub const FPS_30: FrameRate = FrameRate::new(30, 1);
+    pub const FPS_50: FrameRate = FrameRate::new(50, 1);
+    pub const FPS_59_94: FrameRate = FrameRate::new(60000, 1001);
+    pub const FPS_60: FrameRate = FrameRate::new(60, 1);
+
+    pub const fn new(numerator: u32, denominator: u32) -> Self {
+        Self {
+            numerator,
+            denominator,
+        }
+    }
+
+    /// video frame rate stored in the upper 4 bits of the PCS frame rate field, like `0x10`
+    /// for 23.976.
+    pub fn from_pgs(framerate: u8) -> Option<Self> {
+        match framerate >> 4 {
+            1 => Some(Self::FPS_23_976),
+            2 => Some(Self::FPS_24),
+            3 => Some(Self::FPS_25),
+            4 => Some(Self::FPS_29_97),
+            6 => Some(Self::FPS_50),
+            7 => Some(Self::FPS_59_94),
+            _ => None,
+        }
+    }
+
+    /// the integer frame rate used to count frames in timecodes, 24 for 23.976.
+    pub fn timecode_base(&self) -> u64 {
+        u64::from(self.numerator).div_ceil(u64::from(self.denominator))
+    }
+
+    /// index of the frame being shown at `time`.
+    pub fn frames(&self, time: Duration) -> u64 {
+        let numerator = time.as_nanos() * u128::from(self.numerator);
+        let denominator = 1_000_000_000 * u128::from(self.denominator);
+        ((numerator + denominator / 2) / denominator) as u64
+    }
+
+    /// non drop frame timecode `HH:MM:SS:FF` for `time`.
+    pub fn timecode(&self, time: Duration) -> String {
+        let base = self.timecode_base();
+        let frames = self.frames(time);
+        let total_secs = frames / base;
+        format!(
+            "{:02}:{:02}:{:02}:{:02}",
+            total_secs / 3600,
+            (total_secs / 60) % 60,
+            total_secs % 60,
+            frames % base
+        )
+    }
+
+    /// the last timecode of the day, `23:59:59:23` for 23.976, used as the out time of the
+    /// subtitles shown until the end.
+    pub fn last_timecode(&self) -> String {
+        format!("23:59:59:{:02}", self.timecode_base() - 1)
+    }
+
+    fn frame_duration(&self) -> Duration {
+        Duration::from_nanos(
+            1_000_000_000 * u64::from(self.denominator) / u64::from(self.numerator),
+        )
+    }
+}
+
+impl std::fmt::Display for FrameRate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.denominator {
+            1 => write!(f, "{}", self.numerator),
+            _ => write!(
+                f,
+                "{:.3}",
+                f64::from(self.numerator) / f64::from(self.denominator)
+            ),
+        }
+    }
+}
+
+impl FromStr for FrameRate {
+    type Err = String;
+
+    /// accepts the common rates, like `23.976` or `25`, or a fraction like `24000/1001`.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let rate = match s {
+            "23.976" | "23.98" => Self::FPS_23_976,
+            "24" => Self::FPS_24,
+            "25" => Self::FPS_25,
+            "29.97" => Self::FPS_29_97,
+            "30" => Self::FPS_30,
+            "50" => Self::FPS_50,
+            "59.94" => Self::FPS_59_94,
+            "60" => Self::FPS_60,
+            _ => {
+                let (numerator, denominator) = s
+                    .split_once('/')
+                    .ok_or_else(|| format!("invalid frame rate '{s}'"))?;
+                let numerator = numerator
+                    .parse()
+                    .map_err(|_| format!("invalid frame rate numerator '{numerator}'"))?;
+                let denominator = denominator
+                    .parse()
+                    .map_err(|_| format!("invalid frame rate denominator '{denominator}'"))?;
+                Self::new(numerator, denominator)
+            }
+        };
+        if rate.numerator == 0 || rate.denominator == 0 {
+            return Err(format!("invalid frame rate '{s}'"));
+        }
+        Ok(rate)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BdnOptions {
+    pub frame_rate: FrameRate,
+    /// ISO 639-2 language code.
+    pub language: String,
+    pub title: String,
+}
+
+impl Default for BdnOptions {
+    fn default() -> Self {
+        Self {
+            frame_rate: FrameRate::FPS_23_976,
+            language: "eng".to_string(),
+            title: "subtitle".to_string(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct BdnEvent {
+    in_time: Duration,
+    /// none for subtitles still on screen at the end of the stream
+    out_time: Option<Duration>,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    file_name: String,
+    forced: bool,
+}
+
+/// Writes one png per subtitle as they are pushed and the BDN XML index once finished.
+#[derive(Debug)]
+pub struct BdnExporter {
+    dir: PathBuf,
+    options: BdnOptions,
+    file_writer: FileWriter,
+    screen_size: Option<(u32, u32)>,
+    events: Vec<BdnEvent>,
+}
+
+impl BdnExporter {
+    /// the directory must already exist.
+    pub fn new(dir: impl Into<PathBuf>, options: BdnOptions) -> Self {
+        Self {
+            dir: dir.into(),
+            options,
+            file_writer: Default::default(),
+            screen_size: None,
+            events: Default::default(),
+        }
+    }
+
+    /// how the png and xml files are written, defaults to [`FileWriter::default`].
+    pub fn file_writer(mut self, file_writer: FileWriter) -> Self {
+        self.file_writer = file_writer;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn push(&mut self, subtitle: &BitmapSubtitle) -> Result<()> {
+        if subtitle.bitmap.width == 0 || subtitle.bitmap.height == 0 {
+            tracing::warn!("skipping empty bitmap at {:?}", subtitle.range.begin);
+            return Ok(());
+        }
+
+        let file_name = image_file_name(self.events.len() + 1);
+        let mut png = Vec::new();
+        subtitle.bitmap.write_png(&mut png)?;
+        self.file_writer.write(&self.dir.join(&file_name), &png)?;
+
+        let placement = &subtitle.placement;
+        if placement.is_known() && self.screen_size.is_none() {
+            self.screen_size = Some((placement.screen_width, placement.screen_height));
+        }
+
+        // subtitles still on screen at the end of the stream end before they begin
+        let range = subtitle.range;
+        let min_out_time = range.begin + self.options.frame_rate.frame_duration();
+        self.events.push(BdnEvent {
+            in_time: range.begin,
+            out_time: (range.end >= range.begin).then(|| range.end.max(min_out_time)),
+            x: placement.x,
+            y: placement.y,
+            width: subtitle.bitmap.width,
+            height: subtitle.bitmap.height,
+            file_name,
+            forced: subtitle.forced,
+        });
+        Ok(())
+    }
+
+    /// write the BDN XML index describing all pushed subtitles.
+    pub fn finish(self) -> Result<()> {
+        let mut xml = Vec::new();
+        self.write_xml(&mut xml)?;
+        self.file_writer
+            .write(&self.dir.join(BDN_FILE_NAME), &xml)?;
+        Ok(())
+    }
+
+    fn write_xml<W: Write>(&self, mut w: W) -> std::io::Result<()> {
+        let frame_rate = self.options.frame_rate;
+        let (_, screen_height) = self.screen_size.unwrap_or((1920, 1080));
+        let video_format = match screen_height {
+            480 => "480i",
+            576 => "576i",
+            720 => "720p",
+            _ => "1080p",
+        };
+        let out_timecode = |time: Option<Duration>| match time {
+            Some(time) => frame_rate.timecode(time),
+            None => frame_rate.last_timecode(),
+        };
+        let first_in = self.events.first().map(|e| e.in_time).unwrap_or_default();
+        let last_out = match self.events.iter().any(|e| e.out_time.is_none()) {
+            true => None,
+            false => Some(
+                self.events
+                    .iter()
+                    .flat_map(|e| e.out_time)
+                    .max()
+                    .unwrap_or_default(),
+            ),
+        };
+
+        writeln!(w, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            w,
+            r#"<BDN Version="0.93" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:noNamespaceSchemaLocation="BD-03-006-0093b BDN File Format.xsd">"#
+        )?;
+        writeln!(w, "  <Description>")?;
+        writeln!(
+            w,
+            r#"    <Name Title="{}" Content=""/>"#,
+            xml_escape(&self.options.title)
+        )?;
+        writeln!(
+            w,
+            r#"    <Language Code="{}"/>"#,
+            xml_escape(&self.options.language)
+        )?;
+        writeln!(
+            w,
+            r#"    <Format VideoFormat="{video_format}" FrameRate="{frame_rate}" DropFrame="False"/>"#
+        )?;
+        writeln!(
+            w,
+            r#"    <Events Type="Graphic" FirstEventInTC="{}" LastEventOutTC="{}" NumberofEvents="{}"/>"#,
+            frame_rate.timecode(first_in),
+            out_timecode(last_out),
+            self.events.len()
+        )?;
+        writeln!(w, "  </Description>")?;
+        writeln!(w, "  <Events>")?;
+        for event in self.events.iter() {
+            writeln!(
+                w,
+                r#"    <Event Forced="{}" InTC="{}" OutTC="{}">"#,
+                match event.forced {
+                    true => "True",
+                    false => "False",
+                },
+                frame_rate.timecode(event.in_time),
+                out_timecode(event.out_time)
+            )?;
+            writeln!(
+                w,
+                r#"      <Graphic Width="{}" Height="{}" X="{}" Y="{}">{}</Graphic>"#,
+                event.width,
+                event.height,
+                event.x,
+                event.y,
+                xml_escape(&event.file_name)
+            )?;
+            writeln!(w, "    </Event>")?;
+        }
+        writeln!(w, "  </Events>")?;
+        writeln!(w, "</BDN>")?;
+        Ok(())
+    }
+}
+
+/// name of the png of the `number`th event, starting at 1.
+fn image_file_name(number: usize) -> String {
+    format!("{number:04}.png")
+}
+
+/// whether a file is named like the pngs written by [`BdnExporter`], `0001.png` and up.
+pub fn is_image_file_name(name: &str) -> bool {
+    name.strip_suffix(".png")
+        .is_some_and(|stem| stem.len() >= 4 && stem.bytes().all(|b| b.is_ascii_digit()))
+}
+
+fn xml_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Bitmap, Placement, TimeRange};
+
+    #[test]
+    fn test_bdn_export() {
+        let dir = std::env::temp_dir().join(format!("sup-to-srt-{}-bdn", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let subtitle = |begin: u64, end: u64| BitmapSubtitle {
+            range: TimeRange::new(Duration::from_millis(begin), Duration::from_millis(end)),
+            bitmap: Bitmap {
+                width: 2,
+                height: 1,
+                pixels: vec![255, 255, 255, 255, 0, 0, 0, 0],
+            },
+            placement: Placement {
+                x: 100,
+                y: 900,
+                width: 2,
+                height: 1,
+                screen_width: 1920,
+                screen_height: 1080,
+            },
+            color: None,
+            forced: false,
+        };
+
+        let mut exporter = BdnExporter::new(&dir, BdnOptions::default());
+        exporter.push(&subtitle(1001, 2002)).unwrap();
+        exporter.push(&subtitle(3_600_000, 0)).unwrap();
+        exporter.finish().unwrap();
+
+        let xml = std::fs::read_to_string(dir.join(BDN_FILE_NAME)).unwrap();
+        assert!(
+            xml.contains(r#"<Format VideoFormat="1080p" FrameRate="23.976" DropFrame="False"/>"#)
+        );
+        assert!(xml.contains(
+            r#"FirstEventInTC="00:00:01:00" LastEventOutTC="23:59:59:23" NumberofEvents="2""#
+        ));
+        assert!(xml.contains(r#"<Event Forced="False" InTC="00:00:01:00" OutTC="00:00:02:00">"#));
+        // the last subtitle is still on screen at the end, it stays up instead of flashing
+        assert!(xml.contains(r#"<Event Forced="False" InTC="00:59:56:10" OutTC="23:59:59:23">"#));
+        assert!(xml.contains(r#"<Graphic Width="2" Height="1" X="100" Y="900">0002.png</Graphic>"#));
+
+        let png = std::fs::read(dir.join("0001.png")).unwrap();
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
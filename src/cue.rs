@@ -0,0 +1,181 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+use crate::{Placement, TextSubtitle, TimeRange};
+
+/// A span of time with a fixed set of subtitles on screen.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cue {
+    pub range: TimeRange,
+    /// text of all subtitles on screen, one after the other.
+    pub text: String,
+    /// the subtitles on screen, in the order they appeared.
+    pub subtitles: Vec<TextSubtitle>,
+}
+
+impl Cue {
+    /// area of the screen covered by all subtitles in this cue.
+    pub fn placement(&self) -> Placement {
+        self.subtitles
+            .iter()
+            .fold(Placement::default(), |acc, subtitle| {
+                acc.union(&subtitle.placement)
+            })
+    }
+}
+
+/// Merges overlapping subtitles into non overlapping cues.
+///
+/// Every time a subtitle appears or disappears a new cue starts with the text of all subtitles
+/// that are on screen at that point. Subtitles must be pushed ordered by their begin time,
+/// cues are produced as soon as no later subtitle can change them.
+#[derive(Debug, Default)]
+pub struct CueMerger {
+    /// actions not yet processed, sorted by timestamp
+    actions: VecDeque<CueAction>,
+    /// subtitles that were pushed and not yet removed from screen
+    subtitles: HashMap<usize, TextSubtitle>,
+    /// subtitles that are currently on screen, in the order they appeared
+    on_screen: Vec<usize>,
+    next_subtitle: usize,
+}
+
+#[derive(Debug)]
+enum CueActionKind {
+    Add,
+    Remove,
+}
+
+#[derive(Debug)]
+struct CueAction {
+    kind: CueActionKind,
+    subtitle: usize,
+    timestamp: Duration,
+}
+
+impl CueMerger {
+    pub fn push(&mut self, subtitle: TextSubtitle) -> Vec<Cue> {
+        let idx = self.next_subtitle;
+        self.next_subtitle += 1;
+
+        self.insert(CueAction {
+            kind: CueActionKind::Add,
+            subtitle: idx,
+            timestamp: subtitle.range.begin,
+        });
+        // a subtitle that ends before it begins stays on screen until the end
+        if subtitle.range.end >= subtitle.range.begin {
+            self.insert(CueAction {
+                kind: CueActionKind::Remove,
+                subtitle: idx,
+                timestamp: subtitle.range.end,
+            });
+        }
+        let begin = subtitle.range.begin;
+        self.subtitles.insert(idx, subtitle);
+
+        // no subtitle pushed later can produce an action before this point
+        let mut cues = Vec::new();
+        while self.actions.len() >= 2 && self.actions[1].timestamp <= begin {
+            let next_timestamp = self.actions[1].timestamp;
+            cues.extend(self.process(next_timestamp));
+        }
+        cues
+    }
+
+    /// process the remaining actions, the last cue ends at [`Duration::MAX`].
+    pub fn finish(&mut self) -> Vec<Cue> {
+        let mut cues = Vec::new();
+        while !self.actions.is_empty() {
+            let next_timestamp = match self.actions.get(1) {
+                Some(action) => action.timestamp,
+                None => Duration::MAX,
+            };
+            cues.extend(self.process(next_timestamp));
+        }
+        cues
+    }
+
+    /// insert after every action with a timestamp less or equal to this one.
+    fn insert(&mut self, action: CueAction) {
+        let position = self
+            .actions
+            .partition_point(|other| other.timestamp <= action.timestamp);
+        self.actions.insert(position, action);
+    }
+
+    fn process(&mut self, next_timestamp: Duration) -> Option<Cue> {
+        let action = self.actions.pop_front()?;
+        match action.kind {
+            CueActionKind::Add => self.on_screen.push(action.subtitle),
+            CueActionKind::Remove => {
+                self.on_screen.retain(|&x| x != action.subtitle);
+                self.subtitles.remove(&action.subtitle);
+            }
+        }
+
+        let mut on_screen_text = String::default();
+        for idx in self.on_screen.iter() {
+            on_screen_text.push_str(&self.subtitles[idx].text);
+            on_screen_text.push('\n');
+        }
+        let on_screen_text = on_screen_text.trim();
+
+        if on_screen_text.is_empty() {
+            return None;
+        }
+        Some(Cue {
+            range: TimeRange::new(action.timestamp, next_timestamp),
+            text: on_screen_text.to_string(),
+            subtitles: self
+                .on_screen
+                .iter()
+                .map(|idx| self.subtitles[idx].clone())
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cue_merging() {
+        let subtitle = |begin: u64, end: u64, text: &str| TextSubtitle {
+            range: TimeRange::new(Duration::from_secs(begin), Duration::from_secs(end)),
+            text: text.to_string(),
+            placement: Default::default(),
+            color: None,
+            forced: false,
+            confidence: None,
+            words: Vec::new(),
+        };
+        let mut merger = CueMerger::default();
+        let mut cues = Vec::new();
+        cues.extend(merger.push(subtitle(1, 3, "a")));
+        cues.extend(merger.push(subtitle(2, 4, "b")));
+        cues.extend(merger.push(subtitle(6, 7, "c")));
+        cues.extend(merger.push(subtitle(8, 0, "d")));
+        cues.extend(merger.finish());
+
+        let cue = |begin: u64, end: Duration, text: &'static str| {
+            (TimeRange::new(Duration::from_secs(begin), end), text)
+        };
+        let secs = Duration::from_secs;
+        assert_eq!(
+            cues.iter()
+                .map(|cue| (cue.range, cue.text.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                cue(1, secs(2), "a"),
+                cue(2, secs(3), "a\nb"),
+                cue(3, secs(4), "b"),
+                cue(6, secs(7), "c"),
+                cue(8, Duration::MAX, "d"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,1053 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io::Read,
+    time::Duration,
+};
+
+use crate::{palette::PaletteClasses, Bitmap, BitmapSubtitle, Error, Placement, Result, TimeRange};
+
+/// How palette only display updates, used for fades and karaoke color changes, are handled.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PaletteUpdates {
+    /// the updates are folded into the subtitle being shown. a fade in starts the subtitle once
+    /// it becomes visible, a fade out ends it once it is no longer visible and the most opaque
+    /// version of the bitmap is kept.
+    #[default]
+    Fold,
+    /// every update ends the subtitles being shown and starts new ones with the new palette.
+    Events,
+}
+
+/// How the objects of a display set are turned into subtitles.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Composition {
+    /// every composition object becomes its own subtitle.
+    #[default]
+    Objects,
+    /// the objects are drawn onto the display canvas, clipped to their windows, and the
+    /// canvas is trimmed to the area of the windows in use.
+    Windows,
+    /// the objects are drawn onto the display canvas, clipped to their windows, and the whole
+    /// canvas is kept, like a player would show it.
+    Frame,
+}
+
+/// How the palette indices of the objects are turned into bitmap pixels.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Rendering {
+    /// the colors of the palette, like a player would show them.
+    #[default]
+    Palette,
+    /// dark text fill on a white background, with the outline removed, see
+    /// [`crate::palette::PaletteClasses`].
+    Ocr,
+}
+
+/// Which subtitles are kept depending on their forced flag.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ForcedSubtitles {
+    /// keep all subtitles.
+    #[default]
+    All,
+    /// keep only the forced subtitles, usually translations of foreign dialogue.
+    Only,
+    /// drop the forced subtitles.
+    Exclude,
+    /// keep all subtitles and mark the text of the forced ones with [`FORCED_MARKER`].
+    Mark,
+}
+
+/// prefix of the text of forced subtitles with [`ForcedSubtitles::Mark`].
+pub const FORCED_MARKER: &str = "[forced] ";
+
+impl ForcedSubtitles {
+    /// whether a subtitle with this forced flag is kept.
+    pub fn keeps(&self, forced: bool) -> bool {
+        match self {
+            Self::All | Self::Mark => true,
+            Self::Only => forced,
+            Self::Exclude => !forced,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ExtractOptions {
+    pub palette_updates: PaletteUpdates,
+    pub composition: Composition,
+    /// shift all subtitles so the first one starts at this time.
+    pub rebase: Option<Duration>,
+    /// in [`pgs::DecodeMode::Resilient`] invalid display sets are skipped with a warning instead
+    /// of stopping the extraction.
+    pub mode: pgs::DecodeMode,
+    pub forced: ForcedSubtitles,
+    pub rendering: Rendering,
+}
+
+/// Converts display sets into bitmap subtitles as they are decoded.
+///
+/// A subtitle is only complete once the next display set arrives since that is what
+/// determines its end time.
+pub struct SubtitleExtractor {
+    options: ExtractOptions,
+    display_size: Option<(u16, u16)>,
+    current_epoch: u32,
+    objects: HashMap<u16, ExtractorObject>,
+    palettes: HashMap<u8, pgs::PDS>,
+    windows: HashMap<u8, pgs::Window>,
+    /// subtitles shown by the previous display set, waiting for their end time
+    pending: Vec<PendingSubtitle>,
+    /// completed subtitles held back while a pending subtitle began before them
+    held: Vec<BitmapSubtitle>,
+    last_time: Duration,
+    /// begin time of the first subtitle, used to rebase the subtitles
+    first_begin: Option<Duration>,
+}
+
+#[derive(Clone)]
+struct ExtractorObject {
+    width: u16,
+    height: u16,
+    finished: bool,
+    /// length of the rle data declared by the first fragment
+    data_length: usize,
+    /// rle data of the fragments received so far
+    data: Vec<u8>,
+    /// palette indices, kept so the object can be rendered again on palette updates
+    pixels: Vec<u8>,
+}
+
+struct PendingSubtitle {
+    /// none when the objects were composed into a single subtitle
+    object_id: Option<u16>,
+    window_id: u8,
+    subtitle: BitmapSubtitle,
+}
+
+impl PendingSubtitle {
+    fn opacity(&self) -> u64 {
+        self.subtitle
+            .bitmap
+            .pixels
+            .chunks_exact(4)
+            .map(|p| u64::from(p[3]))
+            .sum()
+    }
+}
+
+impl Default for SubtitleExtractor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SubtitleExtractor {
+    pub fn new() -> Self {
+        Self::with_options(Default::default())
+    }
+
+    pub fn with_options(options: ExtractOptions) -> Self {
+        Self {
+            options,
+            display_size: None,
+            current_epoch: 0,
+            objects: Default::default(),
+            palettes: Default::default(),
+            windows: Default::default(),
+            pending: Default::default(),
+            held: Default::default(),
+            last_time: Default::default(),
+            first_begin: None,
+        }
+    }
+
+    /// process the next display set and return the subtitles that were completed by it, in order
+    /// of their begin time.
+    ///
+    /// subtitles that are fully transparent while on screen are dropped. with palette updates
+    /// folded a subtitle can outlive the ones shown after it, those are returned once it is
+    /// completed too.
+    pub fn push(&mut self, ds: pgs::DisplaySet) -> Result<Vec<BitmapSubtitle>> {
+        let time = pgs::clock_to_duration(ds.pcs.header.pts);
+        match self.push_display_set(ds) {
+            Err(err @ (Error::Decode(_) | Error::InvalidStream(_)))
+                if self.options.mode == pgs::DecodeMode::Resilient =>
+            {
+                tracing::warn!(?time, reason = %err, "skipping invalid display set");
+                Ok(Vec::new())
+            }
+            result => result,
+        }
+    }
+
+    fn push_display_set(&mut self, ds: pgs::DisplaySet) -> Result<Vec<BitmapSubtitle>> {
+        // the display set is checked before it changes anything, so skipping an invalid one
+        // leaves the objects and palettes of the previous ones in place
+        if let Some((width, height)) = self.display_size {
+            if (ds.pcs.width, ds.pcs.height) != (width, height) {
+                return Err(Error::InvalidStream(format!(
+                    "display size changed from {width}x{height} to {}x{}",
+                    ds.pcs.width, ds.pcs.height
+                )));
+            }
+        } else if ds.pcs.composition_state != pgs::CompositionState::EpochStart {
+            return Err(Error::InvalidStream(
+                "display set 0 does not start an epoch".to_string(),
+            ));
+        }
+
+        let epoch_start = ds.pcs.composition_state == pgs::CompositionState::EpochStart;
+        let objects = self.decode_objects(ds.ods, epoch_start)?;
+        let defines_palette = ds.pds.iter().any(|pds| pds.palette_id == ds.pcs.palette_id);
+        if !defines_palette && (epoch_start || !self.palettes.contains_key(&ds.pcs.palette_id)) {
+            return Err(Error::InvalidStream(
+                "PCS referenced invalid palette".to_string(),
+            ));
+        }
+        for comp in ds.pcs.composition_objects.iter() {
+            let object = match objects.get(&comp.object_id) {
+                Some(object) => Some(object),
+                None if epoch_start => None,
+                None => self.objects.get(&comp.object_id),
+            };
+            let (Some(object), Some(cropping)) = (object, comp.cropping) else {
+                continue;
+            };
+            let right = u32::from(cropping.horizontal_position) + u32::from(cropping.width);
+            let bottom = u32::from(cropping.vertical_position) + u32::from(cropping.height);
+            if object.finished
+                && (right > u32::from(object.width) || bottom > u32::from(object.height))
+            {
+                return Err(Error::InvalidStream(format!(
+                    "cropping of object {} goes past its size of {}x{}",
+                    comp.object_id, object.width, object.height
+                )));
+            }
+        }
+
+        self.display_size = Some((ds.pcs.width, ds.pcs.height));
+        let current_time = pgs::clock_to_duration(ds.pcs.header.pts);
+        if current_time < self.last_time {
+            tracing::warn!(
+                "display set at {:?} goes back in time from {:?}",
+                current_time,
+                self.last_time
+            );
+        }
+        self.last_time = current_time;
+
+        if epoch_start {
+            self.current_epoch += 1;
+            self.objects.clear();
+            self.palettes.clear();
+            self.windows.clear();
+            tracing::debug!("moving to epoch {}", self.current_epoch);
+        }
+
+        for window in ds.wds.iter().flat_map(|wds| wds.windows.iter()) {
+            self.windows.insert(window.window_id, *window);
+        }
+
+        for pds in ds.pds {
+            tracing::debug!("found palette {}", pds.palette_id);
+            self.palettes.insert(pds.palette_id, pds);
+        }
+        self.objects.extend(objects);
+        let palette = &self.palettes[&ds.pcs.palette_id];
+
+        let mut shown = Vec::with_capacity(ds.pcs.composition_objects.len());
+        for comp in ds.pcs.composition_objects.iter() {
+            let object = match self.objects.get(&comp.object_id) {
+                Some(object) => object,
+                None => {
+                    tracing::warn!(
+                        "invalid object id in composition object: {}",
+                        comp.object_id
+                    );
+                    continue;
+                }
+            };
+
+            if !object.finished {
+                tracing::warn!(
+                    "unfinished object in composition object: {}",
+                    comp.object_id
+                );
+                continue;
+            }
+
+            let classes = PaletteClasses::new(&object.pixels, palette);
+            let bitmap = match self.options.rendering {
+                Rendering::Palette => bitmap_from_object_and_palette(object, palette),
+                Rendering::Ocr => classes.render(
+                    &object.pixels,
+                    u32::from(object.width),
+                    u32::from(object.height),
+                    palette,
+                ),
+            };
+            let bitmap = if let Some(cropping) = comp.cropping {
+                bitmap.sub_image(
+                    u32::from(cropping.horizontal_position),
+                    u32::from(cropping.vertical_position),
+                    u32::from(cropping.width),
+                    u32::from(cropping.height),
+                )
+            } else {
+                bitmap
+            };
+
+            let placement = Placement {
+                x: u32::from(comp.horizontal_position),
+                y: u32::from(comp.vertical_position),
+                width: bitmap.width,
+                height: bitmap.height,
+                screen_width: u32::from(ds.pcs.width),
+                screen_height: u32::from(ds.pcs.height),
+            };
+            shown.push(PendingSubtitle {
+                object_id: Some(comp.object_id),
+                window_id: comp.window_id,
+                subtitle: BitmapSubtitle {
+                    range: TimeRange::new(current_time, Default::default()),
+                    bitmap,
+                    placement,
+                    color: classes
+                        .fill()
+                        .map(|fill| palette.entries[usize::from(fill)].to_rgb()),
+                    forced: comp.forced,
+                },
+            });
+        }
+
+        let (width, height) = (u32::from(ds.pcs.width), u32::from(ds.pcs.height));
+        let shown = match self.options.composition {
+            Composition::Objects => shown,
+            Composition::Windows => self.compose(shown, width, height, false),
+            Composition::Frame => self.compose(shown, width, height, true),
+        };
+
+        let palette_only =
+            ds.pcs.palette_update && ds.pcs.composition_state == pgs::CompositionState::Normal;
+        let completed = match (palette_only, self.options.palette_updates) {
+            (true, PaletteUpdates::Fold) => self.fold_palette_update(shown),
+            _ => std::mem::replace(&mut self.pending, shown),
+        };
+        Ok(self.complete(completed, current_time))
+    }
+
+    /// the objects sent by the ods of a display set, decoded aside from the current objects.
+    ///
+    /// fragments continuing an object started by a previous display set pick up its data.
+    fn decode_objects(
+        &self,
+        segments: Vec<pgs::ODS>,
+        epoch_start: bool,
+    ) -> Result<HashMap<u16, ExtractorObject>> {
+        let mut objects = HashMap::<u16, ExtractorObject>::new();
+        for ods in segments {
+            let obj = if ods.last_in_sequence.is_first() {
+                let obj = objects.entry(ods.object_id).or_insert(ExtractorObject {
+                    width: ods.width,
+                    height: ods.height,
+                    finished: false,
+                    data_length: 0,
+                    data: Default::default(),
+                    pixels: Default::default(),
+                });
+                obj.width = ods.width;
+                obj.height = ods.height;
+                obj.finished = false;
+                // the declared length includes the width and height fields
+                obj.data_length = (ods.object_data_length as usize).saturating_sub(4);
+                obj.data.clear();
+                obj
+            } else {
+                if let Some(obj) = self.objects.get(&ods.object_id).filter(|_| !epoch_start) {
+                    objects.entry(ods.object_id).or_insert_with(|| obj.clone());
+                }
+                match objects.get_mut(&ods.object_id) {
+                    Some(obj) if !obj.finished => obj,
+                    _ => {
+                        tracing::error!(
+                            "received ODS fragment {:?} for object {} without a first fragment",
+                            ods.last_in_sequence,
+                            ods.object_id
+                        );
+                        return Err(Error::InvalidStream("invalid ods segment".to_string()));
+                    }
+                }
+            };
+
+            obj.data.extend(ods.data);
+            if ods.last_in_sequence.is_last() {
+                if obj.data.len() != obj.data_length {
+                    return Err(Error::InvalidStream(format!(
+                        "object {} has {} bytes of data but its length is {}",
+                        ods.object_id,
+                        obj.data.len(),
+                        obj.data_length
+                    )));
+                }
+                obj.finished = true;
+                obj.pixels = pgs::decode_rle_data(&obj.data, obj.width, obj.height)
+                    .map_err(Error::Decode)?;
+            }
+        }
+        Ok(objects)
+    }
+
+    /// subtitles still on screen at the end of the stream, their end time is left unset.
+    pub fn finish(&mut self) -> Vec<BitmapSubtitle> {
+        if self.display_size.is_none() {
+            tracing::warn!("no display sets in input");
+        }
+        let pending = std::mem::take(&mut self.pending);
+        self.complete(pending, Default::default())
+    }
+
+    /// draw the objects onto a canvas of the display size, each object clipped to its window.
+    ///
+    /// unless the whole frame is kept the canvas is trimmed to the union of the windows used.
+    fn compose(
+        &self,
+        shown: Vec<PendingSubtitle>,
+        width: u32,
+        height: u32,
+        frame: bool,
+    ) -> Vec<PendingSubtitle> {
+        let Some(first) = shown.first() else {
+            return Vec::new();
+        };
+        let range = first.subtitle.range;
+        let color = first.subtitle.color;
+        let forced = shown.iter().any(|object| object.subtitle.forced);
+        let screen = Placement {
+            x: 0,
+            y: 0,
+            width,
+            height,
+            screen_width: width,
+            screen_height: height,
+        };
+
+        // the same background the objects are rendered with
+        let background: [u8; 4] = match self.options.rendering {
+            Rendering::Palette => [0, 0, 0, 0],
+            Rendering::Ocr => [255, 255, 255, 0],
+        };
+        let mut canvas = Bitmap {
+            width,
+            height,
+            pixels: background.repeat((width * height) as usize),
+        };
+        let mut area: Option<Placement> = None;
+        for object in shown.iter() {
+            let window = match self.windows.get(&object.window_id) {
+                Some(window) => Placement {
+                    x: u32::from(window.horizontal_position),
+                    y: u32::from(window.vertical_position),
+                    width: u32::from(window.width),
+                    height: u32::from(window.height),
+                    screen_width: width,
+                    screen_height: height,
+                },
+                None => {
+                    tracing::warn!(
+                        "invalid window id in composition object: {}",
+                        object.window_id
+                    );
+                    screen
+                }
+            };
+            let window = window.intersection(&screen);
+            area = Some(match area {
+                Some(area) => area.union(&window),
+                None => window,
+            });
+
+            let placement = &object.subtitle.placement;
+            let bitmap = &object.subtitle.bitmap;
+            let clip = window.intersection(placement);
+            for y in clip.y..clip.y + clip.height {
+                for x in clip.x..clip.x + clip.width {
+                    let src = (((y - placement.y) * bitmap.width + (x - placement.x)) * 4) as usize;
+                    let pixel = &bitmap.pixels[src..src + 4];
+                    if pixel[3] > 0 {
+                        let dst = ((y * width + x) * 4) as usize;
+                        canvas.pixels[dst..dst + 4].copy_from_slice(pixel);
+                    }
+                }
+            }
+        }
+
+        let area = match frame {
+            true => screen,
+            false => area.unwrap_or(screen),
+        };
+        let bitmap = match frame {
+            true => canvas,
+            false => canvas.sub_image(area.x, area.y, area.width, area.height),
+        };
+        vec![PendingSubtitle {
+            object_id: None,
+            window_id: 0,
+            subtitle: BitmapSubtitle {
+                range,
+                bitmap,
+                placement: area,
+                color,
+                forced,
+            },
+        }]
+    }
+
+    /// update the pending subtitles with their re-rendered versions, returning the ones that
+    /// are no longer shown.
+    fn fold_palette_update(&mut self, shown: Vec<PendingSubtitle>) -> Vec<PendingSubtitle> {
+        let mut previous = std::mem::take(&mut self.pending);
+        let mut completed = Vec::new();
+        for update in shown {
+            let index = previous
+                .iter()
+                .position(|pending| pending.object_id == update.object_id);
+            let Some(index) = index else {
+                self.pending.push(update);
+                continue;
+            };
+
+            let mut pending = previous.remove(index);
+            let (pending_opacity, update_opacity) = (pending.opacity(), update.opacity());
+            match (pending_opacity > 0, update_opacity > 0) {
+                // fading in, the subtitle starts once it becomes visible
+                (false, _) => self.pending.push(update),
+                // faded out
+                (true, false) => {
+                    tracing::debug!("subtitle faded out at {:?}", pending.subtitle.range.begin);
+                    completed.push(pending);
+                    self.pending.push(update);
+                }
+                (true, true) => {
+                    if update_opacity > pending_opacity {
+                        pending.subtitle.bitmap = update.subtitle.bitmap;
+                        pending.subtitle.color = update.subtitle.color;
+                    }
+                    self.pending.push(pending);
+                }
+            }
+        }
+        completed.extend(previous);
+        completed
+    }
+
+    fn complete(&mut self, completed: Vec<PendingSubtitle>, end: Duration) -> Vec<BitmapSubtitle> {
+        let forced = self.options.forced;
+        let kept = |pending: &PendingSubtitle| {
+            pending.opacity() > 0 && forced.keeps(pending.subtitle.forced)
+        };
+        self.held
+            .extend(completed.into_iter().filter(kept).map(|pending| {
+                let mut subtitle = pending.subtitle;
+                subtitle.range.end = end;
+                subtitle
+            }));
+        self.held.sort_by_key(|subtitle| subtitle.range.begin);
+
+        let first_pending = self
+            .pending
+            .iter()
+            .filter(|pending| kept(pending))
+            .map(|pending| pending.subtitle.range.begin)
+            .min();
+        let ready = match first_pending {
+            Some(first_pending) => self
+                .held
+                .partition_point(|subtitle| subtitle.range.begin <= first_pending),
+            None => self.held.len(),
+        };
+        let mut ready = self.held.drain(..ready).collect::<Vec<_>>();
+        for subtitle in ready.iter_mut() {
+            self.rebase(subtitle);
+        }
+        ready
+    }
+
+    /// subtitles are returned in order of their begin time so the first one returned is the
+    /// first one of the stream.
+    fn rebase(&mut self, subtitle: &mut BitmapSubtitle) {
+        let Some(origin) = self.options.rebase else {
+            return;
+        };
+        let first = *self.first_begin.get_or_insert(subtitle.range.begin);
+        let rebase = |time: Duration| time.saturating_sub(first) + origin;
+        // an end before the begin means the end is unknown and is left as is
+        if subtitle.range.end >= subtitle.range.begin {
+            subtitle.range.end = rebase(subtitle.range.end);
+        }
+        subtitle.range.begin = rebase(subtitle.range.begin);
+    }
+}
+
+fn bitmap_from_object_and_palette(object: &ExtractorObject, palette: &pgs::PDS) -> Bitmap {
+    let mut pixels = Vec::with_capacity(object.pixels.len() * 4);
+    for &idx in object.pixels.iter() {
+        let (r, g, b, a) = palette.entries[idx as usize].to_rgba();
+        pixels.extend([r, g, b, a]);
+    }
+    Bitmap {
+        width: u32::from(object.width),
+        height: u32::from(object.height),
+        pixels,
+    }
+}
+
+/// display set reader decoding in the mode of the options, the malformed data it skips is
+/// logged as warnings.
+pub fn display_set_reader<R: Read>(
+    reader: R,
+    options: &ExtractOptions,
+) -> pgs::DisplaySetReader<R> {
+    pgs::DisplaySetReader::new(reader)
+        .mode(options.mode)
+        .on_warning(|warning| {
+            tracing::warn!(
+                offset = warning.offset,
+                skipped = warning.skipped,
+                reason = %warning.reason,
+                "skipping invalid data"
+            );
+        })
+}
+
+/// lazily extract bitmap subtitles, in presentation order, from the decoded display sets.
+pub fn subtitles_extract<I>(display_sets: I) -> impl Iterator<Item = Result<BitmapSubtitle>>
+where
+    I: Iterator<Item = std::io::Result<pgs::DisplaySet>>,
+{
+    subtitles_extract_with_options(display_sets, Default::default())
+}
+
+/// like [`subtitles_extract`] but with the given options.
+pub fn subtitles_extract_with_options<I>(
+    mut display_sets: I,
+    options: ExtractOptions,
+) -> impl Iterator<Item = Result<BitmapSubtitle>>
+where
+    I: Iterator<Item = std::io::Result<pgs::DisplaySet>>,
+{
+    let mut extractor = SubtitleExtractor::with_options(options);
+    let mut ready = VecDeque::new();
+    let mut done = false;
+
+    std::iter::from_fn(move || loop {
+        if let Some(subtitle) = ready.pop_front() {
+            return Some(Ok(subtitle));
+        }
+        if done {
+            return None;
+        }
+        let completed = match display_sets.next() {
+            Some(Ok(ds)) => extractor.push(ds),
+            Some(Err(err)) => Err(Error::from_decode(err)),
+            None => {
+                done = true;
+                Ok(extractor.finish())
+            }
+        };
+        match completed {
+            Ok(completed) => ready.extend(completed),
+            Err(err) => {
+                done = true;
+                return Some(Err(err));
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const WIDTH: u16 = 4;
+    const HEIGHT: u16 = 2;
+
+    fn header(millis: u32) -> pgs::Header {
+        pgs::Header {
+            pts: u64::from(millis) * 90,
+            dts: 0,
+        }
+    }
+
+    /// a display set showing a single 4x2 object, drawn with palette entry 1, with the given
+    /// transparency. the object is only sent on epoch start.
+    fn display_set(
+        millis: u32,
+        composition_state: pgs::CompositionState,
+        palette_update: bool,
+        transparency: Option<u8>,
+    ) -> pgs::DisplaySet {
+        let epoch_start = composition_state == pgs::CompositionState::EpochStart;
+        let mut pds = pgs::PDS::new(header(millis), 0, 0);
+        pds.set_entry(pgs::PaletteEntry {
+            entry_id: 1,
+            luminance: 235,
+            color_diff_red: 128,
+            color_diff_blue: 128,
+            transparency: transparency.unwrap_or_default(),
+        });
+        let pixels = [1u8; (WIDTH * HEIGHT) as usize];
+        pgs::DisplaySet {
+            pcs: pgs::PCS {
+                header: header(millis),
+                width: 1920,
+                height: 1080,
+                framerate: 0x10,
+                composition_number: 0,
+                composition_state,
+                palette_update,
+                palette_id: 0,
+                composition_objects: match transparency {
+                    Some(_) => vec![pgs::CompositionObject {
+                        object_id: 0,
+                        window_id: 0,
+                        horizontal_position: 100,
+                        vertical_position: 900,
+                        cropping: None,
+                        forced: false,
+                    }],
+                    None => Vec::new(),
+                },
+            },
+            wds: Vec::new(),
+            pds: vec![pds],
+            ods: match epoch_start {
+                true => {
+                    let data = pgs::encode_rle_data(&pixels, WIDTH, HEIGHT).unwrap();
+                    pgs::ODS::fragments(header(millis), 0, 0, WIDTH, HEIGHT, &data)
+                }
+                false => Vec::new(),
+            },
+            end: pgs::END {
+                header: header(millis),
+            },
+        }
+    }
+
+    /// begin, end and alpha of the subtitles extracted from a fade in and fade out.
+    fn extract_fade(palette_updates: PaletteUpdates) -> Vec<(u128, u128, u8)> {
+        use pgs::CompositionState::{EpochStart, Normal};
+        let stream = pgs::encode_display_sets(&[
+            display_set(1000, EpochStart, false, Some(0)),
+            display_set(2000, Normal, true, Some(128)),
+            display_set(3000, Normal, true, Some(255)),
+            display_set(5000, Normal, true, Some(128)),
+            display_set(6000, Normal, true, Some(0)),
+            display_set(8000, Normal, false, None),
+        ])
+        .unwrap();
+
+        let options = ExtractOptions {
+            palette_updates,
+            ..Default::default()
+        };
+        subtitles_extract_with_options(pgs::DisplaySetReader::new(stream.as_slice()), options)
+            .map(|subtitle| {
+                let subtitle = subtitle.unwrap();
+                (
+                    subtitle.range.begin.as_millis(),
+                    subtitle.range.end.as_millis(),
+                    subtitle.bitmap.pixels[3],
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_palette_updates() {
+        assert_eq!(extract_fade(PaletteUpdates::Fold), vec![(2000, 6000, 255)]);
+        assert_eq!(
+            extract_fade(PaletteUpdates::Events),
+            vec![(2000, 3000, 128), (3000, 5000, 255), (5000, 6000, 128)]
+        );
+    }
+
+    #[test]
+    fn test_independent_fades() {
+        use pgs::CompositionState::{EpochStart, Normal};
+        // object 0 drawn with palette entry 1 and object 1 below it drawn with entry 2
+        let display_set = |millis, composition_state, transparency: [u8; 2]| {
+            let mut ds = display_set(
+                millis,
+                composition_state,
+                composition_state == Normal,
+                Some(transparency[0]),
+            );
+            ds.pds[0].set_entry(pgs::PaletteEntry {
+                entry_id: 2,
+                luminance: 235,
+                color_diff_red: 128,
+                color_diff_blue: 128,
+                transparency: transparency[1],
+            });
+            let mut object = ds.pcs.composition_objects[0].clone();
+            object.object_id = 1;
+            object.vertical_position = 950;
+            ds.pcs.composition_objects.push(object);
+            if composition_state == EpochStart {
+                let pixels = [2u8; (WIDTH * HEIGHT) as usize];
+                let data = pgs::encode_rle_data(&pixels, WIDTH, HEIGHT).unwrap();
+                ds.ods.extend(pgs::ODS::fragments(
+                    header(millis),
+                    1,
+                    0,
+                    WIDTH,
+                    HEIGHT,
+                    &data,
+                ));
+            }
+            ds
+        };
+        // the second object fades in and out while the first one is shown
+        let stream = pgs::encode_display_sets(&[
+            display_set(1000, EpochStart, [255, 0]),
+            display_set(2000, Normal, [255, 255]),
+            display_set(3000, Normal, [255, 0]),
+            display_set(4000, Normal, [0, 0]),
+        ])
+        .unwrap();
+
+        let extract = |rebase| {
+            let options = ExtractOptions {
+                rebase,
+                ..Default::default()
+            };
+            subtitles_extract_with_options(pgs::DisplaySetReader::new(stream.as_slice()), options)
+                .map(|subtitle| {
+                    let subtitle = subtitle.unwrap();
+                    (
+                        subtitle.range.begin.as_millis(),
+                        subtitle.range.end.as_millis(),
+                        subtitle.placement.y,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(extract(None), [(1000, 4000, 900), (2000, 3000, 950)]);
+        assert_eq!(
+            extract(Some(Duration::ZERO)),
+            [(0, 3000, 900), (1000, 2000, 950)]
+        );
+    }
+
+    #[test]
+    fn test_composition() {
+        let mut ds = display_set(1000, pgs::CompositionState::EpochStart, false, Some(255));
+        let window = |window_id: u8, vertical_position: u16| pgs::Window {
+            window_id,
+            width: 3,
+            height: 2,
+            horizontal_position: 100,
+            vertical_position,
+        };
+        ds.wds.push(pgs::WDS {
+            header: header(1000),
+            windows: vec![window(0, 900), window(1, 904)],
+        });
+        // the second object overflows its window by one pixel on the right
+        ds.pcs.composition_objects.push(pgs::CompositionObject {
+            object_id: 0,
+            window_id: 1,
+            horizontal_position: 99,
+            vertical_position: 904,
+            cropping: None,
+            forced: false,
+        });
+
+        let options = ExtractOptions {
+            composition: Composition::Windows,
+            ..Default::default()
+        };
+        let mut extractor = SubtitleExtractor::with_options(options);
+        assert!(extractor.push(ds).unwrap().is_empty());
+        let subtitles = extractor.finish();
+        assert_eq!(subtitles.len(), 1);
+
+        let subtitle = &subtitles[0];
+        assert_eq!((subtitle.placement.x, subtitle.placement.y), (100, 900));
+        assert_eq!((subtitle.bitmap.width, subtitle.bitmap.height), (3, 6));
+        let alpha = subtitle
+            .bitmap
+            .pixels
+            .chunks_exact(4)
+            .map(|p| u8::from(p[3] != 0))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            alpha,
+            [1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1]
+        );
+    }
+
+    #[test]
+    fn test_object_fragments() {
+        let mut ds = display_set(1000, pgs::CompositionState::EpochStart, false, Some(255));
+        let ods = ds.ods.pop().unwrap();
+        // split the object data over three fragments
+        let (first, rest) = ods.data.split_at(1);
+        let (middle, last) = rest.split_at(1);
+        let fragment = |last_in_sequence, data: &[u8]| pgs::ODS {
+            last_in_sequence,
+            object_data_length: 0,
+            width: 0,
+            height: 0,
+            data: data.to_vec(),
+            ..ods.clone()
+        };
+        ds.ods = vec![
+            pgs::ODS {
+                last_in_sequence: pgs::LastInSequenceFlag::First,
+                data: first.to_vec(),
+                ..ods.clone()
+            },
+            fragment(pgs::LastInSequenceFlag::Middle, middle),
+            fragment(pgs::LastInSequenceFlag::Last, last),
+        ];
+
+        let mut extractor = SubtitleExtractor::new();
+        extractor.push(ds.clone()).unwrap();
+        let subtitles = extractor.finish();
+        assert_eq!(subtitles.len(), 1);
+        assert_eq!(subtitles[0].bitmap.pixels[3], 255);
+
+        // the reassembled data is shorter than the declared length
+        ds.ods.pop();
+        ds.ods[1].last_in_sequence = pgs::LastInSequenceFlag::Last;
+        let result = SubtitleExtractor::new().push(ds);
+        assert!(matches!(result, Err(Error::InvalidStream(_))));
+    }
+
+    #[test]
+    fn test_invalid_display_sets() {
+        use pgs::CompositionState::{EpochStart, Normal};
+        let mut resized = display_set(2000, Normal, false, Some(255));
+        resized.pcs.width = 720;
+        let mut missing_palette = display_set(3000, Normal, false, Some(255));
+        missing_palette.pcs.palette_id = 1;
+        let mut bad_cropping = display_set(3500, Normal, false, Some(255));
+        bad_cropping.pcs.composition_objects[0].cropping = Some(pgs::CompositionObjectCropping {
+            horizontal_position: 2,
+            vertical_position: 0,
+            width: WIDTH,
+            height: HEIGHT,
+        });
+        let display_sets = [
+            display_set(1000, EpochStart, false, Some(255)),
+            resized,
+            missing_palette,
+            bad_cropping.clone(),
+            display_set(4000, Normal, false, None),
+        ];
+
+        let extract = |mode| {
+            let options = ExtractOptions {
+                mode,
+                ..Default::default()
+            };
+            subtitles_extract_with_options(display_sets.iter().cloned().map(Ok), options)
+                .collect::<Result<Vec<_>>>()
+        };
+        assert!(matches!(
+            extract(pgs::DecodeMode::Strict),
+            Err(Error::InvalidStream(_))
+        ));
+        let subtitles = extract(pgs::DecodeMode::Resilient).unwrap();
+        let ranges = subtitles
+            .iter()
+            .map(|subtitle| {
+                (
+                    subtitle.range.begin.as_millis(),
+                    subtitle.range.end.as_millis(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(ranges, [(1000, 4000)]);
+
+        let mut extractor = SubtitleExtractor::new();
+        extractor.push(display_sets[0].clone()).unwrap();
+        assert!(matches!(
+            extractor.push(bad_cropping),
+            Err(Error::InvalidStream(_))
+        ));
+    }
+
+    #[test]
+    fn test_skipped_display_set_keeps_state() {
+        use pgs::CompositionState::{EpochStart, Normal};
+        // a new epoch with a smaller object, cropped past its size
+        let mut bad_epoch = display_set(2000, EpochStart, false, Some(255));
+        let data = pgs::encode_rle_data(&[1; 4], 2, 2).unwrap();
+        bad_epoch.ods = pgs::ODS::fragments(header(2000), 0, 0, 2, 2, &data);
+        bad_epoch.pcs.composition_objects[0].cropping = Some(pgs::CompositionObjectCropping {
+            horizontal_position: 0,
+            vertical_position: 0,
+            width: 3,
+            height: 2,
+        });
+        let display_sets = [
+            display_set(1000, EpochStart, false, Some(255)),
+            bad_epoch,
+            display_set(3000, Normal, false, Some(128)),
+            display_set(4000, Normal, false, None),
+        ];
+
+        let options = ExtractOptions {
+            mode: pgs::DecodeMode::Resilient,
+            ..Default::default()
+        };
+        let subtitles = subtitles_extract_with_options(display_sets.into_iter().map(Ok), options)
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        // the skipped epoch did not replace the object shown before it
+        let shown = subtitles
+            .iter()
+            .map(|subtitle| {
+                (
+                    subtitle.range.begin.as_millis(),
+                    subtitle.bitmap.width,
+                    subtitle.bitmap.pixels[3],
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            shown,
+            [(1000, u32::from(WIDTH), 255), (3000, u32::from(WIDTH), 128)]
+        );
+    }
+
+    #[test]
+    fn test_forced() {
+        use pgs::CompositionState::{EpochStart, Normal};
+        let mut forced = display_set(2000, Normal, false, Some(255));
+        forced.pcs.composition_objects[0].forced = true;
+        let display_sets = [
+            display_set(1000, EpochStart, false, Some(255)),
+            forced,
+            display_set(3000, Normal, false, None),
+        ];
+
+        let extract = |forced| {
+            let options = ExtractOptions {
+                forced,
+                ..Default::default()
+            };
+            subtitles_extract_with_options(display_sets.iter().cloned().map(Ok), options)
+                .map(|subtitle| {
+                    let subtitle = subtitle.unwrap();
+                    (subtitle.range.begin.as_millis(), subtitle.forced)
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(extract(ForcedSubtitles::All), [(1000, false), (2000, true)]);
+        assert_eq!(extract(ForcedSubtitles::Only), [(2000, true)]);
+        assert_eq!(extract(ForcedSubtitles::Exclude), [(1000, false)]);
+    }
+}
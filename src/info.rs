@@ -0,0 +1,304 @@
+//! Describe the contents of a pgs stream, either as a summary or segment by segment.
+use std::{collections::HashSet, time::Duration};
+
+use serde::Serialize;
+
+use crate::{bdn::FrameRate, Error, Result};
+
+/// Summary of a pgs stream.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct StreamInfo {
+    /// display size of the first display set.
+    pub width: u16,
+    pub height: u16,
+    /// raw frame rate field of the first display set.
+    pub framerate: u8,
+    pub display_sets: usize,
+    pub epochs: usize,
+    /// number of object definitions, an object redefined in a later epoch is counted again.
+    pub objects: usize,
+    /// number of distinct object ids.
+    pub object_ids: usize,
+    /// number of palette definitions, including palette updates.
+    pub palettes: usize,
+    pub composition_objects: usize,
+    /// number of composition objects with the forced flag set.
+    pub forced_objects: usize,
+    pub first_pts: Option<u64>,
+    pub last_pts: Option<u64>,
+}
+
+impl StreamInfo {
+    pub fn frame_rate(&self) -> Option<FrameRate> {
+        FrameRate::from_pgs(self.framerate)
+    }
+
+    /// time between the first and the last display set.
+    pub fn duration(&self) -> Duration {
+        match (self.first_pts, self.last_pts) {
+            (Some(first), Some(last)) => pgs::clock_to_duration(last.saturating_sub(first)),
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+impl std::fmt::Display for StreamInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let time = |pts: Option<u64>| {
+            crate::srt::srt_duration_display(pgs::clock_to_duration(pts.unwrap_or_default()))
+        };
+        writeln!(f, "resolution: {}x{}", self.width, self.height)?;
+        match self.frame_rate() {
+            Some(frame_rate) => writeln!(f, "frame rate: {frame_rate}")?,
+            None => writeln!(f, "frame rate: unknown ({:#04x})", self.framerate)?,
+        }
+        writeln!(f, "display sets: {}", self.display_sets)?;
+        writeln!(f, "epochs: {}", self.epochs)?;
+        writeln!(f, "objects: {} ({} ids)", self.objects, self.object_ids)?;
+        writeln!(f, "palettes: {}", self.palettes)?;
+        writeln!(
+            f,
+            "composition objects: {} ({} forced)",
+            self.composition_objects, self.forced_objects
+        )?;
+        writeln!(f, "first: {}", time(self.first_pts))?;
+        writeln!(f, "last: {}", time(self.last_pts))?;
+        writeln!(
+            f,
+            "duration: {}",
+            crate::srt::srt_duration_display(self.duration())
+        )
+    }
+}
+
+/// summarize the decoded display sets.
+pub fn stream_info<I>(display_sets: I) -> Result<StreamInfo>
+where
+    I: Iterator<Item = std::io::Result<pgs::DisplaySet>>,
+{
+    let mut info = StreamInfo::default();
+    let mut object_ids = HashSet::new();
+    for ds in display_sets {
+        let ds = ds.map_err(Error::from_decode)?;
+        if info.display_sets == 0 {
+            info.width = ds.pcs.width;
+            info.height = ds.pcs.height;
+            info.framerate = ds.pcs.framerate;
+            info.first_pts = Some(ds.pcs.header.pts);
+        }
+        info.display_sets += 1;
+        if ds.pcs.composition_state == pgs::CompositionState::EpochStart {
+            info.epochs += 1;
+        }
+        for ods in ds.ods.iter().filter(|ods| ods.last_in_sequence.is_first()) {
+            info.objects += 1;
+            object_ids.insert(ods.object_id);
+        }
+        info.palettes += ds.pds.len();
+        info.composition_objects += ds.pcs.composition_objects.len();
+        info.forced_objects += ds
+            .pcs
+            .composition_objects
+            .iter()
+            .filter(|object| object.forced)
+            .count();
+        info.last_pts = Some(ds.pcs.header.pts);
+    }
+    info.object_ids = object_ids.len();
+    Ok(info)
+}
+
+/// A decoded segment with its byte offset in the stream, in a form that can be serialized.
+///
+/// Object data is left out, only its length is kept.
+#[derive(Debug, Clone, Serialize)]
+pub struct SegmentRecord {
+    pub offset: u64,
+    pub pts: u64,
+    pub dts: u64,
+    #[serde(flatten)]
+    pub segment: SegmentFields,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+pub enum SegmentFields {
+    PCS {
+        width: u16,
+        height: u16,
+        framerate: u8,
+        composition_number: u16,
+        composition_state: String,
+        palette_update: bool,
+        palette_id: u8,
+        composition_objects: Vec<CompositionObjectRecord>,
+    },
+    WDS {
+        windows: Vec<WindowRecord>,
+    },
+    PDS {
+        palette_id: u8,
+        palette_version: u8,
+        entries: Vec<PaletteEntryRecord>,
+    },
+    ODS {
+        object_id: u16,
+        object_version: u8,
+        sequence: String,
+        /// only set on the first fragment of an object.
+        object_data_length: u32,
+        width: u16,
+        height: u16,
+        data_length: usize,
+    },
+    END,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CompositionObjectRecord {
+    pub object_id: u16,
+    pub window_id: u8,
+    pub x: u16,
+    pub y: u16,
+    /// `[x, y, width, height]` of the part of the object shown.
+    pub cropping: Option<[u16; 4]>,
+    pub forced: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WindowRecord {
+    pub window_id: u8,
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PaletteEntryRecord {
+    pub id: u8,
+    /// `[Y, Cr, Cb, A]`
+    pub ycrcba: [u8; 4],
+}
+
+impl SegmentRecord {
+    pub fn new(offset: u64, segment: &pgs::Segment) -> Self {
+        let header = segment.header();
+        let segment = match segment {
+            pgs::Segment::PCS(pcs) => SegmentFields::PCS {
+                width: pcs.width,
+                height: pcs.height,
+                framerate: pcs.framerate,
+                composition_number: pcs.composition_number,
+                composition_state: format!("{:?}", pcs.composition_state),
+                palette_update: pcs.palette_update,
+                palette_id: pcs.palette_id,
+                composition_objects: pcs
+                    .composition_objects
+                    .iter()
+                    .map(|object| CompositionObjectRecord {
+                        object_id: object.object_id,
+                        window_id: object.window_id,
+                        x: object.horizontal_position,
+                        y: object.vertical_position,
+                        cropping: object.cropping.map(|cropping| {
+                            [
+                                cropping.horizontal_position,
+                                cropping.vertical_position,
+                                cropping.width,
+                                cropping.height,
+                            ]
+                        }),
+                        forced: object.forced,
+                    })
+                    .collect(),
+            },
+            pgs::Segment::WDS(wds) => SegmentFields::WDS {
+                windows: wds
+                    .windows
+                    .iter()
+                    .map(|window| WindowRecord {
+                        window_id: window.window_id,
+                        x: window.horizontal_position,
+                        y: window.vertical_position,
+                        width: window.width,
+                        height: window.height,
+                    })
+                    .collect(),
+            },
+            pgs::Segment::PDS(pds) => SegmentFields::PDS {
+                palette_id: pds.palette_id,
+                palette_version: pds.palette_version,
+                entries: pds
+                    .defined_entries()
+                    .map(|entry| PaletteEntryRecord {
+                        id: entry.entry_id,
+                        ycrcba: [
+                            entry.luminance,
+                            entry.color_diff_red,
+                            entry.color_diff_blue,
+                            entry.transparency,
+                        ],
+                    })
+                    .collect(),
+            },
+            pgs::Segment::ODS(ods) => SegmentFields::ODS {
+                object_id: ods.object_id,
+                object_version: ods.object_version,
+                sequence: format!("{:?}", ods.last_in_sequence),
+                object_data_length: ods.object_data_length,
+                width: ods.width,
+                height: ods.height,
+                data_length: ods.data.len(),
+            },
+            pgs::Segment::END(_) => SegmentFields::END,
+        };
+        Self {
+            offset,
+            pts: header.pts,
+            dts: header.dts,
+            segment,
+        }
+    }
+}
+
+/// records of the segments read by a [`pgs::SegmentReader`].
+pub fn segment_records<I>(segments: I) -> Result<Vec<SegmentRecord>>
+where
+    I: Iterator<Item = std::io::Result<(u64, pgs::Segment)>>,
+{
+    segments
+        .map(|segment| {
+            let (offset, segment) = segment.map_err(Error::from_decode)?;
+            Ok(SegmentRecord::new(offset, &segment))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PGS: &[u8] = include_bytes!("../subtitle.sup");
+
+    #[test]
+    fn test_stream_info() {
+        let info = stream_info(pgs::DisplaySetReader::new(PGS)).unwrap();
+        assert_eq!((info.width, info.height), (1920, 1080));
+        assert_eq!(info.frame_rate(), Some(FrameRate::FPS_23_976));
+        assert_eq!(info.display_sets, 8);
+        assert_eq!(info.epochs, 4);
+        assert_eq!(info.forced_objects, 0);
+
+        let records = segment_records(pgs::SegmentReader::new(PGS)).unwrap();
+        assert_eq!(records[0].offset, 0);
+        assert!(matches!(records[0].segment, SegmentFields::PCS { .. }));
+        let last = records.last().unwrap();
+        assert!(matches!(last.segment, SegmentFields::END));
+        assert_eq!(last.offset as usize + 13, PGS.len());
+
+        let json = serde_json::to_value(&records[0]).unwrap();
+        assert_eq!(json["type"], "PCS");
+        assert_eq!(json["composition_state"], "EpochStart");
+    }
+}
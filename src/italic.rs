@@ -0,0 +1,192 @@
+//! Detect italic lines from the slant of their glyphs.
+//!
+//! Italics carry meaning in subtitles, like voices off screen, songs or thoughts. OCR engines
+//! do not report them, so the slant is estimated on the bitmap: the vertical strokes of italic
+//! glyphs line up once the line is sheared back by the right amount. Italic lines are wrapped in
+//! [`ITALIC_OPEN`] and [`ITALIC_CLOSE`] tags, which srt and WebVTT understand as is and the other
+//! writers translate.
+use crate::{segment::text_lines, Bitmap, OcrEngine, OcrResult, Result, MIN_OPAQUE};
+
+/// tag starting italic text in [`crate::TextSubtitle::text`].
+pub const ITALIC_OPEN: &str = "<i>";
+/// tag ending italic text in [`crate::TextSubtitle::text`].
+pub const ITALIC_CLOSE: &str = "</i>";
+
+/// shears tried, as horizontal pixels per row, from leaning left to strongly italic.
+const SHEARS: std::ops::RangeInclusive<i32> = -5..=20;
+const SHEAR_STEP: f32 = 0.02;
+/// lines whose glyphs lean more than this, about 8 degrees, are italic.
+const MIN_ITALIC_SHEAR: f32 = 0.14;
+/// and only when straightening them makes the strokes clearly sharper.
+const MIN_SHARPNESS_GAIN: f32 = 1.1;
+
+/// how much the glyphs of the bitmap lean right, in horizontal pixels per row.
+///
+/// every shear is scored by how sharp the vertical projection of the opaque pixels is once
+/// the bitmap is sheared back, with the sum of the squared column counts, and the best scoring
+/// one is picked. `None` if there are no opaque pixels or no shear is clearly better than
+/// leaving the bitmap upright.
+pub fn estimate_slant(bitmap: &Bitmap) -> Option<f32> {
+    let width = bitmap.width as usize;
+    if width == 0 {
+        return None;
+    }
+    let opaque = bitmap
+        .pixels
+        .chunks_exact(4)
+        .enumerate()
+        .filter(|(_, pixel)| pixel[3] >= MIN_OPAQUE)
+        .map(|(i, _)| ((i % width) as f32, (i / width) as f32))
+        .collect::<Vec<_>>();
+    if opaque.is_empty() {
+        return None;
+    }
+    let center = bitmap.height as f32 / 2.0;
+    let margin = bitmap.height as f32 * SHEAR_STEP * *SHEARS.end() as f32;
+
+    let sharpness = |shear: f32| {
+        let mut columns = vec![0u64; width + 2 * margin.ceil() as usize + 1];
+        let last = (columns.len() - 1) as f32;
+        for &(x, y) in &opaque {
+            // with a positive shear the rows above the center move right
+            let column = x + shear * (center - y) + margin;
+            columns[column.round().clamp(0.0, last) as usize] += 1;
+        }
+        columns.iter().map(|count| count * count).sum::<u64>() as f32
+    };
+    let upright = sharpness(0.0);
+    let (shear, best) = SHEARS
+        .map(|step| step as f32 * SHEAR_STEP)
+        .map(|shear| (shear, sharpness(-shear)))
+        .fold((0.0, upright), |best, candidate| {
+            match candidate.1 > best.1 {
+                true => candidate,
+                false => best,
+            }
+        });
+    (best >= upright * MIN_SHARPNESS_GAIN).then_some(shear)
+}
+
+/// whether the glyphs of the bitmap lean enough to be italic.
+pub fn is_italic(bitmap: &Bitmap) -> bool {
+    estimate_slant(bitmap).is_some_and(|shear| shear >= MIN_ITALIC_SHEAR)
+}
+
+/// wrap the text in italic tags, keeping the surrounding whitespace outside of them.
+pub fn italicize(text: &str) -> String {
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+        return text.to_string();
+    }
+    let start = text.len() - text.trim_start().len();
+    let end = start + trimmed.len();
+    format!(
+        "{}{ITALIC_OPEN}{trimmed}{ITALIC_CLOSE}{}",
+        &text[..start],
+        &text[end..]
+    )
+}
+
+/// An [`OcrEngine`] that tags the italic lines of the text recognized by another engine.
+///
+/// the lines are found with [`crate::segment::text_lines`]. when their number does not match
+/// the lines of the recognized text the whole text is tagged if most of the lines are italic.
+pub struct ItalicTagged<E> {
+    engine: E,
+}
+
+impl<E> ItalicTagged<E> {
+    pub fn new(engine: E) -> Self {
+        Self { engine }
+    }
+}
+
+impl<E: OcrEngine> OcrEngine for ItalicTagged<E> {
+    fn recognize(&mut self, bitmap: &Bitmap) -> Result<OcrResult> {
+        let mut result = self.engine.recognize(bitmap)?;
+        let italic = text_lines(bitmap)
+            .into_iter()
+            .map(|region| {
+                is_italic(&bitmap.sub_image(region.x, region.y, region.width, region.height))
+            })
+            .collect::<Vec<_>>();
+        if !italic.contains(&true) {
+            return Ok(result);
+        }
+
+        let text_lines = result
+            .text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .collect::<Vec<_>>();
+        let mut text = String::with_capacity(result.text.len() + italic.len() * 7);
+        if text_lines.len() == italic.len() {
+            for (line, italic) in text_lines.iter().zip(&italic) {
+                match italic {
+                    true => text.push_str(&italicize(line)),
+                    false => text.push_str(line),
+                }
+                text.push('\n');
+            }
+            for (line, italic) in result.lines.iter_mut().zip(&italic) {
+                if *italic {
+                    line.text = italicize(&line.text);
+                }
+            }
+        } else if italic.iter().filter(|italic| **italic).count() * 2 > italic.len() {
+            text = italicize(&result.text);
+        } else {
+            return Ok(result);
+        }
+        result.text = text;
+        Ok(result)
+    }
+
+    fn recognize_line(&mut self, bitmap: &Bitmap) -> Result<OcrResult> {
+        let mut result = self.engine.recognize_line(bitmap)?;
+        if is_italic(bitmap) {
+            result.text = italicize(&result.text);
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// "glyphs" made of vertical strokes, sheared by `shear` pixels per row.
+    fn strokes(shear: f32) -> Bitmap {
+        let (width, height) = (80u32, 30u32);
+        let mut pixels = vec![0u8; (width * height * 4) as usize];
+        for y in 0..height {
+            let offset = (shear * (height - y) as f32).round() as u32;
+            for stroke in [4, 14, 20, 32, 40, 52] {
+                for x in stroke + offset..stroke + offset + 3 {
+                    let i = ((y * width + x) * 4) as usize;
+                    pixels[i..i + 4].copy_from_slice(&[255, 255, 255, 255]);
+                }
+            }
+        }
+        Bitmap {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    #[test]
+    fn test_italic_detection() {
+        assert!(!is_italic(&strokes(0.0)));
+        assert!(is_italic(&strokes(0.25)));
+        let slant = estimate_slant(&strokes(0.25)).unwrap();
+        assert!((slant - 0.25).abs() < 0.03, "{slant}");
+
+        assert_eq!(italicize(" text\n"), " <i>text</i>\n");
+
+        let result = ItalicTagged::new(crate::MockOcr)
+            .recognize(&strokes(0.3))
+            .unwrap();
+        assert_eq!(result.text, "<i>80x30 bitmap, 540 opaque</i>\n");
+    }
+}
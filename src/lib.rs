@@ -0,0 +1,561 @@
+//! Convert PGS (`.sup`) bitmap subtitles to text subtitles using OCR.
+//!
+//! The conversion is a pipeline of three stages, each one usable on its own:
+//! + [`subtitles_extract`] turns decoded [`pgs::DisplaySet`]s into [`BitmapSubtitle`]s.
+//! + [`subtitles_ocr`] recognizes the text of the bitmaps with an [`OcrEngine`], producing
+//!   [`TextSubtitle`]s.
+//! + a [`SubtitleWriter`], like [`srt::SrtWriter`], merges overlapping subtitles into cues and
+//!   writes them out.
+//!
+//! [`Converter`] runs the whole pipeline over a reader:
+//!
+//! ```no_run
+//! # fn main() -> sup_to_srt::Result<()> {
+//! let converter = sup_to_srt::Converter::builder().language("por").build();
+//! let input = std::fs::File::open("subtitle.sup")?;
+//! let mut srt = Vec::new();
+//! converter.convert(std::io::BufReader::new(input), &mut srt)?;
+//! # Ok(())
+//! # }
+//! ```
+use std::{
+    io::{Read, Write},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+pub use pgs;
+
+pub mod ass;
+pub mod bdn;
+pub mod cue;
+pub mod info;
+pub mod italic;
+pub mod mkv;
+pub mod palette;
+pub mod preprocess;
+pub mod review;
+pub mod segment;
+pub mod srt;
+pub mod ts;
+pub mod validate;
+pub mod vobsub;
+pub mod vtt;
+
+mod extract;
+mod ocr;
+
+pub use extract::{
+    display_set_reader, subtitles_extract, subtitles_extract_with_options, Composition,
+    ExtractOptions, ForcedSubtitles, PaletteUpdates, Rendering, SubtitleExtractor, FORCED_MARKER,
+};
+pub use ocr::{
+    subtitles_ocr, subtitles_ocr_with, ExternalOcr, MockOcr, OcrBackend, OcrEngine, OcrLine,
+    OcrOptions, OcrResult, OcrWord, TesseractOcr,
+};
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// reading the input or writing the output failed.
+    Io(std::io::Error),
+    /// the input could not be decoded as a pgs stream.
+    Decode(std::io::Error),
+    /// the pgs stream decoded but its contents are not valid.
+    InvalidStream(String),
+    /// the OCR engine failed.
+    Ocr {
+        context: &'static str,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
+
+impl Error {
+    /// errors returned by the [`pgs`] decoder are either io errors from the underlying reader or
+    /// [`std::io::ErrorKind::InvalidData`] for malformed data.
+    pub(crate) fn from_decode(err: std::io::Error) -> Self {
+        match err.kind() {
+            std::io::ErrorKind::InvalidData => Self::Decode(err),
+            _ => Self::Io(err),
+        }
+    }
+
+    pub(crate) fn ocr(
+        context: &'static str,
+        source: impl Into<Box<dyn std::error::Error + Send + Sync>>,
+    ) -> Self {
+        Self::Ocr {
+            context,
+            source: source.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "io error: {err}"),
+            Error::Decode(err) => write!(f, "decoding pgs: {err}"),
+            Error::InvalidStream(reason) => write!(f, "invalid pgs stream: {reason}"),
+            Error::Ocr { context, source } => write!(f, "ocr {context}: {source}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) | Error::Decode(err) => Some(err),
+            Error::InvalidStream(_) => None,
+            Error::Ocr { source, .. } => Some(source.as_ref()),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeRange {
+    pub begin: Duration,
+    pub end: Duration,
+}
+
+impl TimeRange {
+    pub fn new(begin: Duration, end: Duration) -> Self {
+        Self { begin, end }
+    }
+}
+
+/// pixels, and palette entries, with less alpha are background rather than part of the text.
+pub(crate) const MIN_OPAQUE: u8 = 128;
+
+#[derive(Debug, Default, Clone)]
+pub struct Bitmap {
+    pub width: u32,
+    pub height: u32,
+    /// RGBA 8-bit per channel data
+    pub pixels: Vec<u8>,
+}
+
+impl Bitmap {
+    /// the area of the bitmap with the given top left corner and size, clamped to the bitmap.
+    pub fn sub_image(&self, top_left_x: u32, top_left_y: u32, width: u32, height: u32) -> Bitmap {
+        let top_left_x = top_left_x.min(self.width);
+        let top_left_y = top_left_y.min(self.height);
+        let width = width.min(self.width - top_left_x);
+        let height = height.min(self.height - top_left_y);
+        let mut output_pixels = Vec::with_capacity((4 * width * height) as usize);
+
+        for y in top_left_y..top_left_y + height {
+            let begin_offset = (y * self.width * 4) as usize + top_left_x as usize * 4;
+            let end_offset = begin_offset + width as usize * 4;
+            let line = &self.pixels[begin_offset..end_offset];
+            output_pixels.extend(line);
+        }
+
+        Self {
+            width,
+            height,
+            pixels: output_pixels,
+        }
+    }
+
+    /// encode the bitmap as an RGBA png, keeping the transparency.
+    pub fn write_png<W: Write>(&self, writer: W) -> std::io::Result<()> {
+        let mut encoder = png::Encoder::new(writer, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(std::io::Error::other)?;
+        writer
+            .write_image_data(&self.pixels)
+            .map_err(std::io::Error::other)?;
+        writer.finish().map_err(std::io::Error::other)
+    }
+}
+
+/// Writes the files produced next to the main output, like exported images, given their path
+/// and whole content.
+///
+/// the default writes them with [`std::fs::write`], replacing existing files. applications
+/// usually provide one that refuses to overwrite files and writes them atomically.
+#[derive(Clone)]
+pub struct FileWriter(Arc<WriteFile>);
+
+type WriteFile = dyn Fn(&Path, &[u8]) -> std::io::Result<()> + Send + Sync;
+
+impl FileWriter {
+    pub fn new(
+        write: impl Fn(&Path, &[u8]) -> std::io::Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        Self(Arc::new(write))
+    }
+
+    pub fn write(&self, path: &Path, data: &[u8]) -> std::io::Result<()> {
+        (self.0)(path, data)
+    }
+}
+
+impl Default for FileWriter {
+    fn default() -> Self {
+        Self::new(|path, data| std::fs::write(path, data))
+    }
+}
+
+impl std::fmt::Debug for FileWriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("FileWriter")
+    }
+}
+
+/// Where a subtitle is shown on screen, in pixels.
+///
+/// A zero screen size means the placement is unknown.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Placement {
+    /// horizontal position of the top left corner
+    pub x: u32,
+    /// vertical position of the top left corner
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// width of the video the subtitle is shown on
+    pub screen_width: u32,
+    /// height of the video the subtitle is shown on
+    pub screen_height: u32,
+}
+
+impl Placement {
+    pub fn is_known(&self) -> bool {
+        self.screen_width != 0 && self.screen_height != 0
+    }
+
+    /// whether text written over the placement should be anchored by its bottom edge.
+    ///
+    /// subtitles in the lower half of the screen are anchored by their bottom edge so multi line
+    /// text grows upwards, like it would on a player, while subtitles in the upper half are
+    /// anchored by their top edge.
+    pub fn anchored_at_bottom(&self) -> bool {
+        self.y * 2 + self.height > self.screen_height
+    }
+
+    /// the area covered by both placements, empty if they do not overlap.
+    pub fn intersection(&self, other: &Placement) -> Placement {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = (self.x + self.width).min(other.x + other.width).max(x);
+        let bottom = (self.y + self.height).min(other.y + other.height).max(y);
+        Placement {
+            x,
+            y,
+            width: right - x,
+            height: bottom - y,
+            screen_width: self.screen_width,
+            screen_height: self.screen_height,
+        }
+    }
+
+    /// smallest placement containing both placements.
+    pub fn union(&self, other: &Placement) -> Placement {
+        if !other.is_known() {
+            return *self;
+        }
+        if !self.is_known() {
+            return *other;
+        }
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let right = (self.x + self.width).max(other.x + other.width);
+        let bottom = (self.y + self.height).max(other.y + other.height);
+        Placement {
+            x,
+            y,
+            width: right - x,
+            height: bottom - y,
+            screen_width: self.screen_width,
+            screen_height: self.screen_height,
+        }
+    }
+}
+
+/// A subtitle image together with the time it is on screen.
+#[derive(Debug, Clone)]
+pub struct BitmapSubtitle {
+    pub range: TimeRange,
+    pub bitmap: Bitmap,
+    pub placement: Placement,
+    /// the color of the text fill, see [`palette::PaletteClasses`], if known.
+    pub color: Option<(u8, u8, u8)>,
+    /// shown even when subtitles are turned off, see [`pgs::CompositionObject::forced`].
+    pub forced: bool,
+}
+
+/// The recognized text of a [`BitmapSubtitle`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextSubtitle {
+    pub range: TimeRange,
+    /// italic text is wrapped in [`italic::ITALIC_OPEN`] and [`italic::ITALIC_CLOSE`] tags.
+    pub text: String,
+    pub placement: Placement,
+    /// the dominant color of the text, if known.
+    pub color: Option<(u8, u8, u8)>,
+    pub forced: bool,
+    /// mean confidence of the OCR engine in the text, from 0 to 100, if it reports one.
+    pub confidence: Option<f32>,
+    /// the recognized words with their confidence, empty if the engine does not report them.
+    pub words: Vec<OcrWord>,
+}
+
+/// Writes text subtitles in some format as they are recognized.
+pub trait SubtitleWriter {
+    /// subtitles must be pushed ordered by their begin time.
+    fn push(&mut self, subtitle: TextSubtitle) -> std::io::Result<()>;
+
+    /// write any remaining subtitles and flush the writer.
+    fn finish(&mut self) -> std::io::Result<()>;
+}
+
+/// Output subtitle format.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Format {
+    #[default]
+    Srt,
+    /// WebVTT, with cue settings placing the text where the bitmap was shown.
+    Vtt,
+    /// Advanced SubStation Alpha, keeping the position and color of each subtitle.
+    Ass,
+}
+
+impl Format {
+    /// a writer of this format writing to `output`.
+    pub fn writer<'a, W: Write + 'a>(self, output: W) -> Box<dyn SubtitleWriter + 'a> {
+        match self {
+            Format::Srt => Box::new(srt::SrtWriter::new(output)),
+            Format::Vtt => Box::new(vtt::VttWriter::new(output)),
+            Format::Ass => Box::new(ass::AssWriter::new(output)),
+        }
+    }
+}
+
+/// Runs the whole conversion pipeline, see [`ConverterBuilder`] for the available options.
+#[derive(Debug, Clone)]
+pub struct Converter {
+    format: Format,
+    extract: ExtractOptions,
+    ocr: OcrOptions,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ConverterBuilder {
+    format: Format,
+    extract: ExtractOptions,
+    ocr: OcrOptions,
+}
+
+impl ConverterBuilder {
+    /// Tesseract language code to use for OCR, defaults to `eng`.
+    ///
+    /// Available language codes can be found at:
+    /// https://tesseract-ocr.github.io/tessdoc/Data-Files-in-different-versions.html
+    pub fn language(mut self, language: impl Into<String>) -> Self {
+        self.ocr.language = language.into();
+        self
+    }
+
+    /// output subtitle format, defaults to [`Format::Srt`].
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// how palette only updates, like fades, are handled, defaults to [`PaletteUpdates::Fold`].
+    pub fn palette_updates(mut self, palette_updates: PaletteUpdates) -> Self {
+        self.extract.palette_updates = palette_updates;
+        self
+    }
+
+    /// how the objects of each display set are turned into subtitles, defaults to
+    /// [`Composition::Objects`].
+    pub fn composition(mut self, composition: Composition) -> Self {
+        self.extract.composition = composition;
+        self
+    }
+
+    /// shift all subtitles so the first one starts at `origin`, defaults to keeping the times
+    /// of the stream.
+    pub fn rebase(mut self, origin: Option<Duration>) -> Self {
+        self.extract.rebase = origin;
+        self
+    }
+
+    /// how malformed input is handled, defaults to [`pgs::DecodeMode::Strict`] which stops at the
+    /// first error.
+    pub fn decode_mode(mut self, mode: pgs::DecodeMode) -> Self {
+        self.extract.mode = mode;
+        self
+    }
+
+    /// which subtitles are kept depending on their forced flag, defaults to
+    /// [`ForcedSubtitles::All`].
+    pub fn forced(mut self, forced: ForcedSubtitles) -> Self {
+        self.extract.forced = forced;
+        self
+    }
+
+    /// how the bitmaps are rendered from the palette, defaults to [`Rendering::Palette`].
+    pub fn rendering(mut self, rendering: Rendering) -> Self {
+        self.extract.rendering = rendering;
+        self
+    }
+
+    /// OCR engine used to recognize the text, defaults to [`OcrBackend::Tesseract`].
+    pub fn ocr_backend(mut self, backend: OcrBackend) -> Self {
+        self.ocr.backend = backend;
+        self
+    }
+
+    /// steps run on every bitmap before OCR, in order, defaults to none.
+    ///
+    /// [`preprocess::Preprocess::recommended`] are steps that usually improve tesseract's
+    /// accuracy, they depend on the [`Rendering`].
+    pub fn preprocess(mut self, steps: Vec<preprocess::Preprocess>) -> Self {
+        self.ocr.preprocess = steps;
+        self
+    }
+
+    /// split the bitmaps into lines and recognize every line on its own, defaults to `false`.
+    ///
+    /// see [`segment::LineSegmented`].
+    pub fn segment_lines(mut self, segment_lines: bool) -> Self {
+        self.ocr.segment_lines = segment_lines;
+        self
+    }
+
+    /// wrap the lines whose glyphs are slanted in `<i>` tags, defaults to `false`.
+    ///
+    /// see [`italic::ItalicTagged`].
+    pub fn italics(mut self, italics: bool) -> Self {
+        self.ocr.italics = italics;
+        self
+    }
+
+    /// number of OCR worker threads, defaults to the available parallelism, which 0 also
+    /// stands for.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.ocr.threads = Some(threads);
+        self
+    }
+
+    pub fn build(self) -> Converter {
+        Converter {
+            format: self.format,
+            extract: self.extract,
+            ocr: self.ocr,
+        }
+    }
+}
+
+impl Default for Converter {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+impl Converter {
+    pub fn builder() -> ConverterBuilder {
+        ConverterBuilder::default()
+    }
+
+    /// convert the pgs stream read from `input` and write the subtitles to `output`.
+    ///
+    /// cues are written to `output` as they are recognized.
+    pub fn convert<R, W>(&self, input: R, output: W) -> Result<()>
+    where
+        R: Read + Send,
+        W: Write,
+    {
+        let mut writer = self.format.writer(output);
+        self.convert_to_writer(input, writer.as_mut())
+    }
+
+    /// convert the pgs stream read from `input` and push the subtitles to `writer`, ignoring
+    /// the output format.
+    pub fn convert_to_writer<R>(&self, input: R, writer: &mut dyn SubtitleWriter) -> Result<()>
+    where
+        R: Read + Send,
+    {
+        self.run(input, writer, None)
+    }
+
+    /// like [`Converter::convert_to_writer`], also checking the confidence of every subtitle
+    /// with `review`.
+    pub fn convert_with_review<R>(
+        &self,
+        input: R,
+        writer: &mut dyn SubtitleWriter,
+        review: &mut review::ReviewReport,
+    ) -> Result<()>
+    where
+        R: Read + Send,
+    {
+        self.run(input, writer, Some(review))
+    }
+
+    fn run<R>(
+        &self,
+        input: R,
+        writer: &mut dyn SubtitleWriter,
+        mut review: Option<&mut review::ReviewReport>,
+    ) -> Result<()>
+    where
+        R: Read + Send,
+    {
+        let display_sets = display_set_reader(input, &self.extract);
+        let bitmap_subtitles = subtitles_extract_with_options(display_sets, self.extract.clone());
+        let mark_forced = self.extract.forced == ForcedSubtitles::Mark;
+        ocr::subtitles_ocr_bitmaps(bitmap_subtitles, &self.ocr, |mut subtitle, bitmap| {
+            if let Some(review) = review.as_deref_mut() {
+                review.check(&subtitle, &bitmap)?;
+            }
+            if mark_forced && subtitle.forced {
+                subtitle.text.insert_str(0, FORCED_MARKER);
+            }
+            Ok(writer.push(subtitle)?)
+        })?;
+        writer.finish()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PGS: &[u8] = include_bytes!("../subtitle.sup");
+
+    #[test]
+    fn test_subtitles_to_srt() {
+        let mut srt = Vec::new();
+        Converter::default().convert(PGS, &mut srt).unwrap();
+        let srt = String::from_utf8(srt).unwrap();
+        insta::assert_snapshot!(srt);
+    }
+
+    #[test]
+    fn test_convert_mock_ocr() {
+        let mut srt = Vec::new();
+        Converter::builder()
+            .ocr_backend(OcrBackend::Mock)
+            .build()
+            .convert(PGS, &mut srt)
+            .unwrap();
+        let srt = String::from_utf8(srt).unwrap();
+        insta::assert_snapshot!(srt);
+    }
+}
@@ -1,451 +1,860 @@
 use std::{
-    collections::HashMap,
-    io::Read,
+    io::{BufRead, BufReader, Read, Write},
     path::PathBuf,
-    time::Duration,
 };
 
-use clap::Parser;
-use color_eyre::{
-    eyre::{eyre, Context},
-    Result,
-};
+use clap::{Parser, Subcommand, ValueEnum};
+use color_eyre::{eyre::Context, Result};
 #[cfg(feature = "viewer")]
 use minifb::{Key, KeyRepeat};
+use sup_to_srt::Converter;
+
+mod output;
 
 #[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Open the subtitle image viewer.
     ///
     /// Use A and D to cycle trought the images.
     #[clap(long)]
     view: bool,
 
-    /// input pgs/.sup file, must exist.
-    /// if not specified then the input is read from stdin.
+    /// Export the subtitle images as png files, with a BDN XML index, into this directory
+    /// instead of converting them to text.
+    #[clap(long, value_name = "DIR", conflicts_with = "view")]
+    export_images: Option<PathBuf>,
+
+    /// Video frame rate used for the BDN XML timecodes, like `23.976`, `25` or `24000/1001`.
+    #[clap(long, default_value = "23.976")]
+    frame_rate: sup_to_srt::bdn::FrameRate,
+
+    /// input pgs/.sup file, Matroska file or (M2)TS transport stream with a pgs track, must
+    /// exist.
+    /// if not specified or `-` then the input is read from stdin.
     input: Option<PathBuf>,
 
-    /// output srt file, must not exist.
-    /// if not specified then the output goes to stdout.
+    /// output subtitle file, must not exist unless --force is used.
+    /// if not specified or `-` then the output goes to stdout.
     output: Option<PathBuf>,
 
+    /// Output subtitle format.
+    ///
+    /// If not specified then it is picked from the output file extension, defaulting to srt.
+    #[clap(long, value_enum)]
+    format: Option<FormatArg>,
+
+    /// Codec of the text track added to the Matroska output.
+    #[clap(long, value_enum, default_value = "srt")]
+    text_codec: TextCodecArg,
+
+    /// Screen size of the DVD the vobsub subtitles are made for.
+    #[clap(long, value_enum, default_value = "ntsc")]
+    dvd_standard: DvdStandardArg,
+
+    /// How palette only updates, used for fades and karaoke color changes, are handled.
+    #[clap(long, value_enum, default_value = "fold")]
+    palette_updates: PaletteUpdatesArg,
+
+    /// How the objects of each display set are turned into subtitles.
+    #[clap(long, value_enum, default_value = "objects")]
+    composition: CompositionArg,
+
+    /// Shift all subtitles so the first one starts at this time, like `0` or `00:01:30.500`.
+    #[clap(long, value_name = "TIME", value_parser = parse_time)]
+    rebase: Option<std::time::Duration>,
+
+    /// Skip corrupt data and invalid display sets with a warning instead of stopping at the
+    /// first error.
+    #[clap(long)]
+    resilient: bool,
+
+    /// Number of the Matroska track, or pid of the transport stream, to read. Defaults to the
+    /// first pgs track.
+    #[clap(long)]
+    track: Option<u64>,
+
+    /// How the subtitle images are drawn from their palette.
+    ///
+    /// `ocr` tells the text fill apart from its outline using the palette and draws the fill in
+    /// black on white, which usually recognizes better than the original colors.
+    #[clap(long, value_enum, default_value = "palette")]
+    render: RenderingArg,
+
+    /// Which subtitles to output depending on their forced flag, used for translations of
+    /// foreign dialogue.
+    #[clap(long, value_enum, default_value = "all")]
+    forced: ForcedArg,
+
+    /// Overwrite the output file if it already exists.
+    #[clap(short, long)]
+    force: bool,
+
+    /// Create the parent directories of the output file if they are missing.
+    #[clap(long)]
+    create_dirs: bool,
+
     /// Tesseract language code to use for OCR.
     ///
     /// Available language codes can be found at:
     /// https://tesseract-ocr.github.io/tessdoc/Data-Files-in-different-versions.html
     #[clap(long, default_value = "eng")]
     language: String,
+
+    /// Steps run on the subtitle images before OCR, in order, separated by commas.
+    ///
+    /// The steps are `flatten[=black|white|RRGGBB]` onto an opaque background, `grayscale`,
+    /// `strip-outline` removing the colors darker than the most common bright one, `invert`,
+    /// `upscale[=HEIGHT]` to a minimum height, `pad[=PIXELS]` and `binarize` to black and white
+    /// at a threshold picked from the colors. Outline and fill are only guessed from the
+    /// colors, `--render ocr` tells them apart from the palette. `recommended` stands for
+    /// `strip-outline,flatten,grayscale,invert,upscale,pad,binarize`, or
+    /// `flatten=white,upscale,pad,binarize` with `--render ocr`, which already removes the
+    /// outline and so cannot be combined with `strip-outline`.
+    #[clap(long, value_name = "STEPS", value_delimiter = ',', value_parser = parse_preprocess)]
+    preprocess: Vec<PreprocessSteps>,
+
+    /// Split the subtitle images into lines of text and recognize every line on its own.
+    #[clap(long)]
+    segment_lines: bool,
+
+    /// Detect italic lines from the slant of their letters and wrap them in `<i>` tags.
+    #[clap(long)]
+    italics: bool,
+
+    /// Subtitles with a mean OCR confidence below this percentage are reported as needing
+    /// review. Defaults to 80 when a review report is written.
+    #[clap(long, value_name = "PERCENT", conflicts_with_all = ["view", "export_images"])]
+    min_confidence: Option<f32>,
+
+    /// Write the subtitles below --min-confidence, with their times, text and least confident
+    /// words, to this file, or `-` for stdout. Written as CSV for a `.csv` file and JSON otherwise.
+    #[clap(long, value_name = "FILE", conflicts_with_all = ["view", "export_images"])]
+    review_report: Option<PathBuf>,
+
+    /// Export the images of the subtitles below --min-confidence as png files into this
+    /// directory, referenced from the review report.
+    #[clap(long, value_name = "DIR", conflicts_with_all = ["view", "export_images"])]
+    review_images: Option<PathBuf>,
+
+    /// OCR engine used to recognize the text.
+    #[clap(long, value_enum, default_value = "tesseract")]
+    ocr_engine: OcrEngineArg,
+
+    /// Command run by the external OCR engine for every subtitle, like `my-ocr --lang eng`.
+    ///
+    /// The subtitle image is written to its standard input as a png and the text is read from
+    /// its standard output.
+    #[clap(long, value_name = "COMMAND", required_if_eq("ocr_engine", "external"))]
+    ocr_command: Option<String>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-struct TimeRange {
-    begin: Duration,
-    end: Duration,
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Check a pgs stream against the Blu-ray spec and report the problems found.
+    ///
+    /// Exits with an error if the stream does not conform.
+    Validate {
+        /// input pgs/.sup file, if not specified or `-` then the input is read from stdin.
+        input: Option<PathBuf>,
+
+        /// Print the findings as a JSON list instead of a report.
+        #[clap(long)]
+        json: bool,
+
+        /// Number of the Matroska track, or pid of the transport stream, to read. Defaults to the
+        /// first pgs track.
+        #[clap(long)]
+        track: Option<u64>,
+    },
+    /// Describe a pgs stream: display size, frame rate, number of display sets, epochs,
+    /// objects and palettes, and the time it spans.
+    Info {
+        /// input pgs/.sup file, if not specified or `-` then the input is read from stdin.
+        input: Option<PathBuf>,
+
+        /// Print every decoded segment, with its byte offset in the input, instead of the
+        /// summary.
+        #[clap(long, value_enum, num_args = 0..=1, default_missing_value = "json")]
+        dump: Option<DumpFormatArg>,
+
+        /// Skip corrupt data with a warning instead of stopping at the first error.
+        #[clap(long)]
+        resilient: bool,
+
+        /// Number of the Matroska track, or pid of the transport stream, to read. Defaults to the
+        /// first pgs track.
+        #[clap(long)]
+        track: Option<u64>,
+    },
+    /// List the tracks of a Matroska file, or the streams of a transport stream, with their
+    /// codec and language.
+    Tracks {
+        /// input Matroska file or (M2)TS transport stream, if not specified or `-` then the
+        /// input is read from stdin.
+        input: Option<PathBuf>,
+    },
 }
 
-impl TimeRange {
-    fn new(begin: Duration, end: Duration) -> Self {
-        Self { begin, end }
-    }
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum DumpFormatArg {
+    Json,
+    Yaml,
 }
 
-#[derive(Debug, Default, Clone)]
-struct Bitmap {
-    width: u32,
-    height: u32,
-    /// RGBA 8-bit per channel data
-    pixels: Vec<u8>,
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum FormatArg {
+    Srt,
+    Vtt,
+    Ass,
+    /// DVD VobSub, written to the output `.idx` and `.sub` files without OCR.
+    Vobsub,
+    /// A copy of the input Matroska file with the subtitles added as a new text track, named
+    /// and tagged with the OCR language.
+    Mkv,
 }
 
-impl Bitmap {
-    fn sub_image(&self, top_left_x: u32, top_left_y: u32, width: u32, height: u32) -> Bitmap {
-        let mut output_pixels = Vec::with_capacity((4 * width * height) as usize);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OcrEngineArg {
+    Tesseract,
+    /// Run the command given with --ocr-command.
+    External,
+    /// Describe the subtitle images instead of recognizing them, for testing.
+    Mock,
+}
 
-        for y in top_left_y..top_left_y.saturating_add(height).min(self.height) {
-            let begin_offset = (y * self.width * 4) as usize + top_left_x as usize * 4;
-            let end_offset = begin_offset + width as usize * 4;
-            let line = &self.pixels[begin_offset..end_offset];
-            output_pixels.extend(line);
-        }
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum TextCodecArg {
+    /// S_TEXT/UTF8, plain srt text.
+    Srt,
+    /// S_TEXT/WEBVTT, keeping the position of the subtitles.
+    Vtt,
+}
 
-        Self {
-            width,
-            height,
-            pixels: output_pixels,
-        }
-    }
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum DvdStandardArg {
+    /// 720x480
+    Ntsc,
+    /// 720x576
+    Pal,
 }
 
-#[derive(Debug, Clone)]
-struct BitmapSubtitle {
-    range: TimeRange,
-    bitmap: Bitmap,
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum PaletteUpdatesArg {
+    /// Fold fades into the timing of the subtitle being shown.
+    Fold,
+    /// Output every palette update as a separate subtitle.
+    Events,
 }
 
-#[derive(Debug, Clone)]
-struct TextSubtitle {
-    range: TimeRange,
-    text: String,
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum CompositionArg {
+    /// Every object becomes its own subtitle.
+    Objects,
+    /// Draw the objects onto the screen, clipped to their windows, and keep the windows area.
+    Windows,
+    /// Draw the objects onto the screen, clipped to their windows, and keep the whole frame.
+    Frame,
 }
 
-fn main() -> Result<()> {
-    color_eyre::install().unwrap();
-    tracing_subscriber::fmt::init();
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum RenderingArg {
+    /// The colors of the palette, like a player shows them.
+    Palette,
+    /// Black text fill on white, without the outline.
+    Ocr,
+}
 
-    let args = Args::parse();
-    let input_data = match args.input {
-        Some(path) => {
-            tracing::info!("reading from {}", path.display());
-            std::fs::read(&path).context("reading from input file")?
-        }
-        None => {
-            tracing::info!("reading from stdin");
-            let mut stdin = std::io::stdin().lock();
-            let mut buf = Vec::default();
-            stdin.read_to_end(&mut buf).context("reading from stdin")?;
-            buf
+impl From<RenderingArg> for sup_to_srt::Rendering {
+    fn from(value: RenderingArg) -> Self {
+        match value {
+            RenderingArg::Palette => sup_to_srt::Rendering::Palette,
+            RenderingArg::Ocr => sup_to_srt::Rendering::Ocr,
         }
-    };
+    }
+}
 
-    tracing::info!("extracting bitmap subtitles from input");
-    let bitmap_subtitles = subtitles_extract(&input_data)?;
-    tracing::info!("extracted {} bitmap subtitles", bitmap_subtitles.len());
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum ForcedArg {
+    /// Output all subtitles.
+    All,
+    /// Output only the forced subtitles.
+    Only,
+    /// Output only the subtitles that are not forced.
+    Exclude,
+    /// Output all subtitles and prefix the text of the forced ones with `[forced]`.
+    Mark,
+}
 
-    if args.view {
-        #[cfg(feature = "viewer")]
-        subtitles_viewer(bitmap_subtitles)?;
-        #[cfg(not(feature = "viewer"))]
-        return Err(color_eyre::eyre::eyre!("viewer support not compiled"));
-    } else {
-        tracing::info!("performing OCR on bitmap subtitles");
-        let text_subtitles = subtitles_ocr(bitmap_subtitles, &args.language)?;
-        tracing::info!("OCR complete");
+/// parse a time given as `[[HH:]MM:]SS[.mmm]`.
+fn parse_time(value: &str) -> std::result::Result<std::time::Duration, String> {
+    let invalid = || format!("invalid time '{value}', expected [[HH:]MM:]SS[.mmm]");
+    let mut parts = value.rsplit(':');
+    let seconds = parts
+        .next()
+        .and_then(|seconds| seconds.replace(',', ".").parse::<f64>().ok())
+        .filter(|seconds| seconds.is_finite() && *seconds >= 0.0)
+        .ok_or_else(invalid)?;
+    let mut total = seconds;
+    for multiplier in [60.0, 3600.0] {
+        if let Some(part) = parts.next() {
+            total += part.parse::<u32>().map_err(|_| invalid())? as f64 * multiplier;
+        }
+    }
+    if parts.next().is_some() {
+        return Err(invalid());
+    }
+    Ok(std::time::Duration::from_secs_f64(total))
+}
 
-        tracing::info!("generating srt");
-        let srt = subtitles_to_srt(text_subtitles);
+/// one entry of the --preprocess list, `recommended` expands to several steps depending on
+/// --render.
+#[derive(Debug, Clone)]
+enum PreprocessSteps {
+    Recommended,
+    Step(sup_to_srt::preprocess::Preprocess),
+}
 
-        print!("{srt}");
+fn parse_preprocess(value: &str) -> std::result::Result<PreprocessSteps, String> {
+    match value {
+        "recommended" => Ok(PreprocessSteps::Recommended),
+        step => Ok(PreprocessSteps::Step(step.parse()?)),
     }
-
-    Ok(())
 }
 
-fn subtitles_extract(pgs: &[u8]) -> Result<Vec<BitmapSubtitle>> {
-    struct Object {
-        width: u16,
-        height: u16,
-        finished: bool,
-        data: Vec<u8>,
-        bitmap: Bitmap,
+impl FormatArg {
+    fn from_extension(path: &std::path::Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "srt" => Some(Self::Srt),
+            "vtt" => Some(Self::Vtt),
+            "ass" | "ssa" => Some(Self::Ass),
+            "idx" | "sub" => Some(Self::Vobsub),
+            "mkv" | "mks" => Some(Self::Mkv),
+            _ => None,
+        }
     }
+}
 
-    fn bitmap_from_object_and_palette(object: &Object, palette: &pgs::PDS) -> Result<Bitmap> {
-        let pixels_indexed = pgs::decode_rle_data(&object.data, object.width, object.height)
-            .context("decoding ODS rle data")?;
-        let mut pixels = Vec::with_capacity(pixels_indexed.len());
-        for idx in pixels_indexed {
-            let (r, g, b, a) = palette.entries[idx as usize].to_rgba();
-            pixels.extend([r, g, b, a]);
+impl From<FormatArg> for sup_to_srt::Format {
+    fn from(value: FormatArg) -> Self {
+        match value {
+            FormatArg::Srt => sup_to_srt::Format::Srt,
+            FormatArg::Vtt => sup_to_srt::Format::Vtt,
+            FormatArg::Ass => sup_to_srt::Format::Ass,
+            FormatArg::Vobsub | FormatArg::Mkv => {
+                unreachable!("{value:?} output does not go through the converter")
+            }
         }
-        Ok(Bitmap {
-            width: u32::from(object.width),
-            height: u32::from(object.height),
-            pixels,
-        })
     }
+}
 
-    let display_sets = pgs::decode_display_sets(pgs).context("parsing pgs")?;
-    if display_sets.is_empty() {
-        tracing::warn!("display_sets.len() = 0 ");
-        return Ok(Default::default());
+impl From<PaletteUpdatesArg> for sup_to_srt::PaletteUpdates {
+    fn from(value: PaletteUpdatesArg) -> Self {
+        match value {
+            PaletteUpdatesArg::Fold => sup_to_srt::PaletteUpdates::Fold,
+            PaletteUpdatesArg::Events => sup_to_srt::PaletteUpdates::Events,
+        }
     }
+}
 
-    let display_set_0 = &display_sets[0];
-    if display_set_0.pcs.composition_state != pgs::CompositionState::EpochStart {
-        return Err(eyre!("display set 0 does not start an epoch"));
+impl From<CompositionArg> for sup_to_srt::Composition {
+    fn from(value: CompositionArg) -> Self {
+        match value {
+            CompositionArg::Objects => sup_to_srt::Composition::Objects,
+            CompositionArg::Windows => sup_to_srt::Composition::Windows,
+            CompositionArg::Frame => sup_to_srt::Composition::Frame,
+        }
     }
+}
 
-    let display_width = display_set_0.pcs.width;
-    let display_height = display_set_0.pcs.height;
-    let mut current_epoch = 0;
-    let mut objects: HashMap<u16, Object> = Default::default();
-    let mut palettes: HashMap<u8, pgs::PDS> = Default::default();
-    let mut subtitles: Vec<BitmapSubtitle> = Vec::default();
-    // index of images inserted in the previous display set
-    // used to patch the end time
-    let mut previous_subtitles: Vec<usize> = Vec::default();
+impl From<ForcedArg> for sup_to_srt::ForcedSubtitles {
+    fn from(value: ForcedArg) -> Self {
+        match value {
+            ForcedArg::All => sup_to_srt::ForcedSubtitles::All,
+            ForcedArg::Only => sup_to_srt::ForcedSubtitles::Only,
+            ForcedArg::Exclude => sup_to_srt::ForcedSubtitles::Exclude,
+            ForcedArg::Mark => sup_to_srt::ForcedSubtitles::Mark,
+        }
+    }
+}
 
-    for ds in display_sets {
-        assert_eq!(ds.pcs.width, display_width);
-        assert_eq!(ds.pcs.height, display_height);
+impl From<TextCodecArg> for sup_to_srt::mkv::TextCodec {
+    fn from(value: TextCodecArg) -> Self {
+        match value {
+            TextCodecArg::Srt => sup_to_srt::mkv::TextCodec::Srt,
+            TextCodecArg::Vtt => sup_to_srt::mkv::TextCodec::WebVtt,
+        }
+    }
+}
 
-        let current_time = pgs::clock_to_duration(ds.pcs.header.pts);
-        for subtitle_idx in previous_subtitles.drain(..) {
-            subtitles[subtitle_idx].range.end = current_time;
+impl From<DvdStandardArg> for sup_to_srt::vobsub::DvdStandard {
+    fn from(value: DvdStandardArg) -> Self {
+        match value {
+            DvdStandardArg::Ntsc => sup_to_srt::vobsub::DvdStandard::Ntsc,
+            DvdStandardArg::Pal => sup_to_srt::vobsub::DvdStandard::Pal,
         }
+    }
+}
+
+fn main() -> Result<()> {
+    color_eyre::install().unwrap();
+    // stdout may be the srt output, which is locked while converting
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .with_writer(std::io::stderr)
+        .init();
 
-        match ds.pcs.composition_state {
-            pgs::CompositionState::EpochStart => {
-                current_epoch += 1;
-                objects.clear();
-                palettes.clear();
-                tracing::debug!("moving to epoch {current_epoch}");
+    let args = Args::parse();
+    if let Some(command) = args.command {
+        return match command {
+            Command::Validate { input, json, track } => validate(open_input(input, track)?, json),
+            Command::Info {
+                input,
+                dump,
+                resilient,
+                track,
+            } => info(open_input(input, track)?, dump, decode_mode(resilient)),
+            Command::Tracks { input } => tracks(open_reader(input)?),
+        };
+    }
+    let format = args
+        .format
+        .or_else(|| args.output.as_deref().and_then(FormatArg::from_extension))
+        .unwrap_or(FormatArg::Srt);
+    let output_target = output::OutputTarget::from_arg(args.output);
+    let output_options = output::OutputOptions {
+        force: args.force,
+        create_dirs: args.create_dirs,
+    };
+    let extract_options = sup_to_srt::ExtractOptions {
+        palette_updates: args.palette_updates.into(),
+        composition: args.composition.into(),
+        rebase: args.rebase,
+        mode: decode_mode(args.resilient),
+        forced: args.forced.into(),
+        rendering: args.render.into(),
+    };
+    let ocr_backend = match args.ocr_engine {
+        OcrEngineArg::Tesseract => sup_to_srt::OcrBackend::Tesseract,
+        OcrEngineArg::External => {
+            let command = args.ocr_command.as_deref().unwrap_or_default();
+            sup_to_srt::OcrBackend::External(
+                sup_to_srt::ExternalOcr::from_command_line(command)
+                    .ok_or_else(|| color_eyre::eyre::eyre!("the OCR command is empty"))?,
+            )
+        }
+        OcrEngineArg::Mock => sup_to_srt::OcrBackend::Mock,
+    };
+    let preprocess = args
+        .preprocess
+        .into_iter()
+        .flat_map(|steps| match steps {
+            PreprocessSteps::Recommended => {
+                sup_to_srt::preprocess::Preprocess::recommended(extract_options.rendering)
             }
-            pgs::CompositionState::Normal => {}
-            pgs::CompositionState::AcquisitionPoint => {}
+            PreprocessSteps::Step(step) => vec![step],
+        })
+        .collect::<Vec<_>>();
+    // the fill of ocr renderings is the dark color, the heuristic would remove it
+    if extract_options.rendering == sup_to_srt::Rendering::Ocr
+        && preprocess.contains(&sup_to_srt::preprocess::Preprocess::StripOutline)
+    {
+        return Err(color_eyre::eyre::eyre!(
+            "--preprocess strip-outline cannot be used with --render ocr, which already removes \
+             the outline"
+        ));
+    }
+    let reviewing = args.min_confidence.is_some()
+        || args.review_report.is_some()
+        || args.review_images.is_some();
+    // vobsub output keeps the bitmaps, nothing is recognized to review
+    if reviewing && matches!(format, FormatArg::Vobsub) {
+        return Err(color_eyre::eyre::eyre!(
+            "--min-confidence, --review-report and --review-images need OCR, which the vobsub \
+             format does not run"
+        ));
+    }
+    if let Some(dir) = &args.review_images {
+        check_no_images(dir, output_options.force)?;
+    }
+    let mut review = reviewing.then(|| {
+        let min_confidence = args
+            .min_confidence
+            .unwrap_or(sup_to_srt::review::ReviewReport::DEFAULT_MIN_CONFIDENCE);
+        let report = sup_to_srt::review::ReviewReport::new(min_confidence)
+            .file_writer(file_writer(output_options));
+        match args.review_images.clone() {
+            Some(dir) => report.image_dir(dir),
+            None => report,
         }
-
-        for pds in ds.pds {
-            tracing::debug!("found palette {}", pds.palette_id);
-            palettes.insert(pds.palette_id, pds);
+    });
+    let review_target = args
+        .review_report
+        .map(|path| output::OutputTarget::from_arg(Some(path)));
+    if review_target == Some(output::OutputTarget::Stdout)
+        && output_target == output::OutputTarget::Stdout
+    {
+        return Err(color_eyre::eyre::eyre!(
+            "the review report and the subtitles cannot both be written to stdout"
+        ));
+    }
+    // opened before doing any work so an existing file is reported right away
+    let review_output = match review_target {
+        Some(target) => {
+            let output = output::Output::open(&target, output_options)?;
+            Some((target, output))
         }
+        None => None,
+    };
+    let input_path = args.input.clone();
+    let input = open_input(args.input, args.track)?;
 
-        let palette = match palettes.get(&ds.pcs.palette_id) {
-            Some(palette) => palette,
-            None => {
-                return Err(eyre!("PCS referenced invalid palette"));
+    if args.view {
+        #[cfg(feature = "viewer")]
+        {
+            tracing::info!("extracting bitmap subtitles from input");
+            let display_sets = sup_to_srt::display_set_reader(input, &extract_options);
+            let bitmap_subtitles =
+                sup_to_srt::subtitles_extract_with_options(display_sets, extract_options)
+                    .collect::<sup_to_srt::Result<Vec<_>>>()
+                    .context("extracting bitmap subtitles")?;
+            tracing::info!("extracted {} bitmap subtitles", bitmap_subtitles.len());
+            subtitles_viewer(bitmap_subtitles)?;
+        }
+        #[cfg(not(feature = "viewer"))]
+        return Err(color_eyre::eyre::eyre!("viewer support not compiled"));
+    } else if let Some(dir) = args.export_images {
+        let options = sup_to_srt::bdn::BdnOptions {
+            frame_rate: args.frame_rate,
+            language: args.language,
+            ..Default::default()
+        };
+        export_images(input, extract_options, dir, options, output_options)?;
+    } else if let FormatArg::Vobsub = format {
+        let path = match output_target {
+            output::OutputTarget::File(path) => path,
+            output::OutputTarget::Stdout => {
+                return Err(color_eyre::eyre::eyre!(
+                    "vobsub output needs an output file, it cannot be written to stdout"
+                ));
             }
         };
-
-        for ods in ds.ods {
-            let obj = objects.entry(ods.object_id).or_insert(Object {
-                width: ods.width,
-                height: ods.height,
-                finished: false,
-                data: Default::default(),
-                bitmap: Default::default(),
-            });
-
-            match ods.last_in_sequence {
-                pgs::LastInSequenceFlag::FirstAndLast => {
-                    obj.finished = true;
-                    obj.data.clear();
-                    obj.data.extend(ods.data);
-                    obj.bitmap = bitmap_from_object_and_palette(obj, palette)?;
-                }
-                pgs::LastInSequenceFlag::First => {
-                    obj.finished = false;
-                    obj.data.clear();
-                    obj.data.extend(ods.data);
-                }
-                pgs::LastInSequenceFlag::Last => {
-                    if obj.finished {
-                        tracing::error!(
-                            "received ODS with flag LAST but object was already finished"
-                        );
-                        return Err(eyre!("invalid ods segment"));
-                    }
-                    obj.finished = true;
-                    obj.data.extend(ods.data);
-                    obj.bitmap = bitmap_from_object_and_palette(obj, palette)?;
-                }
+        let options = sup_to_srt::vobsub::VobSubOptions {
+            standard: args.dvd_standard.into(),
+            language: sup_to_srt::vobsub::vobsub_language(&args.language),
+        };
+        export_vobsub(input, extract_options, &path, options, output_options)?;
+    } else {
+        // opened before doing any work, like the output, so a missing file is reported right away
+        let source = match format {
+            FormatArg::Mkv => {
+                let Some(source) = input_path else {
+                    return Err(color_eyre::eyre::eyre!(
+                        "Matroska output needs a Matroska input file to copy"
+                    ));
+                };
+                let file = std::fs::File::open(&source)
+                    .with_context(|| format!("opening {}", source.display()))?;
+                Some(file)
             }
-        }
-
-        for comp in ds.pcs.composition_objects {
-            let object = match objects.get(&comp.object_id) {
-                Some(object) => object,
-                None => {
-                    tracing::warn!(
-                        "invalid object id in composition object: {}",
-                        comp.object_id
-                    );
-                    continue;
-                }
-            };
-
-            if !object.finished {
-                tracing::warn!(
-                    "unfinished object in composition object: {}",
-                    comp.object_id
-                );
-                continue;
+            _ => None,
+        };
+        let mut output = output::Output::open(&output_target, output_options)?;
+        let converter = Converter::builder()
+            .language(args.language.clone())
+            .palette_updates(extract_options.palette_updates)
+            .composition(extract_options.composition)
+            .rebase(extract_options.rebase)
+            .decode_mode(extract_options.mode)
+            .forced(extract_options.forced)
+            .rendering(extract_options.rendering)
+            .ocr_backend(ocr_backend)
+            .preprocess(preprocess)
+            .segment_lines(args.segment_lines)
+            .italics(args.italics)
+            .build();
+        let mut writer: Box<dyn sup_to_srt::SubtitleWriter> = match source {
+            Some(source) => {
+                let options = sup_to_srt::mkv::MuxOptions {
+                    codec: args.text_codec.into(),
+                    language: sup_to_srt::mkv::matroska_language(&args.language),
+                    name: None,
+                };
+                Box::new(sup_to_srt::mkv::MatroskaTextWriter::new(
+                    source,
+                    &mut output,
+                    options,
+                ))
             }
+            None => sup_to_srt::Format::from(format).writer(&mut output),
+        };
 
-            let bitmap = if let Some(cropping) = comp.cropping {
-                let image = object.bitmap.sub_image(
-                    u32::from(cropping.horizontal_position),
-                    u32::from(cropping.vertical_position),
-                    u32::from(cropping.width),
-                    u32::from(cropping.height),
-                );
-                image
-            } else {
-                object.bitmap.clone()
-            };
-
-            previous_subtitles.push(subtitles.len());
-            subtitles.push(BitmapSubtitle {
-                range: TimeRange::new(current_time, Default::default()),
-                bitmap,
-            });
+        tracing::info!("converting subtitles");
+        match review.as_mut() {
+            Some(review) => converter.convert_with_review(input, writer.as_mut(), review),
+            None => converter.convert_to_writer(input, writer.as_mut()),
         }
+        .context("converting subtitles")?;
+        drop(writer);
+        tracing::info!("conversion complete");
+
+        output.commit()?;
+    }
+
+    if let (Some(review), Some((target, mut output))) = (review, review_output) {
+        let (format, name) = match &target {
+            output::OutputTarget::File(path) => (
+                sup_to_srt::review::ReportFormat::from_path(path),
+                path.display().to_string(),
+            ),
+            output::OutputTarget::Stdout => (Default::default(), "stdout".to_string()),
+        };
+        review
+            .write(&mut output, format)
+            .with_context(|| format!("writing review report to {name}"))?;
+        output.commit()?;
+        tracing::info!(
+            "{} subtitles to review listed in {name}",
+            review.entries().len()
+        );
     }
 
-    Ok(subtitles)
+    Ok(())
 }
 
-fn subtitles_ocr(subtitles: Vec<BitmapSubtitle>, language: &str) -> Result<Vec<TextSubtitle>> {
-    let mut text_subtitles = Vec::with_capacity(subtitles.len());
-    let (ocr_in_sender, ocr_in_receiver) = crossbeam::channel::unbounded::<BitmapSubtitle>();
-    let (ocr_out_sender, ocr_out_receiver) = crossbeam::channel::unbounded::<TextSubtitle>();
+fn open_reader(path: Option<PathBuf>) -> Result<Box<dyn BufRead + Send>> {
+    match path {
+        Some(path) if path.as_os_str() != "-" => {
+            tracing::info!("reading from {}", path.display());
+            let file = std::fs::File::open(&path).context("opening input file")?;
+            Ok(Box::new(BufReader::new(file)))
+        }
+        _ => {
+            tracing::info!("reading from stdin");
+            Ok(Box::new(BufReader::new(std::io::stdin())))
+        }
+    }
+}
 
-    for subtitle in subtitles {
-        ocr_in_sender.send(subtitle).unwrap();
+/// open the input as a pgs stream, demuxing the pgs track of Matroska files and transport
+/// streams.
+fn open_input(path: Option<PathBuf>, track: Option<u64>) -> Result<Box<dyn Read + Send>> {
+    let mut reader = open_reader(path)?;
+    let start = reader.fill_buf().context("reading input")?;
+    if sup_to_srt::mkv::is_matroska(start) {
+        let reader = sup_to_srt::mkv::PgsTrackReader::new(reader, track)
+            .context("reading Matroska input")?;
+        tracing::info!("reading Matroska track {}", reader.track());
+        Ok(Box::new(reader))
+    } else if sup_to_srt::ts::packet_size(start).is_some() {
+        let pid = track
+            .map(u16::try_from)
+            .transpose()
+            .context("invalid transport stream pid")?;
+        let reader = sup_to_srt::ts::PgsStreamReader::new(reader, pid)
+            .context("reading transport stream input")?;
+        tracing::info!("reading transport stream pid {:#x}", reader.pid());
+        Ok(Box::new(reader))
+    } else {
+        Ok(reader)
     }
-    drop(ocr_in_sender);
+}
 
-    tracing::info!("starting ocr");
-    std::thread::scope(|scope| -> Result<()> {
-        let mut handles = Vec::new();
-        for _ in 0..std::thread::available_parallelism()
-            .map(|v| usize::from(v))
-            .unwrap_or(4)
-        {
-            let handle = scope.spawn(|| -> Result<()> {
-                let mut tesseract = tesseract::Tesseract::new(None, Some(language))
-                    .context("initializing tesseract")?;
-                while let Ok(subtitle) = ocr_in_receiver.recv() {
-                    let image = &subtitle.bitmap;
-                    tesseract = tesseract
-                        .set_frame(
-                            &image.pixels,
-                            image.width as i32,
-                            image.height as i32,
-                            4,
-                            image.width as i32 * 4,
-                        )
-                        .context("setting tesseract frame")?;
-                    tesseract = tesseract.recognize().context("tesseract recognize")?;
-                    let text = tesseract.get_text().context("tesseract get text")?;
-                    ocr_out_sender
-                        .send(TextSubtitle {
-                            range: subtitle.range,
-                            text,
-                        })
-                        .unwrap();
-                }
-                Ok(())
-            });
-            handles.push(handle);
+fn tracks(mut input: Box<dyn BufRead + Send>) -> Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    if sup_to_srt::ts::packet_size(input.fill_buf().context("reading input")?).is_some() {
+        let streams = sup_to_srt::ts::streams(input).context("reading transport stream input")?;
+        for stream in streams.iter() {
+            writeln!(stdout, "{stream}")?;
         }
-        for handle in handles {
-            handle.join().unwrap()?;
-        }
-        Ok(())
-    })?;
+        return Ok(());
+    }
+    let tracks = sup_to_srt::mkv::tracks(input).context("reading Matroska input")?;
+    for track in tracks.iter() {
+        writeln!(stdout, "{track}")?;
+    }
+    Ok(())
+}
 
-    drop(ocr_out_sender);
-    while let Ok(text_subtitle) = ocr_out_receiver.recv() {
-        text_subtitles.push(text_subtitle);
+fn decode_mode(resilient: bool) -> sup_to_srt::pgs::DecodeMode {
+    match resilient {
+        true => sup_to_srt::pgs::DecodeMode::Resilient,
+        false => sup_to_srt::pgs::DecodeMode::Strict,
     }
-    Ok(text_subtitles)
 }
 
-fn srt_duration_display(duration: Duration) -> impl std::fmt::Display {
-    struct SrtDurationDisplay(Duration);
+fn info(
+    input: Box<dyn Read + Send>,
+    dump: Option<DumpFormatArg>,
+    mode: sup_to_srt::pgs::DecodeMode,
+) -> Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    let Some(dump) = dump else {
+        let options = sup_to_srt::ExtractOptions {
+            mode,
+            ..Default::default()
+        };
+        let display_sets = sup_to_srt::display_set_reader(input, &options);
+        let info = sup_to_srt::info::stream_info(display_sets).context("reading input")?;
+        write!(stdout, "{info}")?;
+        return Ok(());
+    };
 
-    impl std::fmt::Display for SrtDurationDisplay {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            let total_secs = self.0.as_secs();
-            let hours = total_secs / 3600;
-            let minutes = (total_secs / 60) % 60;
-            let seconds = total_secs % 60;
-            let millis = self.0.subsec_millis();
-            write!(f, "{hours:02}:{minutes:02}:{seconds:02},{millis:03}")
+    let segments = sup_to_srt::pgs::SegmentReader::new(input)
+        .mode(mode)
+        .on_warning(|warning| {
+            tracing::warn!(
+                offset = warning.offset,
+                skipped = warning.skipped,
+                reason = %warning.reason,
+                "skipping invalid data"
+            );
+        });
+    let records = sup_to_srt::info::segment_records(segments).context("reading input")?;
+    match dump {
+        DumpFormatArg::Json => {
+            serde_json::to_writer_pretty(&mut stdout, &records).context("writing dump")?;
+            writeln!(stdout)?;
+        }
+        DumpFormatArg::Yaml => {
+            serde_yaml::to_writer(&mut stdout, &records).context("writing dump")?;
         }
     }
-
-    SrtDurationDisplay(duration)
+    Ok(())
 }
 
-fn subtitles_to_srt(subtitles: Vec<TextSubtitle>) -> String {
-    use std::fmt::Write;
+fn validate(input: Box<dyn Read + Send>, json: bool) -> Result<()> {
+    use sup_to_srt::validate::Severity;
 
-    #[derive(Debug, PartialEq, Eq)]
-    enum ActionKind {
-        Add,
-        Remove,
+    let findings = sup_to_srt::validate::validate(input).context("validating input")?;
+    let mut stdout = std::io::stdout().lock();
+    if json {
+        serde_json::to_writer_pretty(&mut stdout, &findings).context("writing findings")?;
+        writeln!(stdout)?;
+    } else {
+        for finding in findings.iter() {
+            writeln!(stdout, "{finding}")?;
+        }
     }
 
-    #[derive(Debug, PartialEq, Eq)]
-    struct Action {
-        kind: ActionKind,
-        subtitle: usize,
-        timestamp: Duration,
+    let count = |severity| {
+        findings
+            .iter()
+            .filter(|finding| finding.severity == severity)
+            .count()
+    };
+    let (errors, warnings) = (count(Severity::Error), count(Severity::Warning));
+    if !json {
+        writeln!(stdout, "{errors} errors, {warnings} warnings")?;
     }
-
-    impl std::cmp::PartialOrd for Action {
-        fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-            Some(self.cmp(other))
-        }
+    if errors > 0 {
+        return Err(color_eyre::eyre::eyre!("the stream has {errors} errors"));
     }
+    Ok(())
+}
 
-    impl std::cmp::Ord for Action {
-        fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-            self.timestamp.cmp(&other.timestamp)
-        }
+fn export_images(
+    input: Box<dyn Read + Send>,
+    extract_options: sup_to_srt::ExtractOptions,
+    dir: PathBuf,
+    options: sup_to_srt::bdn::BdnOptions,
+    output_options: output::OutputOptions,
+) -> Result<()> {
+    let index = dir.join(sup_to_srt::bdn::BDN_FILE_NAME);
+    if index.exists() && !output_options.force {
+        return Err(color_eyre::eyre::eyre!(
+            "{} already exists, use --force to overwrite it",
+            index.display()
+        ));
     }
+    check_no_images(&dir, output_options.force)?;
+    std::fs::create_dir_all(&dir).context("creating export directory")?;
+
+    tracing::info!("exporting subtitle images to {}", dir.display());
+    let display_sets = sup_to_srt::display_set_reader(input, &extract_options);
+    let mut exporter =
+        sup_to_srt::bdn::BdnExporter::new(dir, options).file_writer(file_writer(output_options));
+    for subtitle in sup_to_srt::subtitles_extract_with_options(display_sets, extract_options) {
+        let subtitle = subtitle.context("extracting bitmap subtitles")?;
+        exporter
+            .push(&subtitle)
+            .context("exporting subtitle image")?;
+    }
+    exporter.finish().context("writing BDN XML")?;
+    tracing::info!("export complete");
+    Ok(())
+}
 
-    // index of subtitles that are currently in display
-    let mut on_screen: Vec<usize> = Default::default();
-    let mut on_screen_text = String::default();
-    let mut actions: Vec<Action> = Default::default();
-    let mut srt = String::default();
-    let mut current_sub_num = 1;
+/// writes the files produced next to the output through [`output::Output`], so they are written
+/// atomically and only replace existing files with --force.
+fn file_writer(options: output::OutputOptions) -> sup_to_srt::FileWriter {
+    sup_to_srt::FileWriter::new(move |path, data| {
+        let error = |err: color_eyre::Report| std::io::Error::other(format!("{err:#}"));
+        let target = output::OutputTarget::File(path.to_path_buf());
+        let mut output = output::Output::open(&target, options).map_err(error)?;
+        output.write_all(data)?;
+        output.commit().map_err(error)
+    })
+}
 
-    for (idx, subtitle) in subtitles.iter().enumerate() {
-        actions.push(Action {
-            kind: ActionKind::Add,
-            subtitle: idx,
-            timestamp: subtitle.range.begin,
-        });
-        actions.push(Action {
-            kind: ActionKind::Remove,
-            subtitle: idx,
-            timestamp: subtitle.range.end,
-        });
+/// fail if the directory already holds numbered images, unless forced, so the images of two
+/// runs are never mixed.
+fn check_no_images(dir: &std::path::Path, force: bool) -> Result<()> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Ok(());
+    };
+    if force {
+        return Ok(());
     }
-    actions.sort();
-
-    for (action_idx, action) in actions.iter().enumerate() {
-        match action.kind {
-            ActionKind::Add => on_screen.push(action.subtitle),
-            ActionKind::Remove => on_screen.retain(|&x| x != action.subtitle),
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name
+            .to_str()
+            .is_some_and(sup_to_srt::bdn::is_image_file_name)
+        {
+            return Err(color_eyre::eyre::eyre!(
+                "{} already exists, use --force to overwrite it",
+                entry.path().display()
+            ));
         }
+    }
+    Ok(())
+}
 
-        on_screen_text.clear();
-        for &idx in on_screen.iter() {
-            on_screen_text.push_str(&subtitles[idx].text);
-            on_screen_text.push('\n');
-        }
-        let on_screen_text = on_screen_text.trim();
-
-        if !on_screen_text.is_empty() {
-            let timestamp_begin = action.timestamp;
-            let timestamp_end = match actions.get(action_idx + 1) {
-                Some(action) => action.timestamp,
-                None => Duration::MAX,
-            };
-
-            let _ = writeln!(srt, "{current_sub_num}");
-            let _ = writeln!(
-                srt,
-                "{} --> {}",
-                srt_duration_display(timestamp_begin),
-                srt_duration_display(timestamp_end),
-            );
-            srt.push_str(&on_screen_text);
-            srt.push_str("\n\n");
-            current_sub_num += 1;
-        }
+fn export_vobsub(
+    input: Box<dyn Read + Send>,
+    extract_options: sup_to_srt::ExtractOptions,
+    path: &std::path::Path,
+    options: sup_to_srt::vobsub::VobSubOptions,
+    output_options: output::OutputOptions,
+) -> Result<()> {
+    let sub_target = output::OutputTarget::File(path.with_extension("sub"));
+    let idx_target = output::OutputTarget::File(path.with_extension("idx"));
+    let mut sub = output::Output::open(&sub_target, output_options)?;
+    let mut idx = output::Output::open(&idx_target, output_options)?;
+
+    tracing::info!("converting subtitles to vobsub");
+    let display_sets = sup_to_srt::display_set_reader(input, &extract_options);
+    let mut writer = sup_to_srt::vobsub::VobSubWriter::new(&mut sub, &mut idx, options);
+    for subtitle in sup_to_srt::subtitles_extract_with_options(display_sets, extract_options) {
+        let subtitle = subtitle.context("extracting bitmap subtitles")?;
+        writer.push(&subtitle).context("writing vobsub subtitle")?;
     }
+    writer.finish().context("writing vobsub index")?;
+    tracing::info!("conversion complete");
 
-    srt
+    sub.commit()?;
+    idx.commit()?;
+    Ok(())
 }
 
 #[cfg(feature = "viewer")]
-fn subtitles_viewer(subtitles: Vec<BitmapSubtitle>) -> Result<()> {
+fn subtitles_viewer(subtitles: Vec<sup_to_srt::BitmapSubtitle>) -> Result<()> {
     let mut window = minifb::Window::new(
         "sup2srt",
         1200,
@@ -472,7 +881,7 @@ fn subtitles_viewer(subtitles: Vec<BitmapSubtitle>) -> Result<()> {
         for i in 0..bitmap.pixels.len() / 4 {
             let v = ((bitmap.pixels[i * 4] as u32) << 16)
                 | ((bitmap.pixels[i * 4 + 1] as u32) << 8)
-                | ((bitmap.pixels[i * 4 + 2] as u32) << 0);
+                | (bitmap.pixels[i * 4 + 2] as u32);
             buffer.push(v);
         }
         window
@@ -482,18 +891,3 @@ fn subtitles_viewer(subtitles: Vec<BitmapSubtitle>) -> Result<()> {
 
     Ok(())
 }
-
-#[cfg(test)]
-mod test {
-    use super::*;
-
-    const PGS: &'static [u8] = include_bytes!("../subtitle.sup");
-
-    #[test]
-    fn test_subtitles_to_srt() {
-        let bitmap_subtitles = subtitles_extract(PGS).unwrap();
-        let text_subtitles = subtitles_ocr(bitmap_subtitles, "eng").unwrap();
-        let srt = subtitles_to_srt(text_subtitles);
-        insta::assert_snapshot!(srt);
-    }
-}
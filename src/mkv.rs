@@ -0,0 +1,1376 @@
+//! Read pgs subtitle tracks directly from Matroska (`.mkv`, `.mks`) files, and add text tracks
+//! to them.
+//!
+//! Matroska stores the segments of a display set without the `PG` header of `.sup` files, the
+//! timestamps come from the blocks instead. [`PgsTrackReader`] adds the headers back so the
+//! track can be decoded like a `.sup` file.
+use std::{
+    collections::HashMap,
+    io::{Read, Seek, SeekFrom, Write},
+};
+
+use crate::{
+    cue::{Cue, CueMerger},
+    Error, Result, SubtitleWriter, TextSubtitle,
+};
+
+/// codec id of pgs subtitle tracks.
+pub const PGS_CODEC_ID: &str = "S_HDMV/PGS";
+
+const EBML_MAGIC: [u8; 4] = [0x1A, 0x45, 0xDF, 0xA3];
+
+const ID_EBML: u32 = 0x1A45DFA3;
+const ID_VOID: u32 = 0xEC;
+const ID_CRC32: u32 = 0xBF;
+const ID_DOC_TYPE: u32 = 0x4282;
+const ID_SEGMENT: u32 = 0x18538067;
+const ID_SEEK_HEAD: u32 = 0x114D9B74;
+const ID_SEEK: u32 = 0x4DBB;
+const ID_SEEK_POSITION: u32 = 0x53AC;
+const ID_INFO: u32 = 0x1549A966;
+const ID_TIMESTAMP_SCALE: u32 = 0x2AD7B1;
+const ID_TRACKS: u32 = 0x1654AE6B;
+const ID_TRACK_ENTRY: u32 = 0xAE;
+const ID_TRACK_NUMBER: u32 = 0xD7;
+const ID_TRACK_UID: u32 = 0x73C5;
+const ID_TRACK_TYPE: u32 = 0x83;
+const ID_CODEC_ID: u32 = 0x86;
+const ID_CODEC_PRIVATE: u32 = 0x63A2;
+const ID_NAME: u32 = 0x536E;
+const ID_LANGUAGE: u32 = 0x22B59C;
+const ID_LANGUAGE_BCP47: u32 = 0x22B59D;
+const ID_FLAG_DEFAULT: u32 = 0x88;
+const ID_FLAG_FORCED: u32 = 0x55AA;
+const ID_FLAG_LACING: u32 = 0x9C;
+const ID_CONTENT_ENCODINGS: u32 = 0x6D80;
+const ID_CONTENT_ENCODING: u32 = 0x6240;
+const ID_CONTENT_ENCODING_ORDER: u32 = 0x5031;
+const ID_CONTENT_ENCODING_SCOPE: u32 = 0x5032;
+const ID_CONTENT_ENCODING_TYPE: u32 = 0x5033;
+const ID_CONTENT_COMPRESSION: u32 = 0x5034;
+const ID_CONTENT_COMP_ALGO: u32 = 0x4254;
+const ID_CONTENT_COMP_SETTINGS: u32 = 0x4255;
+const ID_CLUSTER: u32 = 0x1F43B675;
+const ID_CLUSTER_TIMESTAMP: u32 = 0xE7;
+const ID_BLOCK_GROUP: u32 = 0xA0;
+const ID_BLOCK: u32 = 0xA1;
+const ID_SIMPLE_BLOCK: u32 = 0xA3;
+const ID_BLOCK_DURATION: u32 = 0x9B;
+const ID_BLOCK_ADDITIONS: u32 = 0x75A1;
+const ID_BLOCK_MORE: u32 = 0xA6;
+const ID_BLOCK_ADD_ID: u32 = 0xEE;
+const ID_BLOCK_ADDITIONAL: u32 = 0xA5;
+const ID_CUES: u32 = 0x1C53BB6B;
+const ID_CUE_POINT: u32 = 0xBB;
+const ID_CUE_TRACK_POSITIONS: u32 = 0xB7;
+const ID_CUE_CLUSTER_POSITION: u32 = 0xF1;
+const ID_CUE_RELATIVE_POSITION: u32 = 0xF0;
+
+const TRACK_TYPE_SUBTITLE: u64 = 0x11;
+
+/// nanoseconds per timestamp unit when the segment info does not say otherwise.
+const DEFAULT_TIMESTAMP_SCALE: u64 = 1_000_000;
+
+/// whether the data starts like a Matroska file.
+pub fn is_matroska(data: &[u8]) -> bool {
+    data.starts_with(&EBML_MAGIC)
+}
+
+/// How the blocks of a track are compressed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Compression {
+    Zlib,
+    /// the bytes are stripped from the start of every block.
+    HeaderStripping(Vec<u8>),
+    /// an algorithm that is not supported, like bzlib or lzo.
+    Unsupported(u64),
+}
+
+impl Compression {
+    fn decompress(&self, data: Vec<u8>) -> std::io::Result<Vec<u8>> {
+        match self {
+            Compression::Zlib => {
+                let mut decompressed = Vec::with_capacity(data.len() * 2);
+                flate2::read::ZlibDecoder::new(data.as_slice()).read_to_end(&mut decompressed)?;
+                Ok(decompressed)
+            }
+            Compression::HeaderStripping(header) => {
+                let mut decompressed = header.clone();
+                decompressed.extend(data);
+                Ok(decompressed)
+            }
+            Compression::Unsupported(algo) => Err(invalid_data(format!(
+                "unsupported content compression algorithm {algo}"
+            ))),
+        }
+    }
+}
+
+/// A track of a Matroska file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Track {
+    pub number: u64,
+    pub track_type: u64,
+    pub codec_id: String,
+    /// BCP 47 tag when present, the ISO 639-2 code otherwise.
+    pub language: String,
+    pub name: Option<String>,
+    pub default: bool,
+    pub forced: bool,
+    /// compressions applied to the blocks, in the order they have to be undone.
+    pub compression: Vec<Compression>,
+}
+
+impl Track {
+    pub fn is_pgs(&self) -> bool {
+        self.codec_id == PGS_CODEC_ID
+    }
+}
+
+impl std::fmt::Display for Track {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {} [{}]", self.number, self.codec_id, self.language)?;
+        if let Some(name) = &self.name {
+            write!(f, " \"{name}\"")?;
+        }
+        match (self.default, self.forced) {
+            (true, true) => write!(f, " (default, forced)"),
+            (true, false) => write!(f, " (default)"),
+            (false, true) => write!(f, " (forced)"),
+            (false, false) => Ok(()),
+        }
+    }
+}
+
+/// A block of a track, with its content decompressed.
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub track: u64,
+    /// in nanoseconds.
+    pub timestamp: i64,
+    pub data: Vec<u8>,
+}
+
+/// Reads the tracks and blocks of a Matroska file.
+///
+/// Only reads forward so the file can come from a pipe. Clusters of unknown size, as written
+/// by live muxers, are supported since the elements inside clusters are read as a flat list.
+#[derive(Debug)]
+pub struct Demuxer<R> {
+    reader: R,
+    tracks: Vec<Track>,
+    timestamp_scale: u64,
+    cluster_timestamp: u64,
+}
+
+impl<R: Read> Demuxer<R> {
+    /// read the headers of the file, up to and including the track list.
+    pub fn new(mut reader: R) -> std::io::Result<Self> {
+        let header = read_element_header(&mut reader)?;
+        let Some((ID_EBML, Some(size))) = header else {
+            return Err(invalid_data("not a Matroska file"));
+        };
+        let ebml = read_payload(&mut reader, size)?;
+        for (id, data) in parse_elements(&ebml)? {
+            if id == ID_DOC_TYPE {
+                let doc_type = read_string(data);
+                if doc_type != "matroska" && doc_type != "webm" {
+                    return Err(invalid_data(format!("unsupported doc type '{doc_type}'")));
+                }
+            }
+        }
+
+        let mut demuxer = Self {
+            reader,
+            tracks: Vec::new(),
+            timestamp_scale: DEFAULT_TIMESTAMP_SCALE,
+            cluster_timestamp: 0,
+        };
+        loop {
+            let Some((id, size)) = read_element_header(&mut demuxer.reader)? else {
+                return Err(invalid_data("no tracks in the Matroska file"));
+            };
+            match id {
+                ID_SEGMENT => {}
+                ID_INFO => {
+                    let info = read_payload(&mut demuxer.reader, known_size(id, size)?)?;
+                    for (id, data) in parse_elements(&info)? {
+                        if id == ID_TIMESTAMP_SCALE {
+                            demuxer.timestamp_scale = read_uint(data)?;
+                        }
+                    }
+                }
+                ID_TRACKS => {
+                    let tracks = read_payload(&mut demuxer.reader, known_size(id, size)?)?;
+                    demuxer.tracks = parse_tracks(&tracks)?;
+                    return Ok(demuxer);
+                }
+                ID_CLUSTER => return Err(invalid_data("no tracks before the first cluster")),
+                _ => skip(&mut demuxer.reader, known_size(id, size)?)?,
+            }
+        }
+    }
+
+    pub fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    /// the next block of any track, `None` at the end of the file.
+    pub fn next_block(&mut self) -> std::io::Result<Option<Block>> {
+        loop {
+            let Some((id, size)) = read_element_header(&mut self.reader)? else {
+                return Ok(None);
+            };
+            match id {
+                // the children of these are read as if they were top level elements
+                ID_SEGMENT | ID_CLUSTER | ID_BLOCK_GROUP => {}
+                ID_CLUSTER_TIMESTAMP => {
+                    let data = read_payload(&mut self.reader, known_size(id, size)?)?;
+                    self.cluster_timestamp = read_uint(&data)?;
+                }
+                ID_SIMPLE_BLOCK | ID_BLOCK => {
+                    let data = read_payload(&mut self.reader, known_size(id, size)?)?;
+                    return self.parse_block(&data).map(Some);
+                }
+                _ => skip(&mut self.reader, known_size(id, size)?)?,
+            }
+        }
+    }
+
+    fn parse_block(&self, mut data: &[u8]) -> std::io::Result<Block> {
+        let (track, _) = read_vint(&mut data, false)?.ok_or_else(truncated)?;
+        let [t0, t1, flags, ..] = *data else {
+            return Err(truncated());
+        };
+        data = &data[3..];
+        if flags & 0x06 != 0 {
+            return Err(invalid_data(format!("laced block in track {track}")));
+        }
+
+        let relative = i64::from(i16::from_be_bytes([t0, t1]));
+        let timestamp = (self.cluster_timestamp as i64 + relative) * self.timestamp_scale as i64;
+        let mut data = data.to_vec();
+        if let Some(track) = self.tracks.iter().find(|t| t.number == track) {
+            for compression in track.compression.iter() {
+                data = compression.decompress(data)?;
+            }
+        }
+        Ok(Block {
+            track,
+            timestamp,
+            data,
+        })
+    }
+}
+
+/// the tracks of a Matroska file.
+pub fn tracks<R: Read>(reader: R) -> Result<Vec<Track>> {
+    let demuxer = Demuxer::new(reader).map_err(Error::from_decode)?;
+    Ok(demuxer.tracks)
+}
+
+/// Reads a pgs track of a Matroska file as a `.sup` stream.
+#[derive(Debug)]
+pub struct PgsTrackReader<R> {
+    demuxer: Demuxer<R>,
+    track: u64,
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read> PgsTrackReader<R> {
+    /// read the given track, or the first pgs track if not specified.
+    pub fn new(reader: R, track: Option<u64>) -> Result<Self> {
+        let demuxer = Demuxer::new(reader).map_err(Error::from_decode)?;
+        let tracks = demuxer.tracks();
+        let track = match track {
+            Some(number) => {
+                let found = tracks
+                    .iter()
+                    .find(|t| t.number == number)
+                    .ok_or_else(|| Error::InvalidStream(format!("no track {number}")))?;
+                if !found.is_pgs() {
+                    return Err(Error::InvalidStream(format!(
+                        "track {number} is {}, not {PGS_CODEC_ID}",
+                        found.codec_id
+                    )));
+                }
+                number
+            }
+            None => {
+                tracks
+                    .iter()
+                    .find(|t| t.is_pgs())
+                    .ok_or_else(|| Error::InvalidStream(format!("no {PGS_CODEC_ID} track")))?
+                    .number
+            }
+        };
+        Ok(Self {
+            demuxer,
+            track,
+            buffer: Vec::new(),
+            position: 0,
+        })
+    }
+
+    pub fn track(&self) -> u64 {
+        self.track
+    }
+
+    /// turn the segments of a block into sup segments timestamped with the block time.
+    fn fill_buffer(&mut self, block: Block) -> std::io::Result<()> {
+        let pts = pgs::duration_to_clock(std::time::Duration::from_nanos(
+            block.timestamp.max(0) as u64
+        ));
+        self.buffer.clear();
+        self.position = 0;
+        pgs::write_sup_segments(&mut self.buffer, &block.data, pts, 0)
+    }
+}
+
+impl<R: Read> Read for PgsTrackReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.position == self.buffer.len() {
+            match self.demuxer.next_block()? {
+                Some(block) if block.track == self.track => self.fill_buffer(block)?,
+                Some(_) => {}
+                None => return Ok(0),
+            }
+        }
+        let available = &self.buffer[self.position..];
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+/// Codec of the text track added by [`mux_text_track`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TextCodec {
+    /// `S_TEXT/UTF8`, the text of srt cues.
+    #[default]
+    Srt,
+    /// `S_TEXT/WEBVTT`, keeping the cue settings that place the text.
+    WebVtt,
+}
+
+impl TextCodec {
+    pub fn codec_id(&self) -> &'static str {
+        match self {
+            TextCodec::Srt => "S_TEXT/UTF8",
+            TextCodec::WebVtt => "S_TEXT/WEBVTT",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MuxOptions {
+    pub codec: TextCodec,
+    /// ISO 639-2 language code of the track.
+    pub language: String,
+    pub name: Option<String>,
+}
+
+impl Default for MuxOptions {
+    fn default() -> Self {
+        Self {
+            codec: Default::default(),
+            language: "eng".to_string(),
+            name: None,
+        }
+    }
+}
+
+/// the ISO 639-2/B code used by Matroska for a tesseract language code.
+///
+/// tesseract uses the terminology codes, which only differ from the bibliographic codes for a
+/// few languages, and suffixes for scripts, like `chi_sim`.
+pub fn matroska_language(code: &str) -> String {
+    let language = code.split('_').next().unwrap_or(code);
+    let bibliographic = match language {
+        "bod" => "tib",
+        "ces" => "cze",
+        "cym" => "wel",
+        "deu" => "ger",
+        "ell" => "gre",
+        "eus" => "baq",
+        "fas" => "per",
+        "fra" => "fre",
+        "hye" => "arm",
+        "isl" => "ice",
+        "kat" => "geo",
+        "mkd" => "mac",
+        "msa" => "may",
+        "mya" => "bur",
+        "nld" => "dut",
+        "ron" => "rum",
+        "slk" => "slo",
+        "sqi" => "alb",
+        language => language,
+    };
+    bibliographic.to_string()
+}
+
+/// Collects text subtitles and, once finished, writes a copy of a Matroska file with the
+/// subtitles added as a new text track.
+pub struct MatroskaTextWriter<R, W> {
+    source: R,
+    writer: W,
+    options: MuxOptions,
+    merger: CueMerger,
+    cues: Vec<Cue>,
+}
+
+impl<R: Read + Seek, W: Write> MatroskaTextWriter<R, W> {
+    pub fn new(source: R, writer: W, options: MuxOptions) -> Self {
+        Self {
+            source,
+            writer,
+            options,
+            merger: Default::default(),
+            cues: Vec::new(),
+        }
+    }
+}
+
+impl<R: Read + Seek, W: Write> SubtitleWriter for MatroskaTextWriter<R, W> {
+    fn push(&mut self, subtitle: TextSubtitle) -> std::io::Result<()> {
+        self.cues.extend(self.merger.push(subtitle));
+        Ok(())
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.cues.extend(self.merger.finish());
+        mux_text_track(
+            &mut self.source,
+            &mut self.writer,
+            &self.cues,
+            &self.options,
+        )?;
+        self.writer.flush()
+    }
+}
+
+/// A top level element of the source segment.
+#[derive(Debug, Clone, Copy)]
+struct SourceElement {
+    id: u32,
+    /// absolute offset of the element header
+    header_offset: u64,
+    /// absolute offset of the payload
+    offset: u64,
+    size: u64,
+}
+
+/// An element of the output segment.
+enum OutputElement {
+    /// copied as is from the source
+    Copy(SourceElement),
+    /// a source cluster with blocks inserted at the given offsets of its payload
+    Cluster(SourceElement, Vec<(u64, Vec<u8>)>),
+    /// a source seek head or cues, with the payload read from the source, whose positions are
+    /// updated to the output layout
+    Positions(SourceElement, Vec<u8>),
+    /// a source element replaced by a new payload
+    Replace(SourceElement, Vec<u8>),
+    /// a new element with its payload
+    New(u32, Vec<u8>),
+}
+
+impl OutputElement {
+    fn source(&self) -> Option<&SourceElement> {
+        match self {
+            OutputElement::Copy(source)
+            | OutputElement::Cluster(source, _)
+            | OutputElement::Positions(source, _)
+            | OutputElement::Replace(source, _) => Some(source),
+            OutputElement::New(..) => None,
+        }
+    }
+
+    fn size(&self) -> std::io::Result<u64> {
+        let (id, payload) = match self {
+            OutputElement::Copy(source) => {
+                return Ok(source.offset - source.header_offset + source.size);
+            }
+            OutputElement::Cluster(source, blocks) => (
+                source.id,
+                source.size
+                    + blocks
+                        .iter()
+                        .map(|(_, block)| block.len() as u64)
+                        .sum::<u64>(),
+            ),
+            // positions have a fixed size so the layout does not depend on them
+            OutputElement::Positions(source, payload) => (
+                source.id,
+                rewrite_positions(payload, source.id, &|position| position, &|_, relative| {
+                    relative
+                })?
+                .len() as u64,
+            ),
+            OutputElement::Replace(source, payload) => (source.id, payload.len() as u64),
+            OutputElement::New(id, payload) => (*id, payload.len() as u64),
+        };
+        Ok(element_header(id, payload).len() as u64 + payload)
+    }
+}
+
+/// write a copy of the Matroska file `source` to `writer` with the cues added as a new text
+/// track.
+///
+/// The source is copied as is except for the track list, which gets the new track, and the
+/// seek head and cues, whose positions are updated. The blocks of the new track are inserted
+/// in the cluster they fall in, before the first block with a later timestamp, so the blocks of
+/// every cluster stay in timestamp order.
+pub fn mux_text_track<R, W>(
+    mut source: R,
+    mut writer: W,
+    cues: &[Cue],
+    options: &MuxOptions,
+) -> std::io::Result<()>
+where
+    R: Read + Seek,
+    W: Write,
+{
+    source.seek(SeekFrom::Start(0))?;
+    let Some((ID_EBML, Some(ebml_size))) = read_element_header(&mut source)? else {
+        return Err(invalid_data("not a Matroska file"));
+    };
+    skip(&mut source, ebml_size)?;
+    let ebml_end = source.stream_position()?;
+    let Some((ID_SEGMENT, segment_size)) = read_element_header(&mut source)? else {
+        return Err(invalid_data("no segment in the Matroska file"));
+    };
+    let segment_start = source.stream_position()?;
+    let segment_end = match segment_size {
+        Some(size) => segment_start + size,
+        None => source.seek(SeekFrom::End(0))?,
+    };
+
+    // the top level elements of the segment
+    source.seek(SeekFrom::Start(segment_start))?;
+    let mut elements = Vec::new();
+    while source.stream_position()? < segment_end {
+        let header_offset = source.stream_position()?;
+        let Some((id, size)) = read_element_header(&mut source)? else {
+            break;
+        };
+        let size =
+            size.ok_or_else(|| invalid_data(format!("top level element {id:#x} of unknown size")))?;
+        let offset = source.stream_position()?;
+        elements.push(SourceElement {
+            id,
+            header_offset,
+            offset,
+            size,
+        });
+        source.seek(SeekFrom::Start(offset + size))?;
+    }
+
+    let read = |source: &mut R, element: &SourceElement| -> std::io::Result<Vec<u8>> {
+        source.seek(SeekFrom::Start(element.offset))?;
+        read_payload(&mut *source, element.size)
+    };
+    let mut timestamp_scale = DEFAULT_TIMESTAMP_SCALE;
+    let mut tracks = None;
+    let mut clusters = Vec::new();
+    for element in elements.iter() {
+        match element.id {
+            ID_INFO => {
+                for (id, data) in parse_elements(&read(&mut source, element)?)? {
+                    if id == ID_TIMESTAMP_SCALE {
+                        timestamp_scale = read_uint(data)?.max(1);
+                    }
+                }
+            }
+            ID_TRACKS => tracks = Some(read(&mut source, element)?),
+            ID_CLUSTER => {
+                source.seek(SeekFrom::Start(element.offset))?;
+                let mut cluster = (&mut source).take(element.size);
+                let mut timestamp = 0;
+                while let Some((id, size)) = read_element_header(&mut cluster)? {
+                    let size = known_size(id, size)?;
+                    if id == ID_CLUSTER_TIMESTAMP {
+                        timestamp = read_uint(&read_payload(&mut cluster, size)?)?;
+                        break;
+                    }
+                    skip(&mut cluster, size)?;
+                }
+                clusters.push(timestamp);
+            }
+            _ => {}
+        }
+    }
+    let tracks = tracks.ok_or_else(|| invalid_data("no tracks in the Matroska file"))?;
+
+    // the new track goes after the existing ones
+    let mut number = 0;
+    let mut uid = 0;
+    for (_, entry) in parse_elements(&tracks)?
+        .into_iter()
+        .filter(|(id, _)| *id == ID_TRACK_ENTRY)
+    {
+        for (id, data) in parse_elements(entry)? {
+            match id {
+                ID_TRACK_NUMBER => number = number.max(read_uint(data)?),
+                ID_TRACK_UID => uid = uid.max(read_uint(data)?),
+                _ => {}
+            }
+        }
+    }
+    let (number, uid) = (number + 1, uid.wrapping_add(1).max(1));
+    let mut new_tracks: Vec<u8> = parse_elements(&tracks)?
+        .into_iter()
+        .filter(|(id, _)| *id != ID_CRC32)
+        .flat_map(|(id, data)| encode_element(id, data))
+        .collect();
+    new_tracks.extend(text_track_entry(number, uid, options));
+
+    // every block goes in the last cluster starting before it, or in a cluster of its own when
+    // it is too far from the cluster timestamp
+    let mut appended: Vec<Vec<(i16, Vec<u8>)>> = vec![Vec::new(); clusters.len()];
+    let mut inserted: Vec<Vec<Vec<u8>>> = vec![Vec::new(); clusters.len() + 1];
+    let to_units = |time: std::time::Duration| {
+        let nanos = time.as_nanos() + u128::from(timestamp_scale / 2);
+        (nanos / u128::from(timestamp_scale)).min(u128::from(u64::MAX / 2)) as u64
+    };
+    for cue in cues {
+        let begin = to_units(cue.range.begin);
+        let duration = (cue.range.end != std::time::Duration::MAX)
+            .then(|| to_units(cue.range.end).saturating_sub(begin));
+        let cluster = clusters.partition_point(|timestamp| *timestamp <= begin);
+        let relative = match cluster {
+            0 => None,
+            cluster => i16::try_from(begin - clusters[cluster - 1]).ok(),
+        };
+        match relative {
+            Some(relative) => appended[cluster - 1].push((
+                relative,
+                text_block(number, relative, duration, cue, options),
+            )),
+            None => {
+                let mut payload = uint_element(ID_CLUSTER_TIMESTAMP, begin);
+                payload.extend(text_block(number, 0, duration, cue, options));
+                inserted[cluster].push(payload);
+            }
+        }
+    }
+
+    let mut output = Vec::new();
+    // bytes inserted in the clusters, by offset in their payload, to update the positions of
+    // the blocks that follow
+    let mut shifts: HashMap<u64, Vec<(u64, u64)>> = HashMap::new();
+    let mut cluster = 0;
+    for element in elements.iter() {
+        match element.id {
+            ID_TRACKS => output.push(OutputElement::Replace(*element, new_tracks.clone())),
+            ID_SEEK_HEAD | ID_CUES => {
+                let payload = read(&mut source, element)?;
+                output.push(OutputElement::Positions(*element, payload));
+            }
+            ID_CLUSTER => {
+                output.extend(
+                    inserted[cluster]
+                        .drain(..)
+                        .map(|payload| OutputElement::New(ID_CLUSTER, payload)),
+                );
+                let mut blocks = Vec::new();
+                let appended = std::mem::take(&mut appended[cluster]);
+                if !appended.is_empty() {
+                    let existing = cluster_blocks(&read(&mut source, element)?)?;
+                    for (relative, block) in appended {
+                        let offset = existing
+                            .iter()
+                            .find(|(_, timestamp)| *timestamp > relative)
+                            .map_or(element.size, |(offset, _)| *offset);
+                        blocks.push((offset, block));
+                    }
+                    // the cues are sorted, this only moves blocks placed after later ones
+                    blocks.sort_by_key(|(offset, _)| *offset);
+                    shifts.insert(
+                        element.header_offset - segment_start,
+                        blocks
+                            .iter()
+                            .map(|(offset, block)| (*offset, block.len() as u64))
+                            .collect(),
+                    );
+                }
+                output.push(OutputElement::Cluster(*element, blocks));
+                cluster += 1;
+            }
+            _ => output.push(OutputElement::Copy(*element)),
+        }
+    }
+    output.extend(
+        inserted[cluster]
+            .drain(..)
+            .map(|payload| OutputElement::New(ID_CLUSTER, payload)),
+    );
+
+    // positions are relative to the start of the segment payload
+    let mut positions = HashMap::new();
+    let mut segment_size = 0;
+    for element in output.iter() {
+        if let Some(source) = element.source() {
+            positions.insert(source.header_offset - segment_start, segment_size);
+        }
+        segment_size += element.size()?;
+    }
+    let position = |old: u64| match positions.get(&old) {
+        Some(new) => *new,
+        None => {
+            tracing::warn!("position {old} does not point to a top level element");
+            old
+        }
+    };
+    // relative positions point inside a cluster payload, they move with the blocks inserted
+    // before them
+    let relative_position = |cluster: u64, relative: u64| {
+        let inserted = shifts.get(&cluster).into_iter().flatten();
+        relative
+            + inserted
+                .filter(|(offset, _)| *offset <= relative)
+                .map(|(_, size)| size)
+                .sum::<u64>()
+    };
+
+    // the ebml header is copied as is
+    source.seek(SeekFrom::Start(0))?;
+    std::io::copy(&mut (&mut source).take(ebml_end), &mut writer)?;
+    writer.write_all(&element_header(ID_SEGMENT, segment_size))?;
+    for element in output.iter() {
+        match element {
+            OutputElement::Copy(source_element) => {
+                source.seek(SeekFrom::Start(source_element.header_offset))?;
+                let size = element.size()?;
+                std::io::copy(&mut (&mut source).take(size), &mut writer)?;
+            }
+            OutputElement::Cluster(source_element, blocks) => {
+                let mut data = read(&mut source, source_element)?;
+                // a checksum would no longer match, a void element of the same size keeps the
+                // positions of the blocks
+                if data.starts_with(&[ID_CRC32 as u8, 0x84]) && data.len() >= 6 {
+                    data[..6].copy_from_slice(&[ID_VOID as u8, 0x84, 0, 0, 0, 0]);
+                }
+                let inserted = blocks.iter().map(|(_, block)| block.len() as u64);
+                let size = source_element.size + inserted.sum::<u64>();
+                writer.write_all(&element_header(ID_CLUSTER, size))?;
+                let mut written = 0;
+                for (offset, block) in blocks {
+                    writer.write_all(&data[written..*offset as usize])?;
+                    writer.write_all(block)?;
+                    written = *offset as usize;
+                }
+                writer.write_all(&data[written..])?;
+            }
+            OutputElement::Positions(source_element, payload) => {
+                let payload =
+                    rewrite_positions(payload, source_element.id, &position, &relative_position)?;
+                writer.write_all(&encode_element(source_element.id, &payload))?;
+            }
+            OutputElement::Replace(source_element, payload) => {
+                writer.write_all(&encode_element(source_element.id, payload))?;
+            }
+            OutputElement::New(id, payload) => writer.write_all(&encode_element(*id, payload))?,
+        }
+    }
+    Ok(())
+}
+
+/// the offset in the cluster payload and the relative timestamp of every block of a cluster.
+fn cluster_blocks(data: &[u8]) -> std::io::Result<Vec<(u64, i16)>> {
+    let mut blocks = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let offset = (data.len() - rest.len()) as u64;
+        let Some((id, size)) = read_element_header(&mut rest)? else {
+            break;
+        };
+        let size = usize::try_from(known_size(id, size)?).map_err(|_| truncated())?;
+        let payload = rest.get(..size).ok_or_else(truncated)?;
+        rest = &rest[size..];
+        let block = match id {
+            ID_SIMPLE_BLOCK => payload,
+            ID_BLOCK_GROUP => match parse_elements(payload)?
+                .into_iter()
+                .find(|(id, _)| *id == ID_BLOCK)
+            {
+                Some((_, block)) => block,
+                None => continue,
+            },
+            _ => continue,
+        };
+        let mut block = block;
+        read_vint(&mut block, false)?.ok_or_else(truncated)?;
+        let timestamp = block.get(..2).ok_or_else(truncated)?;
+        blocks.push((offset, i16::from_be_bytes([timestamp[0], timestamp[1]])));
+    }
+    Ok(blocks)
+}
+
+/// the header of an element, its size is always written with 8 bytes.
+fn element_header(id: u32, size: u64) -> Vec<u8> {
+    let mut header: Vec<u8> = id
+        .to_be_bytes()
+        .into_iter()
+        .skip_while(|b| *b == 0)
+        .collect();
+    header.push(0x01);
+    header.extend(&size.to_be_bytes()[1..]);
+    header
+}
+
+fn encode_element(id: u32, payload: &[u8]) -> Vec<u8> {
+    let mut data = element_header(id, payload.len() as u64);
+    data.extend(payload);
+    data
+}
+
+/// an unsigned integer element, with as few bytes as possible.
+fn uint_element(id: u32, value: u64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let skip = (value.leading_zeros() as usize / 8).min(7);
+    encode_element(id, &bytes[skip..])
+}
+
+/// the variable length integer of a track number in a block.
+fn encode_vint(value: u64) -> Vec<u8> {
+    // values with all bits set are reserved
+    let length = (1..=8)
+        .find(|length| value < (1 << (7 * length)) - 1)
+        .unwrap_or(8);
+    let mut bytes = value.to_be_bytes()[8 - length..].to_vec();
+    bytes[0] |= 0x80 >> (length - 1);
+    bytes
+}
+
+fn text_track_entry(number: u64, uid: u64, options: &MuxOptions) -> Vec<u8> {
+    let mut entry = [
+        uint_element(ID_TRACK_NUMBER, number),
+        uint_element(ID_TRACK_UID, uid),
+        uint_element(ID_TRACK_TYPE, TRACK_TYPE_SUBTITLE),
+        uint_element(ID_FLAG_LACING, 0),
+        uint_element(ID_FLAG_DEFAULT, 0),
+        encode_element(ID_CODEC_ID, options.codec.codec_id().as_bytes()),
+        encode_element(ID_LANGUAGE, options.language.as_bytes()),
+    ]
+    .concat();
+    if let Some(name) = &options.name {
+        entry.extend(encode_element(ID_NAME, name.as_bytes()));
+    }
+    if options.codec == TextCodec::WebVtt {
+        entry.extend(encode_element(ID_CODEC_PRIVATE, b"WEBVTT"));
+    }
+    encode_element(ID_TRACK_ENTRY, &entry)
+}
+
+/// a block group with the text of the cue.
+fn text_block(
+    track: u64,
+    relative: i16,
+    duration: Option<u64>,
+    cue: &Cue,
+    options: &MuxOptions,
+) -> Vec<u8> {
+    let mut block = encode_vint(track);
+    block.extend(relative.to_be_bytes());
+    block.push(0x00);
+    let mut group = Vec::new();
+    match options.codec {
+        TextCodec::Srt => block.extend(cue.text.as_bytes()),
+        TextCodec::WebVtt => {
+            block.extend(crate::vtt::vtt_escape_text(&cue.text).as_bytes());
+            // the cue settings, followed by the cue identifier and comments, which are empty
+            if let Some(settings) = crate::vtt::vtt_cue_settings(&cue.placement()) {
+                let more = [
+                    uint_element(ID_BLOCK_ADD_ID, 1),
+                    encode_element(ID_BLOCK_ADDITIONAL, format!("{settings}\n").as_bytes()),
+                ]
+                .concat();
+                group.extend(encode_element(
+                    ID_BLOCK_ADDITIONS,
+                    &encode_element(ID_BLOCK_MORE, &more),
+                ));
+            }
+        }
+    }
+    let mut payload = encode_element(ID_BLOCK, &block);
+    if let Some(duration) = duration {
+        payload.extend(uint_element(ID_BLOCK_DURATION, duration));
+    }
+    payload.extend(group);
+    encode_element(ID_BLOCK_GROUP, &payload)
+}
+
+/// re-encode a seek head or cues element with the positions mapped to the output layout.
+///
+/// positions are written with 8 bytes so the size does not depend on their value, checksums
+/// are dropped since they would no longer match. `relative` maps a position inside a cluster,
+/// given with the old position of that cluster.
+fn rewrite_positions(
+    data: &[u8],
+    id: u32,
+    position: &dyn Fn(u64) -> u64,
+    relative: &dyn Fn(u64, u64) -> u64,
+) -> std::io::Result<Vec<u8>> {
+    let (masters, position_id): (&[u32], u32) = match id {
+        ID_SEEK_HEAD => (&[ID_SEEK], ID_SEEK_POSITION),
+        _ => (
+            &[ID_CUE_POINT, ID_CUE_TRACK_POSITIONS],
+            ID_CUE_CLUSTER_POSITION,
+        ),
+    };
+    let mut output = Vec::new();
+    for (child, payload) in parse_elements(data)? {
+        match child {
+            ID_CRC32 => {}
+            child if masters.contains(&child) => {
+                let payload =
+                    rewrite_positions_in(payload, masters, position_id, position, relative)?;
+                output.extend(encode_element(child, &payload));
+            }
+            _ => output.extend(encode_element(child, payload)),
+        }
+    }
+    Ok(output)
+}
+
+fn rewrite_positions_in(
+    data: &[u8],
+    masters: &[u32],
+    position_id: u32,
+    position: &dyn Fn(u64) -> u64,
+    relative: &dyn Fn(u64, u64) -> u64,
+) -> std::io::Result<Vec<u8>> {
+    let children = parse_elements(data)?;
+    // a relative position is within the cluster given by its sibling
+    let cluster = children
+        .iter()
+        .find(|(child, _)| *child == position_id)
+        .map(|(_, payload)| read_uint(payload))
+        .transpose()?;
+    let mut output = Vec::new();
+    for (child, payload) in children {
+        match child {
+            ID_CRC32 => {}
+            child if child == position_id => {
+                let value = position(read_uint(payload)?);
+                output.extend(encode_element(child, &value.to_be_bytes()));
+            }
+            ID_CUE_RELATIVE_POSITION => {
+                let value = read_uint(payload)?;
+                let value = cluster.map_or(value, |cluster| relative(cluster, value));
+                output.extend(encode_element(child, &value.to_be_bytes()));
+            }
+            child if masters.contains(&child) => {
+                let payload =
+                    rewrite_positions_in(payload, masters, position_id, position, relative)?;
+                output.extend(encode_element(child, &payload));
+            }
+            _ => output.extend(encode_element(child, payload)),
+        }
+    }
+    Ok(output)
+}
+
+fn parse_tracks(data: &[u8]) -> std::io::Result<Vec<Track>> {
+    let mut tracks = Vec::new();
+    for (id, entry) in parse_elements(data)? {
+        if id != ID_TRACK_ENTRY {
+            continue;
+        }
+        let mut track = Track {
+            number: 0,
+            track_type: 0,
+            codec_id: String::new(),
+            language: "eng".to_string(),
+            name: None,
+            default: true,
+            forced: false,
+            compression: Vec::new(),
+        };
+        let mut bcp47 = None;
+        for (id, data) in parse_elements(entry)? {
+            match id {
+                ID_TRACK_NUMBER => track.number = read_uint(data)?,
+                ID_TRACK_TYPE => track.track_type = read_uint(data)?,
+                ID_CODEC_ID => track.codec_id = read_string(data),
+                ID_LANGUAGE => track.language = read_string(data),
+                ID_LANGUAGE_BCP47 => bcp47 = Some(read_string(data)),
+                ID_NAME => track.name = Some(read_string(data)),
+                ID_FLAG_DEFAULT => track.default = read_uint(data)? != 0,
+                ID_FLAG_FORCED => track.forced = read_uint(data)? != 0,
+                ID_CONTENT_ENCODINGS => track.compression = parse_content_encodings(data)?,
+                _ => {}
+            }
+        }
+        if let Some(bcp47) = bcp47 {
+            track.language = bcp47;
+        }
+        tracks.push(track);
+    }
+    Ok(tracks)
+}
+
+fn parse_content_encodings(data: &[u8]) -> std::io::Result<Vec<Compression>> {
+    let mut encodings = Vec::new();
+    for (id, encoding) in parse_elements(data)? {
+        if id != ID_CONTENT_ENCODING {
+            continue;
+        }
+        let (mut order, mut scope, mut encoding_type) = (0, 1, 0);
+        let mut compression = Compression::Zlib;
+        for (id, data) in parse_elements(encoding)? {
+            match id {
+                ID_CONTENT_ENCODING_ORDER => order = read_uint(data)?,
+                ID_CONTENT_ENCODING_SCOPE => scope = read_uint(data)?,
+                ID_CONTENT_ENCODING_TYPE => encoding_type = read_uint(data)?,
+                ID_CONTENT_COMPRESSION => compression = parse_content_compression(data)?,
+                _ => {}
+            }
+        }
+        if encoding_type != 0 {
+            return Err(invalid_data("encrypted tracks are not supported"));
+        }
+        // only encodings of the block contents matter, not of the codec private data
+        if scope & 1 != 0 {
+            encodings.push((order, compression));
+        }
+    }
+    // decoding goes from the highest order to the lowest
+    encodings.sort_by_key(|(order, _)| std::cmp::Reverse(*order));
+    Ok(encodings
+        .into_iter()
+        .map(|(_, compression)| compression)
+        .collect())
+}
+
+fn parse_content_compression(data: &[u8]) -> std::io::Result<Compression> {
+    let (mut algo, mut settings) = (0, Vec::new());
+    for (id, data) in parse_elements(data)? {
+        match id {
+            ID_CONTENT_COMP_ALGO => algo = read_uint(data)?,
+            ID_CONTENT_COMP_SETTINGS => settings = data.to_vec(),
+            _ => {}
+        }
+    }
+    Ok(match algo {
+        0 => Compression::Zlib,
+        3 => Compression::HeaderStripping(settings),
+        algo => Compression::Unsupported(algo),
+    })
+}
+
+/// read a variable length integer, keeping the length marker bit for element ids.
+///
+/// returns the value and whether all value bits are set, which means an unknown size.
+fn read_vint<R: Read>(mut reader: R, keep_marker: bool) -> std::io::Result<Option<(u64, bool)>> {
+    let mut first = [0u8];
+    if reader.read(&mut first)? == 0 {
+        return Ok(None);
+    }
+    let length = first[0].leading_zeros() as usize + 1;
+    if length > 8 {
+        return Err(invalid_data("invalid variable length integer"));
+    }
+    let mut rest = [0u8; 7];
+    reader.read_exact(&mut rest[..length - 1])?;
+
+    let marker = 0x80u8 >> (length - 1);
+    let mut value = u64::from(match keep_marker {
+        true => first[0],
+        false => first[0] & !marker,
+    });
+    let mut all_ones = first[0] | marker == 0xFF;
+    for byte in rest[..length - 1].iter() {
+        value = (value << 8) | u64::from(*byte);
+        all_ones &= *byte == 0xFF;
+    }
+    Ok(Some((value, all_ones && !keep_marker)))
+}
+
+/// the id and size of the next element, the size is `None` when unknown.
+fn read_element_header<R: Read>(mut reader: R) -> std::io::Result<Option<(u32, Option<u64>)>> {
+    let Some((id, _)) = read_vint(&mut reader, true)? else {
+        return Ok(None);
+    };
+    let id = u32::try_from(id).map_err(|_| invalid_data("invalid element id"))?;
+    let (size, unknown) = read_vint(&mut reader, false)?.ok_or_else(truncated)?;
+    Ok(Some((id, (!unknown).then_some(size))))
+}
+
+fn known_size(id: u32, size: Option<u64>) -> std::io::Result<u64> {
+    size.ok_or_else(|| invalid_data(format!("element {id:#x} has an unknown size")))
+}
+
+fn read_payload<R: Read>(reader: R, size: u64) -> std::io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.take(size).read_to_end(&mut data)?;
+    if data.len() as u64 != size {
+        return Err(truncated());
+    }
+    Ok(data)
+}
+
+fn skip<R: Read>(reader: R, size: u64) -> std::io::Result<()> {
+    let skipped = std::io::copy(&mut reader.take(size), &mut std::io::sink())?;
+    if skipped != size {
+        return Err(truncated());
+    }
+    Ok(())
+}
+
+/// the child elements of a master element read in memory.
+fn parse_elements(mut data: &[u8]) -> std::io::Result<Vec<(u32, &[u8])>> {
+    let mut elements = Vec::new();
+    while let Some((id, size)) = read_element_header(&mut data)? {
+        let size = usize::try_from(known_size(id, size)?).map_err(|_| truncated())?;
+        let payload = data.get(..size).ok_or_else(truncated)?;
+        elements.push((id, payload));
+        data = &data[size..];
+    }
+    Ok(elements)
+}
+
+fn read_uint(data: &[u8]) -> std::io::Result<u64> {
+    if data.len() > 8 {
+        return Err(invalid_data("unsigned integer element longer than 8 bytes"));
+    }
+    Ok(data
+        .iter()
+        .fold(0, |value, byte| (value << 8) | u64::from(*byte)))
+}
+
+/// strings may be padded with zeros.
+fn read_string(data: &[u8]) -> String {
+    let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+fn invalid_data(message: impl Into<String>) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.into())
+}
+
+fn truncated() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::UnexpectedEof,
+        "truncated Matroska element",
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{encode_element as element, *};
+
+    const PGS: &[u8] = include_bytes!("../subtitle.sup");
+
+    /// a Matroska file with a video track and the test stream as a zlib compressed pgs track,
+    /// every display set in its own cluster, with a cue pointing to the first video block.
+    fn matroska(unknown_cluster_size: bool) -> Vec<u8> {
+        let video = [
+            element(ID_TRACK_NUMBER, &[1]),
+            element(ID_TRACK_TYPE, &[1]),
+            element(ID_CODEC_ID, b"V_MPEG4/ISO/AVC"),
+        ]
+        .concat();
+        let compression = element(ID_CONTENT_COMPRESSION, &element(ID_CONTENT_COMP_ALGO, &[0]));
+        let subtitles = [
+            element(ID_TRACK_NUMBER, &[2]),
+            element(ID_TRACK_TYPE, &[0x11]),
+            element(ID_CODEC_ID, PGS_CODEC_ID.as_bytes()),
+            element(ID_LANGUAGE, b"por"),
+            element(ID_FLAG_DEFAULT, &[0]),
+            element(
+                ID_CONTENT_ENCODINGS,
+                &element(ID_CONTENT_ENCODING, &compression),
+            ),
+        ]
+        .concat();
+        let tracks = [
+            element(ID_TRACK_ENTRY, &video),
+            element(ID_TRACK_ENTRY, &subtitles),
+        ]
+        .concat();
+
+        let mut clusters = Vec::new();
+        let mut video_cue = None;
+        for ds in pgs::decode_display_sets(PGS).unwrap() {
+            let sup = pgs::encode_display_set(&ds).unwrap();
+            // strip the magic number and timestamps of every segment
+            let mut segments = Vec::new();
+            let mut data = sup.as_slice();
+            while !data.is_empty() {
+                let size = usize::from(u16::from_be_bytes([data[11], data[12]]));
+                segments.extend(&data[10..13 + size]);
+                data = &data[13 + size..];
+            }
+            let mut encoder =
+                flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(&segments).unwrap();
+
+            // the pgs block is 5ms after the start of its cluster and the video block 10ms
+            let millis = ds.pcs.header.pts / 90;
+            let mut cluster = element(ID_CLUSTER_TIMESTAMP, &(millis - 5).to_be_bytes());
+            let mut block = vec![0x82];
+            block.extend(5i16.to_be_bytes());
+            block.push(0x80);
+            block.extend(encoder.finish().unwrap());
+            cluster.extend(element(ID_SIMPLE_BLOCK, &block));
+            video_cue.get_or_insert((clusters.len() as u64, cluster.len() as u64));
+            cluster.extend(element(ID_SIMPLE_BLOCK, &[0x81, 0, 10, 0x80, 0xFF]));
+            match unknown_cluster_size {
+                true => {
+                    clusters.extend([0x1F, 0x43, 0xB6, 0x75, 0xFF]);
+                    clusters.extend(cluster);
+                }
+                false => clusters.extend(element(ID_CLUSTER, &cluster)),
+            }
+        }
+
+        // a seek head pointing to the tracks that follow it
+        let seek_head = |position: u64| {
+            let seek = [
+                element(0x53AB, &ID_TRACKS.to_be_bytes()),
+                element(ID_SEEK_POSITION, &position.to_be_bytes()),
+            ]
+            .concat();
+            element(ID_SEEK_HEAD, &element(ID_SEEK, &seek))
+        };
+        let mut segment = seek_head(seek_head(0).len() as u64);
+        segment.extend(element(ID_TRACKS, &tracks));
+        let (cluster, relative) = video_cue.unwrap();
+        let positions = [
+            element(0xF7, &[1]),
+            element(
+                ID_CUE_CLUSTER_POSITION,
+                &(segment.len() as u64 + cluster).to_be_bytes(),
+            ),
+            element(ID_CUE_RELATIVE_POSITION, &relative.to_be_bytes()),
+        ]
+        .concat();
+        let cue_point = [
+            element(0xB3, &[0]),
+            element(ID_CUE_TRACK_POSITIONS, &positions),
+        ]
+        .concat();
+        segment.extend(clusters);
+        segment.extend(element(ID_CUES, &element(ID_CUE_POINT, &cue_point)));
+        [
+            element(ID_EBML, &element(ID_DOC_TYPE, b"matroska")),
+            element(ID_SEGMENT, &segment),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn test_pgs_track() {
+        let mkv = matroska(true);
+        assert!(is_matroska(&mkv));
+        let tracks = tracks(mkv.as_slice()).unwrap();
+        assert_eq!(tracks.len(), 2);
+        assert_eq!(tracks[1].to_string(), "2: S_HDMV/PGS [por]");
+        assert_eq!(tracks[1].compression, [Compression::Zlib]);
+
+        let reader = PgsTrackReader::new(mkv.as_slice(), None).unwrap();
+        assert_eq!(reader.track(), 2);
+        let decoded = pgs::DisplaySetReader::new(reader)
+            .collect::<std::io::Result<Vec<_>>>()
+            .unwrap();
+        let expected = pgs::decode_display_sets(PGS).unwrap();
+        assert_eq!(decoded.len(), expected.len());
+        for (decoded, expected) in decoded.iter().zip(expected.iter()) {
+            // the block timestamps are in milliseconds
+            assert!(decoded.pcs.header.pts.abs_diff(expected.pcs.header.pts) < 90);
+            assert_eq!(decoded.ods.len(), expected.ods.len());
+        }
+
+        assert!(matches!(
+            PgsTrackReader::new(mkv.as_slice(), Some(1)),
+            Err(Error::InvalidStream(_))
+        ));
+    }
+
+    #[test]
+    fn test_mux_text_track() {
+        let mkv = matroska(false);
+        let cue = |begin: u64, end: u64, text: &str| Cue {
+            range: crate::TimeRange::new(
+                std::time::Duration::from_millis(begin),
+                std::time::Duration::from_millis(end),
+            ),
+            text: text.to_string(),
+            subtitles: Vec::new(),
+        };
+        // the first cue is before the first cluster, the second one between the blocks of the
+        // first cluster and the last one far after the last cluster
+        let cues = [
+            cue(1000, 2000, "first"),
+            cue(86_172, 86_300, "between"),
+            cue(86_500, 87_000, "second"),
+            cue(600_000, 601_000, "last"),
+        ];
+        let options = MuxOptions {
+            language: "por".to_string(),
+            name: Some("OCR".to_string()),
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        mux_text_track(std::io::Cursor::new(&mkv), &mut output, &cues, &options).unwrap();
+
+        let mut demuxer = Demuxer::new(output.as_slice()).unwrap();
+        let track = &demuxer.tracks()[2];
+        assert_eq!(track.to_string(), "3: S_TEXT/UTF8 [por] \"OCR\"");
+        let mut blocks = Vec::new();
+        let mut timestamps = Vec::new();
+        while let Some(block) = demuxer.next_block().unwrap() {
+            timestamps.push(block.timestamp);
+            if block.track == 3 {
+                let text = String::from_utf8(block.data).unwrap();
+                blocks.push((block.timestamp / 1_000_000, text));
+            }
+        }
+        assert_eq!(
+            blocks,
+            [
+                (1000, "first".to_string()),
+                (86_172, "between".to_string()),
+                (86_500, "second".to_string()),
+                (600_000, "last".to_string())
+            ]
+        );
+        assert!(timestamps.is_sorted());
+
+        // the pgs track is untouched and the seek head still points to the tracks
+        let reader = PgsTrackReader::new(output.as_slice(), None).unwrap();
+        let display_sets = pgs::DisplaySetReader::new(reader)
+            .collect::<std::io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(
+            display_sets.len(),
+            pgs::decode_display_sets(PGS).unwrap().len()
+        );
+        let segment = output
+            .windows(4)
+            .position(|w| w == ID_SEGMENT.to_be_bytes())
+            .unwrap()
+            + 12;
+        let seek_head = parse_elements(&output[segment..]).unwrap()[0].1;
+        let seek = parse_elements(seek_head).unwrap()[0].1;
+        let position = read_uint(parse_elements(seek).unwrap()[1].1).unwrap() as usize;
+        assert_eq!(&output[segment + position..][..4], ID_TRACKS.to_be_bytes());
+
+        // the cue still points to the first video block
+        let elements = parse_elements(&output[segment..]).unwrap();
+        let cues = elements.iter().find(|(id, _)| *id == ID_CUES).unwrap().1;
+        let cue_point = parse_elements(cues).unwrap()[0].1;
+        let positions = parse_elements(parse_elements(cue_point).unwrap()[1].1).unwrap();
+        let cluster = read_uint(positions[1].1).unwrap() as usize;
+        let relative = read_uint(positions[2].1).unwrap() as usize;
+        let cluster = parse_elements(&output[segment + cluster..]).unwrap()[0];
+        assert_eq!(cluster.0, ID_CLUSTER);
+        let block = parse_elements(&cluster.1[relative..]).unwrap()[0];
+        assert_eq!(block, (ID_SIMPLE_BLOCK, &[0x81, 0, 10, 0x80, 0xFF][..]));
+    }
+}
@@ -0,0 +1,584 @@
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use serde::Serialize;
+
+use crate::{
+    italic::ItalicTagged,
+    preprocess::{Preprocess, Preprocessed},
+    segment::LineSegmented,
+    Bitmap, BitmapSubtitle, Error, Result, TextSubtitle,
+};
+
+/// Recognizes the text in a bitmap.
+///
+/// Engines are not shared between threads, [`subtitles_ocr_with`] creates one per worker.
+pub trait OcrEngine {
+    fn recognize(&mut self, bitmap: &Bitmap) -> Result<OcrResult>;
+
+    /// recognize a bitmap known to hold a single line of text, see
+    /// [`crate::segment::LineSegmented`].
+    fn recognize_line(&mut self, bitmap: &Bitmap) -> Result<OcrResult> {
+        self.recognize(bitmap)
+    }
+}
+
+impl<E: OcrEngine + ?Sized> OcrEngine for Box<E> {
+    fn recognize(&mut self, bitmap: &Bitmap) -> Result<OcrResult> {
+        (**self).recognize(bitmap)
+    }
+
+    fn recognize_line(&mut self, bitmap: &Bitmap) -> Result<OcrResult> {
+        (**self).recognize_line(bitmap)
+    }
+}
+
+/// Text recognized in a bitmap.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct OcrResult {
+    pub text: String,
+    /// the lines of text with their position in the bitmap, empty if the engine does not report
+    /// them.
+    pub lines: Vec<OcrLine>,
+    /// mean confidence of the whole text, from 0 to 100.
+    pub confidence: Option<f32>,
+}
+
+/// A line of recognized text.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct OcrLine {
+    pub text: String,
+    /// horizontal position of the top left corner of the line in the bitmap
+    pub x: u32,
+    /// vertical position of the top left corner of the line in the bitmap
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// from 0 to 100.
+    pub confidence: Option<f32>,
+    /// the words of the line, empty if the engine does not report them.
+    pub words: Vec<OcrWord>,
+}
+
+/// A recognized word with how sure the engine is of it.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct OcrWord {
+    pub text: String,
+    /// from 0 to 100.
+    pub confidence: Option<f32>,
+}
+
+/// OCR engine used to recognize the text in the bitmaps.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum OcrBackend {
+    #[default]
+    Tesseract,
+    /// a command run for every bitmap, see [`ExternalOcr`].
+    External(ExternalOcr),
+    /// fake text describing the bitmap, see [`MockOcr`].
+    Mock,
+}
+
+impl OcrBackend {
+    /// create an engine of this backend for the given language.
+    pub fn engine(&self, language: &str) -> Result<Box<dyn OcrEngine>> {
+        Ok(match self {
+            OcrBackend::Tesseract => Box::new(TesseractOcr::new(language)?),
+            OcrBackend::External(external) => Box::new(external.clone().language(language)),
+            OcrBackend::Mock => Box::new(MockOcr),
+        })
+    }
+}
+
+/// OCR using the tesseract library.
+pub struct TesseractOcr {
+    // taken while tesseract is working on a frame, since its api consumes itself
+    tesseract: Option<tesseract::Tesseract>,
+    /// page segmentation mode currently set
+    mode: tesseract::PageSegMode,
+}
+
+impl TesseractOcr {
+    pub fn new(language: &str) -> Result<Self> {
+        let tesseract = tesseract::Tesseract::new(None, Some(language))
+            .map_err(|err| Error::ocr("initializing tesseract", err))?;
+        Ok(Self {
+            tesseract: Some(tesseract),
+            // the default of the tesseract api
+            mode: tesseract::PageSegMode::PsmSingleBlock,
+        })
+    }
+
+    fn run(&mut self, bitmap: &Bitmap, mode: tesseract::PageSegMode) -> Result<OcrResult> {
+        let mut tesseract = self.tesseract.take().ok_or_else(|| {
+            Error::ocr(
+                "tesseract recognize",
+                "tesseract failed on a previous bitmap",
+            )
+        })?;
+        if mode != self.mode {
+            tesseract.set_page_seg_mode(mode);
+            self.mode = mode;
+        }
+        let mut tesseract = tesseract
+            .set_frame(
+                &bitmap.pixels,
+                bitmap.width as i32,
+                bitmap.height as i32,
+                4,
+                bitmap.width as i32 * 4,
+            )
+            .map_err(|err| Error::ocr("setting tesseract frame", err))?
+            .recognize()
+            .map_err(|err| Error::ocr("tesseract recognize", err))?;
+        let text = tesseract
+            .get_text()
+            .map_err(|err| Error::ocr("tesseract get text", err))?;
+        let tsv = tesseract
+            .get_tsv_text(0)
+            .map_err(|err| Error::ocr("tesseract get tsv text", err))?;
+        let confidence = tesseract.mean_text_conf();
+        self.tesseract = Some(tesseract);
+        Ok(OcrResult {
+            text,
+            lines: tsv_lines(&tsv),
+            confidence: (confidence >= 0).then_some(confidence as f32),
+        })
+    }
+}
+
+impl OcrEngine for TesseractOcr {
+    fn recognize(&mut self, bitmap: &Bitmap) -> Result<OcrResult> {
+        self.run(bitmap, tesseract::PageSegMode::PsmSingleBlock)
+    }
+
+    fn recognize_line(&mut self, bitmap: &Bitmap) -> Result<OcrResult> {
+        self.run(bitmap, tesseract::PageSegMode::PsmSingleLine)
+    }
+}
+
+/// group the words of tesseract's tsv output into lines.
+///
+/// each row is `level page block paragraph line word left top width height confidence text`,
+/// lines are level 4 and are followed by their words, level 5.
+fn tsv_lines(tsv: &str) -> Vec<OcrLine> {
+    let mut lines = Vec::<OcrLine>::new();
+    for row in tsv.lines() {
+        let fields = row.split('\t').collect::<Vec<_>>();
+        let [level, _, _, _, _, _, x, y, width, height, confidence, text] = fields[..] else {
+            continue;
+        };
+        // also skips the header row, if there is one
+        let (Ok(level), Ok(x), Ok(y), Ok(width), Ok(height)) = (
+            level.parse::<u32>(),
+            x.parse(),
+            y.parse(),
+            width.parse(),
+            height.parse(),
+        ) else {
+            continue;
+        };
+        match level {
+            4 => lines.push(OcrLine {
+                text: String::new(),
+                x,
+                y,
+                width,
+                height,
+                confidence: None,
+                words: Vec::new(),
+            }),
+            5 if !text.trim().is_empty() => {
+                let Some(line) = lines.last_mut() else {
+                    continue;
+                };
+                if !line.text.is_empty() {
+                    line.text.push(' ');
+                }
+                line.text.push_str(text.trim());
+                line.words.push(OcrWord {
+                    text: text.trim().to_string(),
+                    // words without a confidence have -1
+                    confidence: confidence.parse::<f32>().ok().filter(|c| *c >= 0.0),
+                });
+            }
+            _ => {}
+        }
+    }
+    lines.retain(|line| !line.text.is_empty());
+    for line in &mut lines {
+        line.confidence = mean_confidence(line.words.iter().map(|word| word.confidence));
+    }
+    lines
+}
+
+/// mean of the known confidences, `None` if there are none.
+pub(crate) fn mean_confidence(confidences: impl Iterator<Item = Option<f32>>) -> Option<f32> {
+    let (sum, count) = confidences
+        .flatten()
+        .fold((0.0, 0), |(sum, count), confidence| {
+            (sum + confidence, count + 1)
+        });
+    (count > 0).then(|| sum / count as f32)
+}
+
+/// OCR by running a command for every bitmap.
+///
+/// The bitmap is written to the standard input of the command as a png and the text is read
+/// from its standard output. The OCR language is passed in the `SUP_TO_SRT_LANGUAGE`
+/// environment variable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExternalOcr {
+    pub program: String,
+    pub args: Vec<String>,
+    language: String,
+}
+
+impl ExternalOcr {
+    pub fn new(program: impl Into<String>, args: Vec<String>) -> Self {
+        Self {
+            program: program.into(),
+            args,
+            language: String::new(),
+        }
+    }
+
+    /// parse a command line, split on whitespace without any quoting.
+    pub fn from_command_line(command: &str) -> Option<Self> {
+        let mut parts = command.split_whitespace().map(str::to_string);
+        let program = parts.next()?;
+        Some(Self::new(program, parts.collect()))
+    }
+
+    fn language(mut self, language: &str) -> Self {
+        self.language = language.to_string();
+        self
+    }
+}
+
+impl OcrEngine for ExternalOcr {
+    fn recognize(&mut self, bitmap: &Bitmap) -> Result<OcrResult> {
+        let mut png = Vec::new();
+        bitmap.write_png(&mut png)?;
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .env("SUP_TO_SRT_LANGUAGE", &self.language)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| Error::ocr("starting external command", err))?;
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        // written from another thread so a command that writes before reading all of its input
+        // does not block on a full pipe
+        let output = std::thread::scope(|scope| {
+            let writer = scope.spawn(move || stdin.write_all(&png));
+            let output = child.wait_with_output();
+            // the command may exit without reading its input, that is not an error
+            let _ = writer.join().unwrap();
+            output
+        })
+        .map_err(|err| Error::ocr("running external command", err))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let mut reason = format!("{} {}", self.program, output.status);
+            if !stderr.trim().is_empty() {
+                reason = format!("{reason}: {}", stderr.trim());
+            }
+            return Err(Error::ocr("running external command", reason));
+        }
+        Ok(OcrResult {
+            text: String::from_utf8_lossy(&output.stdout).into_owned(),
+            lines: Vec::new(),
+            confidence: None,
+        })
+    }
+}
+
+/// OCR that does not recognize anything, the text only describes the bitmap.
+///
+/// Its output only depends on the bitmap, which makes it useful for testing without tesseract.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MockOcr;
+
+impl OcrEngine for MockOcr {
+    fn recognize(&mut self, bitmap: &Bitmap) -> Result<OcrResult> {
+        let opaque = bitmap
+            .pixels
+            .chunks_exact(4)
+            .filter(|pixel| pixel[3] != 0)
+            .count();
+        let text = format!("{}x{} bitmap, {opaque} opaque", bitmap.width, bitmap.height);
+        Ok(OcrResult {
+            lines: vec![OcrLine {
+                text: text.clone(),
+                x: 0,
+                y: 0,
+                width: bitmap.width,
+                height: bitmap.height,
+                confidence: Some(100.0),
+                words: text
+                    .split_whitespace()
+                    .map(|word| OcrWord {
+                        text: word.to_string(),
+                        confidence: Some(100.0),
+                    })
+                    .collect(),
+            }],
+            text: format!("{text}\n"),
+            confidence: Some(100.0),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OcrOptions {
+    /// Tesseract language code.
+    pub language: String,
+    pub backend: OcrBackend,
+    /// steps run on every bitmap before OCR.
+    pub preprocess: Vec<Preprocess>,
+    /// recognize every line of the bitmaps on its own.
+    pub segment_lines: bool,
+    /// tag italic lines, see [`crate::italic::ItalicTagged`].
+    pub italics: bool,
+    /// number of worker threads, if not set or 0 then one per cpu is used.
+    pub threads: Option<usize>,
+}
+
+impl Default for OcrOptions {
+    fn default() -> Self {
+        Self {
+            language: "eng".to_string(),
+            backend: Default::default(),
+            preprocess: Vec::new(),
+            segment_lines: false,
+            italics: false,
+            threads: None,
+        }
+    }
+}
+
+/// run OCR over the bitmap subtitles using one worker thread per cpu.
+///
+/// `on_subtitle` is called with the text subtitles in the same order the bitmap subtitles were
+/// produced, as soon as they are available.
+pub fn subtitles_ocr<I>(
+    subtitles: I,
+    options: &OcrOptions,
+    mut on_subtitle: impl FnMut(TextSubtitle) -> Result<()>,
+) -> Result<()>
+where
+    I: Iterator<Item = Result<BitmapSubtitle>> + Send,
+{
+    subtitles_ocr_bitmaps(subtitles, options, |subtitle, _| on_subtitle(subtitle))
+}
+
+/// like [`subtitles_ocr`] but `on_subtitle` also gets back the bitmap each text subtitle was
+/// recognized from.
+pub(crate) fn subtitles_ocr_bitmaps<I>(
+    subtitles: I,
+    options: &OcrOptions,
+    on_subtitle: impl FnMut(TextSubtitle, Bitmap) -> Result<()>,
+) -> Result<()>
+where
+    I: Iterator<Item = Result<BitmapSubtitle>> + Send,
+{
+    ocr_workers(
+        subtitles,
+        options.threads,
+        || {
+            let engine = options.backend.engine(&options.language)?;
+            // lines are found on the bitmaps as extracted, then preprocessed one by one
+            let engine = Preprocessed::new(options.preprocess.clone(), engine);
+            let engine = match options.italics {
+                true => Box::new(ItalicTagged::new(engine)) as Box<dyn OcrEngine>,
+                false => Box::new(engine),
+            };
+            Ok(match options.segment_lines {
+                true => Box::new(LineSegmented::new(engine)) as Box<dyn OcrEngine>,
+                false => Box::new(engine),
+            })
+        },
+        on_subtitle,
+    )
+}
+
+/// like [`subtitles_ocr`] but with engines created by `new_engine`, called once by every worker
+/// thread.
+pub fn subtitles_ocr_with<I, E>(
+    subtitles: I,
+    threads: Option<usize>,
+    new_engine: impl Fn() -> Result<E> + Sync,
+    mut on_subtitle: impl FnMut(TextSubtitle) -> Result<()>,
+) -> Result<()>
+where
+    I: Iterator<Item = Result<BitmapSubtitle>> + Send,
+    E: OcrEngine,
+{
+    ocr_workers(subtitles, threads, new_engine, |subtitle, _| {
+        on_subtitle(subtitle)
+    })
+}
+
+fn ocr_workers<I, E>(
+    subtitles: I,
+    threads: Option<usize>,
+    new_engine: impl Fn() -> Result<E> + Sync,
+    mut on_subtitle: impl FnMut(TextSubtitle, Bitmap) -> Result<()>,
+) -> Result<()>
+where
+    I: Iterator<Item = Result<BitmapSubtitle>> + Send,
+    E: OcrEngine,
+{
+    // without a worker nothing would read the subtitles
+    let num_workers = threads.filter(|&threads| threads > 0).unwrap_or_else(|| {
+        std::thread::available_parallelism()
+            .map(usize::from)
+            .unwrap_or(4)
+    });
+    let new_engine = &new_engine;
+    // bounded so a slow OCR does not cause the whole input to be buffered in memory
+    let (ocr_in_sender, ocr_in_receiver) =
+        crossbeam::channel::bounded::<(usize, BitmapSubtitle)>(num_workers * 2);
+    // workers send their first error instead of a result, stopping the conversion
+    let (ocr_out_sender, ocr_out_receiver) =
+        crossbeam::channel::unbounded::<Result<(usize, TextSubtitle, Bitmap)>>();
+
+    tracing::info!("starting ocr");
+    std::thread::scope(|scope| -> Result<()> {
+        let producer = scope.spawn(move || -> Result<()> {
+            for (idx, subtitle) in subtitles.enumerate() {
+                if ocr_in_sender.send((idx, subtitle?)).is_err() {
+                    // all workers exited, the error is reported by them
+                    break;
+                }
+            }
+            Ok(())
+        });
+
+        for _ in 0..num_workers {
+            let ocr_in_receiver = ocr_in_receiver.clone();
+            let ocr_out_sender = ocr_out_sender.clone();
+            scope.spawn(move || {
+                let recognize = || -> Result<()> {
+                    let mut engine = new_engine()?;
+                    while let Ok((idx, subtitle)) = ocr_in_receiver.recv() {
+                        let result = engine.recognize(&subtitle.bitmap)?;
+                        let text_subtitle = TextSubtitle {
+                            range: subtitle.range,
+                            text: result.text,
+                            placement: subtitle.placement,
+                            color: subtitle.color,
+                            forced: subtitle.forced,
+                            confidence: result.confidence,
+                            words: result
+                                .lines
+                                .into_iter()
+                                .flat_map(|line| line.words)
+                                .collect(),
+                        };
+                        if ocr_out_sender
+                            .send(Ok((idx, text_subtitle, subtitle.bitmap)))
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                    Ok(())
+                };
+                if let Err(err) = recognize() {
+                    let _ = ocr_out_sender.send(Err(err));
+                }
+            });
+        }
+        drop(ocr_in_receiver);
+        drop(ocr_out_sender);
+
+        // workers finish out of order, hold results back until the ones before them arrive
+        let mut reorder = BTreeMap::new();
+        let mut next_idx = 0;
+        let mut result = Ok(());
+        'recv: while let Ok(received) = ocr_out_receiver.recv() {
+            let (idx, text_subtitle, bitmap) = match received {
+                Ok(received) => received,
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            };
+            reorder.insert(idx, (text_subtitle, bitmap));
+            while let Some((text_subtitle, bitmap)) = reorder.remove(&next_idx) {
+                next_idx += 1;
+                if let Err(err) = on_subtitle(text_subtitle, bitmap) {
+                    result = Err(err);
+                    break 'recv;
+                }
+            }
+        }
+        // stops the workers, and with them the producer, if we are exiting because of an error
+        drop(ocr_out_receiver);
+
+        result?;
+        producer.join().unwrap()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::TimeRange;
+
+    #[test]
+    fn test_tsv_lines() {
+        let tsv = "\
+1\t1\t0\t0\t0\t0\t0\t0\t600\t120\t-1\t
+4\t1\t1\t1\t1\t0\t20\t10\t300\t40\t-1\t
+5\t1\t1\t1\t1\t1\t20\t10\t100\t40\t90\tHello
+5\t1\t1\t1\t1\t2\t130\t10\t190\t40\t80\tthere,
+4\t1\t1\t1\t2\t0\t40\t60\t200\t40\t-1\t
+5\t1\t1\t1\t2\t1\t40\t60\t200\t40\t70\tfriend.
+4\t1\t1\t1\t3\t0\t0\t0\t10\t10\t-1\t
+5\t1\t1\t1\t3\t1\t0\t0\t10\t10\t95\t \n";
+        let lines = tsv_lines(tsv);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].text, "Hello there,");
+        assert_eq!((lines[0].x, lines[0].y, lines[0].width), (20, 10, 300));
+        assert_eq!(lines[0].confidence, Some(85.0));
+        assert_eq!(lines[1].text, "friend.");
+        assert_eq!(lines[1].confidence, Some(70.0));
+        assert_eq!(lines[0].words[1].text, "there,");
+        assert_eq!(lines[0].words[1].confidence, Some(80.0));
+    }
+
+    /// fails on every bitmap.
+    struct FailingOcr;
+
+    impl OcrEngine for FailingOcr {
+        fn recognize(&mut self, _bitmap: &Bitmap) -> Result<OcrResult> {
+            Err(Error::InvalidStream("unreadable".to_string()))
+        }
+    }
+
+    #[test]
+    fn test_worker_error_stops_ocr() {
+        // the input never ends, so this only returns if the error stops the producer
+        let subtitles = std::iter::repeat_with(|| {
+            Ok(BitmapSubtitle {
+                range: TimeRange::new(Duration::ZERO, Duration::from_secs(1)),
+                bitmap: Bitmap::default(),
+                placement: Default::default(),
+                color: None,
+                forced: false,
+            })
+        });
+        let result = subtitles_ocr_with(subtitles, Some(2), || Ok(FailingOcr), |_| Ok(()));
+        assert!(matches!(result, Err(Error::InvalidStream(_))));
+    }
+}
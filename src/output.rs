@@ -0,0 +1,255 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use color_eyre::{
+    eyre::{eyre, Context},
+    Result,
+};
+
+/// where the generated subtitles should be written to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputTarget {
+    Stdout,
+    File(PathBuf),
+}
+
+impl OutputTarget {
+    /// a missing path or `-` means stdout.
+    pub fn from_arg(path: Option<PathBuf>) -> Self {
+        match path {
+            Some(path) if path.as_os_str() != "-" => Self::File(path),
+            _ => Self::Stdout,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OutputOptions {
+    /// replace the output file if it already exists.
+    pub force: bool,
+    /// create missing parent directories of the output file.
+    pub create_dirs: bool,
+}
+
+/// An output sink.
+///
+/// When writing to a file the data goes to a temporary file in the same directory that only
+/// replaces the target path once [`Output::commit`] is called, so the target never contains a
+/// partially written subtitle file. Dropping an uncommitted output removes the temporary file.
+pub struct Output {
+    inner: OutputInner,
+}
+
+enum OutputInner {
+    Stdout(std::io::StdoutLock<'static>),
+    File {
+        writer: Option<BufWriter<File>>,
+        temp_path: PathBuf,
+        path: PathBuf,
+        force: bool,
+    },
+}
+
+impl Output {
+    pub fn open(target: &OutputTarget, options: OutputOptions) -> Result<Self> {
+        let path = match target {
+            OutputTarget::Stdout => {
+                return Ok(Self {
+                    inner: OutputInner::Stdout(std::io::stdout().lock()),
+                })
+            }
+            OutputTarget::File(path) => path,
+        };
+
+        // fail early, before any work is done, instead of only when committing
+        if !options.force && path.exists() {
+            return Err(eyre!(
+                "output file {} already exists, use --force to overwrite it",
+                path.display()
+            ));
+        }
+
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        if options.create_dirs {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("creating output directory {}", parent.display()))?;
+        }
+
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| eyre!("output path {} is not a file", path.display()))?;
+        let mut temp_name = std::ffi::OsString::from(".");
+        temp_name.push(file_name);
+        temp_name.push(format!(".{}.tmp", std::process::id()));
+        let temp_path = parent.join(temp_name);
+
+        let file = File::options()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)
+            .with_context(|| format!("creating temporary file {}", temp_path.display()))?;
+
+        Ok(Self {
+            inner: OutputInner::File {
+                writer: Some(BufWriter::new(file)),
+                temp_path,
+                path: path.clone(),
+                force: options.force,
+            },
+        })
+    }
+
+    /// flush all written data and move it to the target path.
+    pub fn commit(mut self) -> Result<()> {
+        match &mut self.inner {
+            OutputInner::Stdout(stdout) => stdout.flush().context("flushing stdout"),
+            OutputInner::File {
+                writer,
+                temp_path,
+                path,
+                force,
+            } => {
+                let file = writer
+                    .take()
+                    .expect("output already committed")
+                    .into_inner()
+                    .map_err(|err| err.into_error())
+                    .context("flushing output file")?;
+                file.sync_all().context("syncing output file")?;
+                drop(file);
+
+                if *force {
+                    std::fs::rename(&*temp_path, &*path)
+                        .with_context(|| format!("renaming output to {}", path.display()))?;
+                } else {
+                    persist_no_clobber(temp_path, path)?;
+                }
+                tracing::info!("wrote output to {}", path.display());
+                Ok(())
+            }
+        }
+    }
+}
+
+/// move `temp_path` to `path` failing if `path` already exists.
+fn persist_no_clobber(temp_path: &Path, path: &Path) -> Result<()> {
+    match std::fs::hard_link(temp_path, path) {
+        Ok(()) => {
+            let _ = std::fs::remove_file(temp_path);
+            Ok(())
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => Err(eyre!(
+            "output file {} already exists, use --force to overwrite it",
+            path.display()
+        )),
+        Err(err) if err.kind() == std::io::ErrorKind::Unsupported => {
+            // filesystems without hard links, there is a small window for a race here
+            if path.exists() {
+                return Err(eyre!(
+                    "output file {} already exists, use --force to overwrite it",
+                    path.display()
+                ));
+            }
+            std::fs::rename(temp_path, path)
+                .with_context(|| format!("renaming output to {}", path.display()))
+        }
+        Err(err) => Err(err).with_context(|| format!("moving output to {}", path.display())),
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match &mut self.inner {
+            OutputInner::Stdout(stdout) => stdout.write(buf),
+            OutputInner::File { writer, .. } => writer.as_mut().unwrap().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.inner {
+            OutputInner::Stdout(stdout) => stdout.flush(),
+            OutputInner::File { writer, .. } => writer.as_mut().unwrap().flush(),
+        }
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        if let OutputInner::File {
+            writer, temp_path, ..
+        } = &mut self.inner
+            && writer.take().is_some()
+        {
+            let _ = std::fs::remove_file(&*temp_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("sup-to-srt-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn output_is_atomic_and_does_not_overwrite() {
+        let dir = test_dir("output");
+        let path = dir.join("nested").join("out.srt");
+        let target = OutputTarget::File(path.clone());
+
+        assert!(Output::open(&target, OutputOptions::default()).is_err());
+
+        let options = OutputOptions {
+            force: false,
+            create_dirs: true,
+        };
+        let mut output = Output::open(&target, options).unwrap();
+        output.write_all(b"first").unwrap();
+        assert!(!path.exists());
+        output.commit().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"first");
+
+        assert!(Output::open(&target, options).is_err());
+
+        let force = OutputOptions {
+            force: true,
+            ..options
+        };
+        let mut output = Output::open(&target, force).unwrap();
+        output.write_all(b"second").unwrap();
+        drop(output);
+        assert_eq!(std::fs::read(&path).unwrap(), b"first");
+
+        let mut output = Output::open(&target, force).unwrap();
+        output.write_all(b"second").unwrap();
+        output.commit().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"second");
+
+        let entries = std::fs::read_dir(path.parent().unwrap()).unwrap().count();
+        assert_eq!(entries, 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dash_is_stdout() {
+        assert_eq!(OutputTarget::from_arg(None), OutputTarget::Stdout);
+        assert_eq!(
+            OutputTarget::from_arg(Some(PathBuf::from("-"))),
+            OutputTarget::Stdout
+        );
+        assert_eq!(
+            OutputTarget::from_arg(Some(PathBuf::from("a.srt"))),
+            OutputTarget::File(PathBuf::from("a.srt"))
+        );
+    }
+}
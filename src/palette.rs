@@ -0,0 +1,194 @@
+//! Tell the text fill apart from its outline using the palette of the object.
+//!
+//! pgs glyphs are drawn from a handful of palette entries: a fill, an outline and a few entries
+//! blending them with each other and with the background. Knowing which is which gives a clean
+//! dark-on-light bitmap for OCR straight from the palette indices, without guessing from the
+//! rendered colors.
+use crate::{Bitmap, MIN_OPAQUE};
+
+/// the fill and the outline differ at least this much in luminance.
+const MIN_CONTRAST: u8 = 32;
+/// fills are usually white or yellow, brighter than this.
+const MIN_FILL_LUMINANCE: u8 = 64;
+
+/// What a palette entry is used for in an object.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PaletteRole {
+    /// not used by the object, or fully transparent.
+    #[default]
+    Background,
+    /// the body of the glyphs.
+    Fill,
+    /// the border drawn around the glyphs.
+    Outline,
+    /// a blend of the fill with the outline or the background.
+    Antialias,
+}
+
+/// Roles of the palette entries used by an object, with how much of the fill each one holds.
+#[derive(Debug, Clone)]
+pub struct PaletteClasses {
+    fill: Option<u8>,
+    roles: [PaletteRole; 256],
+    /// from 0, nothing of the fill, to 255, the fill itself
+    ink: [u8; 256],
+}
+
+impl PaletteClasses {
+    /// classify the entries by their luminance, transparency and how many pixels of the object
+    /// use them.
+    ///
+    /// the fill is the most used bright opaque entry, or the most used opaque entry if none is
+    /// bright, and the outline the most used opaque entry with a contrasting luminance.
+    pub fn new(pixels: &[u8], palette: &pgs::PDS) -> Self {
+        let mut counts = [0usize; 256];
+        for &idx in pixels {
+            counts[idx as usize] += 1;
+        }
+        let entries = &palette.entries;
+        let used = |idx: &usize| counts[*idx] > 0 && entries[*idx].transparency > 0;
+        let opaque = (0..256)
+            .filter(used)
+            .filter(|&idx| entries[idx].transparency >= MIN_OPAQUE)
+            .collect::<Vec<_>>();
+        let most_used = |candidates: &mut dyn Iterator<Item = usize>| {
+            candidates.max_by_key(|&idx| (counts[idx], std::cmp::Reverse(idx)))
+        };
+
+        let fill = most_used(
+            &mut opaque
+                .iter()
+                .copied()
+                .filter(|&idx| entries[idx].luminance >= MIN_FILL_LUMINANCE),
+        )
+        .or_else(|| most_used(&mut opaque.iter().copied()));
+        let outline = fill.and_then(|fill| {
+            let fill = entries[fill].luminance;
+            most_used(
+                &mut opaque
+                    .iter()
+                    .copied()
+                    .filter(|&idx| entries[idx].luminance.abs_diff(fill) >= MIN_CONTRAST),
+            )
+        });
+
+        let mut roles = [PaletteRole::Background; 256];
+        let mut ink = [0u8; 256];
+        let Some(fill) = fill else {
+            return Self {
+                fill: None,
+                roles,
+                ink,
+            };
+        };
+        let fill_luminance = f32::from(entries[fill].luminance);
+        for idx in (0..256).filter(used) {
+            let entry = &entries[idx];
+            let luminance = f32::from(entry.luminance);
+            let alpha = f32::from(entry.transparency) / 255.0;
+            let (role, share) = if idx == fill {
+                (PaletteRole::Fill, 1.0)
+            } else if Some(idx) == outline {
+                (PaletteRole::Outline, 0.0)
+            } else {
+                // how close the luminance is to the fill, on the way from the outline or
+                // from the opposite end of the range when there is no outline
+                let far = match outline {
+                    Some(outline) => f32::from(entries[outline].luminance),
+                    None if fill_luminance >= 128.0 => 0.0,
+                    None => 255.0,
+                };
+                let span = fill_luminance - far;
+                let share = if span == 0.0 {
+                    0.0
+                } else {
+                    ((luminance - far) / span).clamp(0.0, 1.0)
+                };
+                (PaletteRole::Antialias, share)
+            };
+            roles[idx] = role;
+            ink[idx] = (share * alpha * 255.0).round() as u8;
+        }
+        Self {
+            fill: Some(fill as u8),
+            roles,
+            ink,
+        }
+    }
+
+    /// the entry drawing the fill, `None` when the object has no opaque entry.
+    pub fn fill(&self) -> Option<u8> {
+        self.fill
+    }
+
+    pub fn role(&self, idx: u8) -> PaletteRole {
+        self.roles[idx as usize]
+    }
+
+    /// how much of the fill the entry holds, from 0 to 255, blended with its transparency.
+    pub fn ink(&self, idx: u8) -> u8 {
+        self.ink[idx as usize]
+    }
+
+    /// render the indexed pixels as dark fill on a white background.
+    ///
+    /// the transparency of the entries is kept, so fades are still visible, with the
+    /// background pixels left fully transparent.
+    pub fn render(&self, pixels: &[u8], width: u32, height: u32, palette: &pgs::PDS) -> Bitmap {
+        let mut output = Vec::with_capacity(pixels.len() * 4);
+        for &idx in pixels {
+            let gray = 255 - self.ink(idx);
+            let alpha = match self.role(idx) {
+                PaletteRole::Background => 0,
+                _ => palette.entries[idx as usize].transparency,
+            };
+            output.extend([gray, gray, gray, alpha]);
+        }
+        Bitmap {
+            width,
+            height,
+            pixels: output,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_palette_classes() {
+        let entry = |entry_id, luminance, transparency| pgs::PaletteEntry {
+            entry_id,
+            luminance,
+            color_diff_red: 128,
+            color_diff_blue: 128,
+            transparency,
+        };
+        let mut palette = pgs::PDS::new(pgs::Header { pts: 0, dts: 0 }, 0, 0);
+        palette.set_entry(entry(1, 235, 255)); // fill
+        palette.set_entry(entry(2, 16, 255)); // outline
+        palette.set_entry(entry(3, 126, 255)); // between the fill and the outline
+        palette.set_entry(entry(4, 235, 128)); // fill blended with the background
+        palette.set_entry(entry(5, 200, 255)); // bright but rarely used
+        let pixels = [0, 0, 1, 1, 1, 1, 2, 2, 2, 3, 4, 5];
+        let classes = PaletteClasses::new(&pixels, &palette);
+
+        let roles = (0..6).map(|idx| classes.role(idx)).collect::<Vec<_>>();
+        use PaletteRole::*;
+        assert_eq!(
+            roles,
+            [Background, Fill, Outline, Antialias, Antialias, Antialias]
+        );
+        assert_eq!(classes.fill(), Some(1));
+        assert_eq!(classes.ink(1), 255);
+        assert_eq!(classes.ink(2), 0);
+        assert_eq!(classes.ink(3), 128);
+        assert_eq!(classes.ink(4), 128);
+
+        let bitmap = classes.render(&pixels, 12, 1, &palette);
+        assert_eq!(&bitmap.pixels[..4], &[255, 255, 255, 0]);
+        assert_eq!(&bitmap.pixels[8..12], &[0, 0, 0, 255]);
+        assert_eq!(&bitmap.pixels[24..28], &[255, 255, 255, 255]);
+    }
+}
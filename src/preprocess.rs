@@ -0,0 +1,470 @@
+//! Clean up subtitle bitmaps before OCR.
+//!
+//! pgs bitmaps have a transparent background, colored text and thick outlines, none of which
+//! OCR engines handle well. A pipeline of [`Preprocess`] steps, usually run through
+//! [`Preprocessed`], turns them into something closer to a scanned page.
+//!
+//! The steps only see the rendered colors, not the palette, so telling the text apart from its
+//! outline is a color heuristic here. [`crate::Rendering::Ocr`] does it from the palette
+//! entries instead.
+use std::str::FromStr;
+
+use crate::{Bitmap, OcrEngine, OcrResult, Rendering, Result, MIN_OPAQUE};
+
+/// A step applied to a bitmap before OCR.
+///
+/// Every step takes and returns an RGBA bitmap of the same size, except [`Preprocess::Upscale`]
+/// and [`Preprocess::Pad`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preprocess {
+    /// blend the bitmap onto an opaque background of this color.
+    Flatten([u8; 3]),
+    /// replace the colors by their luma.
+    Grayscale,
+    /// make the pixels guessed to be the outline transparent, hoping to keep only the fill.
+    ///
+    /// a color heuristic: the fill is taken as the most used color clearly brighter than the
+    /// darkest opaque one, and the outline as the colors closer to that darkest color than to
+    /// the fill. dark text on a light outline loses its fill instead.
+    StripOutline,
+    /// invert the colors, turning light text on a dark background into dark text on a light
+    /// one.
+    Invert,
+    /// scale the bitmap up by a whole factor, up to 8, so it is at least this tall.
+    Upscale(u32),
+    /// add a margin of this many pixels on every side, filled with the border color.
+    Pad(u32),
+    /// turn every pixel black or white, with the threshold placed between the colors of the
+    /// bitmap by Otsu's method on their luma, so what ends up as text depends on the colors
+    /// rather than on what the palette entries are used for.
+    Binarize,
+}
+
+impl Preprocess {
+    pub const DEFAULT_UPSCALE_HEIGHT: u32 = 100;
+    pub const DEFAULT_PADDING: u32 = 10;
+
+    /// steps that work well with tesseract for bitmaps drawn with `rendering`: dark text
+    /// without outline on a white page.
+    ///
+    /// [`Rendering::Ocr`] bitmaps already are dark fill without outline, on a transparent white
+    /// background, so they only need flattening onto white.
+    pub fn recommended(rendering: Rendering) -> Vec<Preprocess> {
+        let colors = match rendering {
+            Rendering::Palette => vec![
+                Preprocess::StripOutline,
+                Preprocess::Flatten([0, 0, 0]),
+                Preprocess::Grayscale,
+                Preprocess::Invert,
+            ],
+            Rendering::Ocr => vec![Preprocess::Flatten([255, 255, 255])],
+        };
+        let mut steps = colors;
+        steps.extend([
+            Preprocess::Upscale(Self::DEFAULT_UPSCALE_HEIGHT),
+            Preprocess::Pad(Self::DEFAULT_PADDING),
+            Preprocess::Binarize,
+        ]);
+        steps
+    }
+
+    pub fn apply(&self, bitmap: &Bitmap) -> Bitmap {
+        match *self {
+            Preprocess::Flatten(background) => flatten(bitmap, background),
+            Preprocess::Grayscale => map_pixels(bitmap, |[r, g, b, a]| {
+                let y = luma(r, g, b);
+                [y, y, y, a]
+            }),
+            Preprocess::StripOutline => strip_outline(bitmap),
+            Preprocess::Invert => map_pixels(bitmap, |[r, g, b, a]| [255 - r, 255 - g, 255 - b, a]),
+            Preprocess::Upscale(min_height) => upscale(bitmap, min_height),
+            Preprocess::Pad(margin) => pad(bitmap, margin),
+            Preprocess::Binarize => binarize(bitmap),
+        }
+    }
+}
+
+impl FromStr for Preprocess {
+    type Err = String;
+
+    /// accepts the step names in kebab case, with an optional argument after `=`: `flatten`,
+    /// `flatten=white` or `flatten=RRGGBB`, `grayscale`, `strip-outline`, `invert`, `upscale` or
+    /// `upscale=HEIGHT`, `pad` or `pad=PIXELS` and `binarize`.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (name, argument) = match s.split_once('=') {
+            Some((name, argument)) => (name, Some(argument)),
+            None => (s, None),
+        };
+        let number = |default: u32| match argument {
+            Some(argument) => argument
+                .parse()
+                .map_err(|_| format!("invalid {name} argument '{argument}'")),
+            None => Ok(default),
+        };
+        let step = match name {
+            "flatten" => Preprocess::Flatten(match argument {
+                None | Some("black") => [0, 0, 0],
+                Some("white") => [255, 255, 255],
+                Some(hex) => {
+                    parse_hex_color(hex).ok_or_else(|| format!("invalid flatten color '{hex}'"))?
+                }
+            }),
+            "grayscale" => Preprocess::Grayscale,
+            "strip-outline" => Preprocess::StripOutline,
+            "invert" => Preprocess::Invert,
+            "upscale" => Preprocess::Upscale(number(Self::DEFAULT_UPSCALE_HEIGHT)?),
+            "pad" => Preprocess::Pad(number(Self::DEFAULT_PADDING)?),
+            "binarize" => Preprocess::Binarize,
+            _ => return Err(format!("unknown preprocessing step '{name}'")),
+        };
+        match (step, argument) {
+            (
+                Preprocess::Grayscale
+                | Preprocess::StripOutline
+                | Preprocess::Invert
+                | Preprocess::Binarize,
+                Some(_),
+            ) => Err(format!("{name} does not take an argument")),
+            _ => Ok(step),
+        }
+    }
+}
+
+fn parse_hex_color(hex: &str) -> Option<[u8; 3]> {
+    let hex = hex.strip_prefix('#').unwrap_or(hex);
+    if hex.len() != 6 {
+        return None;
+    }
+    let component = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    Some([component(0)?, component(2)?, component(4)?])
+}
+
+/// run the steps in order.
+pub fn preprocess(bitmap: &Bitmap, steps: &[Preprocess]) -> Bitmap {
+    let mut bitmap = bitmap.clone();
+    for step in steps {
+        bitmap = step.apply(&bitmap);
+    }
+    bitmap
+}
+
+/// An [`OcrEngine`] that preprocesses the bitmaps before handing them to another engine.
+pub struct Preprocessed<E> {
+    steps: Vec<Preprocess>,
+    engine: E,
+}
+
+impl<E> Preprocessed<E> {
+    pub fn new(steps: Vec<Preprocess>, engine: E) -> Self {
+        Self { steps, engine }
+    }
+}
+
+impl<E: OcrEngine> OcrEngine for Preprocessed<E> {
+    fn recognize(&mut self, bitmap: &Bitmap) -> Result<OcrResult> {
+        if self.steps.is_empty() {
+            return self.engine.recognize(bitmap);
+        }
+        self.engine.recognize(&preprocess(bitmap, &self.steps))
+    }
+
+    fn recognize_line(&mut self, bitmap: &Bitmap) -> Result<OcrResult> {
+        if self.steps.is_empty() {
+            return self.engine.recognize_line(bitmap);
+        }
+        self.engine.recognize_line(&preprocess(bitmap, &self.steps))
+    }
+}
+
+/// BT.601 luma, the same weights the pgs palette is defined with.
+fn luma(r: u8, g: u8, b: u8) -> u8 {
+    ((299 * u32::from(r) + 587 * u32::from(g) + 114 * u32::from(b)) / 1000) as u8
+}
+
+fn map_pixels(bitmap: &Bitmap, f: impl Fn([u8; 4]) -> [u8; 4]) -> Bitmap {
+    Bitmap {
+        width: bitmap.width,
+        height: bitmap.height,
+        pixels: bitmap
+            .pixels
+            .chunks_exact(4)
+            .flat_map(|pixel| f([pixel[0], pixel[1], pixel[2], pixel[3]]))
+            .collect(),
+    }
+}
+
+fn flatten(bitmap: &Bitmap, background: [u8; 3]) -> Bitmap {
+    map_pixels(bitmap, |[r, g, b, a]| {
+        let blend = |color: u8, background: u8| {
+            let a = u32::from(a);
+            ((u32::from(color) * a + u32::from(background) * (255 - a)) / 255) as u8
+        };
+        [
+            blend(r, background[0]),
+            blend(g, background[1]),
+            blend(b, background[2]),
+            255,
+        ]
+    })
+}
+
+fn strip_outline(bitmap: &Bitmap) -> Bitmap {
+    // outlines are at least this much darker than the fill
+    const MIN_CONTRAST: u8 = 32;
+
+    let mut colors = std::collections::HashMap::<[u8; 3], usize>::new();
+    for pixel in bitmap.pixels.chunks_exact(4) {
+        if pixel[3] >= MIN_OPAQUE {
+            *colors.entry([pixel[0], pixel[1], pixel[2]]).or_default() += 1;
+        }
+    }
+    let Some(darkest) = colors.keys().map(|&[r, g, b]| luma(r, g, b)).min() else {
+        return bitmap.clone();
+    };
+    let fill = colors
+        .iter()
+        .filter(|&(&[r, g, b], _)| luma(r, g, b) >= darkest.saturating_add(MIN_CONTRAST))
+        .max_by_key(|&(color, count)| (*count, *color))
+        .map(|(&[r, g, b], _)| luma(r, g, b));
+    let Some(fill) = fill else {
+        // a single shade, there is no outline
+        return bitmap.clone();
+    };
+    let threshold = darkest + (fill - darkest) / 2;
+    map_pixels(bitmap, |[r, g, b, a]| {
+        if a >= MIN_OPAQUE && luma(r, g, b) < threshold {
+            [0, 0, 0, 0]
+        } else {
+            [r, g, b, a]
+        }
+    })
+}
+
+fn upscale(bitmap: &Bitmap, min_height: u32) -> Bitmap {
+    const MAX_FACTOR: u32 = 8;
+
+    if bitmap.height == 0 || bitmap.height >= min_height {
+        return bitmap.clone();
+    }
+    let factor = min_height.div_ceil(bitmap.height).min(MAX_FACTOR);
+    let width = bitmap.width * factor;
+    let mut pixels = Vec::with_capacity((width * bitmap.height * factor * 4) as usize);
+    for row in bitmap.pixels.chunks_exact(bitmap.width as usize * 4) {
+        let scaled = row
+            .chunks_exact(4)
+            .flat_map(|pixel| std::iter::repeat_n(pixel, factor as usize).flatten())
+            .copied()
+            .collect::<Vec<_>>();
+        for _ in 0..factor {
+            pixels.extend_from_slice(&scaled);
+        }
+    }
+    Bitmap {
+        width,
+        height: bitmap.height * factor,
+        pixels,
+    }
+}
+
+fn pad(bitmap: &Bitmap, margin: u32) -> Bitmap {
+    let border = border_color(bitmap);
+    let width = bitmap.width + 2 * margin;
+    let height = bitmap.height + 2 * margin;
+    let mut pixels = border.repeat((width * height) as usize);
+    for (y, row) in bitmap
+        .pixels
+        .chunks_exact(bitmap.width as usize * 4)
+        .enumerate()
+    {
+        let offset = ((y as u32 + margin) * width + margin) as usize * 4;
+        pixels[offset..offset + row.len()].copy_from_slice(row);
+    }
+    Bitmap {
+        width,
+        height,
+        pixels,
+    }
+}
+
+/// the most common color along the edges of the bitmap, transparent for an empty bitmap.
+fn border_color(bitmap: &Bitmap) -> [u8; 4] {
+    let (width, height) = (bitmap.width, bitmap.height);
+    let pixel = |x: u32, y: u32| {
+        let offset = ((y * width + x) * 4) as usize;
+        [
+            bitmap.pixels[offset],
+            bitmap.pixels[offset + 1],
+            bitmap.pixels[offset + 2],
+            bitmap.pixels[offset + 3],
+        ]
+    };
+    let mut counts = std::collections::HashMap::<[u8; 4], usize>::new();
+    if width > 0 && height > 0 {
+        for x in 0..width {
+            *counts.entry(pixel(x, 0)).or_default() += 1;
+            *counts.entry(pixel(x, height - 1)).or_default() += 1;
+        }
+        for y in 0..height {
+            *counts.entry(pixel(0, y)).or_default() += 1;
+            *counts.entry(pixel(width - 1, y)).or_default() += 1;
+        }
+    }
+    counts
+        .into_iter()
+        .max_by_key(|&(color, count)| (count, color))
+        .map(|(color, _)| color)
+        .unwrap_or_default()
+}
+
+fn binarize(bitmap: &Bitmap) -> Bitmap {
+    // transparent pixels count as black, like after flattening onto black
+    let value = |[r, g, b, a]: [u8; 4]| (u32::from(luma(r, g, b)) * u32::from(a) / 255) as u8;
+    let mut histogram = [0u64; 256];
+    for pixel in bitmap.pixels.chunks_exact(4) {
+        histogram[value([pixel[0], pixel[1], pixel[2], pixel[3]]) as usize] += 1;
+    }
+    let threshold = otsu_threshold(&histogram);
+    map_pixels(bitmap, |pixel| {
+        let v = if value(pixel) > threshold { 255 } else { 0 };
+        [v, v, v, 255]
+    })
+}
+
+/// the threshold that best separates the histogram in two classes.
+///
+/// since the pixels come from a small palette the histogram has few values and the threshold
+/// always falls between two of them.
+fn otsu_threshold(histogram: &[u64; 256]) -> u8 {
+    let total = histogram.iter().sum::<u64>() as f64;
+    let sum = histogram
+        .iter()
+        .enumerate()
+        .map(|(value, &count)| value as f64 * count as f64)
+        .sum::<f64>();
+    let mut best = (0.0, 0);
+    let (mut background_count, mut background_sum) = (0.0, 0.0);
+    for (value, &count) in histogram.iter().enumerate() {
+        background_count += count as f64;
+        background_sum += value as f64 * count as f64;
+        let foreground_count = total - background_count;
+        if background_count == 0.0 || foreground_count == 0.0 {
+            continue;
+        }
+        let background_mean = background_sum / background_count;
+        let foreground_mean = (sum - background_sum) / foreground_count;
+        let variance =
+            background_count * foreground_count * (background_mean - foreground_mean).powi(2);
+        if variance > best.0 {
+            best = (variance, value as u8);
+        }
+    }
+    best.1
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_preprocess() {
+        const CLEAR: [u8; 4] = [0, 0, 0, 0];
+        const FILL: [u8; 4] = [240, 240, 40, 255];
+        const OUTLINE: [u8; 4] = [16, 16, 16, 255];
+        // a yellow stroke with a black outline on a transparent background
+        let rows = [
+            [CLEAR, OUTLINE, OUTLINE, OUTLINE, CLEAR],
+            [CLEAR, OUTLINE, FILL, OUTLINE, CLEAR],
+            [CLEAR, OUTLINE, FILL, OUTLINE, CLEAR],
+            [CLEAR, OUTLINE, OUTLINE, OUTLINE, CLEAR],
+        ];
+        let bitmap = Bitmap {
+            width: 5,
+            height: 4,
+            pixels: rows.iter().flatten().flatten().copied().collect(),
+        };
+
+        let steps = "strip-outline,flatten,grayscale,invert,upscale=8,pad=1,binarize"
+            .split(',')
+            .map(str::parse)
+            .collect::<std::result::Result<Vec<Preprocess>, _>>()
+            .unwrap();
+        let mut recommended = Preprocess::recommended(Rendering::Palette);
+        recommended[4] = Preprocess::Upscale(8);
+        recommended[5] = Preprocess::Pad(1);
+        assert_eq!(steps, recommended);
+
+        let output = preprocess(&bitmap, &steps);
+        assert_eq!((output.width, output.height), (12, 10));
+        let black = output
+            .pixels
+            .chunks_exact(4)
+            .enumerate()
+            .filter(|(_, pixel)| pixel[..3] == [0, 0, 0])
+            .map(|(i, _)| (i as u32 % output.width, i as u32 / output.width))
+            .collect::<Vec<_>>();
+        // only the fill is left, as black on white
+        assert_eq!(
+            black,
+            [
+                (5, 3),
+                (6, 3),
+                (5, 4),
+                (6, 4),
+                (5, 5),
+                (6, 5),
+                (5, 6),
+                (6, 6)
+            ]
+        );
+        assert!(output.pixels.chunks_exact(4).all(|pixel| pixel[3] == 255));
+
+        assert_eq!(
+            "flatten=#ff8000".parse(),
+            Ok(Preprocess::Flatten([255, 128, 0]))
+        );
+        assert!("invert=1".parse::<Preprocess>().is_err());
+    }
+
+    #[test]
+    fn test_recommended_with_ocr_rendering() {
+        let entry = |entry_id, luminance| pgs::PaletteEntry {
+            entry_id,
+            luminance,
+            color_diff_red: 128,
+            color_diff_blue: 128,
+            transparency: 255,
+        };
+        let mut palette = pgs::PDS::new(pgs::Header { pts: 0, dts: 0 }, 0, 0);
+        palette.set_entry(entry(1, 235)); // fill
+        palette.set_entry(entry(2, 16)); // outline
+
+        // the same stroke as above, as palette indices
+        let pixels = [
+            [0, 2, 2, 2, 0],
+            [0, 2, 1, 2, 0],
+            [0, 2, 1, 2, 0],
+            [0, 2, 2, 2, 0],
+        ]
+        .concat();
+        let classes = crate::palette::PaletteClasses::new(&pixels, &palette);
+        let bitmap = classes.render(&pixels, 5, 4, &palette);
+
+        let mut steps = Preprocess::recommended(Rendering::Ocr);
+        let len = steps.len();
+        steps[len - 3] = Preprocess::Upscale(8);
+        steps[len - 2] = Preprocess::Pad(1);
+        let output = preprocess(&bitmap, &steps);
+        let black = output
+            .pixels
+            .chunks_exact(4)
+            .enumerate()
+            .filter(|(_, pixel)| pixel[..3] == [0, 0, 0])
+            .map(|(i, _)| (i as u32 % output.width, i as u32 / output.width))
+            .collect::<Vec<_>>();
+        // the fill is kept, not the outline around it
+        assert_eq!(black.len(), 8);
+        assert!(black
+            .iter()
+            .all(|&(x, y)| (5..7).contains(&x) && (3..7).contains(&y)));
+    }
+}
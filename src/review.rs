@@ -0,0 +1,241 @@
+//! List the subtitles the OCR engine is unsure about so they can be reviewed by hand.
+//!
+//! Every subtitle with a mean confidence below a threshold goes into a report, written as JSON
+//! or CSV, with its times, text, the words below the threshold and optionally a png of its
+//! bitmap.
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::{srt::srt_duration_display, Bitmap, FileWriter, OcrWord, Result, TextSubtitle};
+
+/// Format of the written [`ReviewReport`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    #[default]
+    Json,
+    /// one row per subtitle, with the low confidence words joined by spaces.
+    Csv,
+}
+
+impl ReportFormat {
+    /// `.csv` files are written as CSV and anything else as JSON.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("csv") => Self::Csv,
+            _ => Self::Json,
+        }
+    }
+}
+
+/// A subtitle below the confidence threshold.
+///
+/// entries are identified by their times, the subtitles are merged or split into cues by the
+/// writers so their position in the output is not known here.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReviewEntry {
+    /// in the srt time format.
+    pub begin: String,
+    /// not set when the end is unknown, for a subtitle still shown at the end of the stream.
+    pub end: Option<String>,
+    pub text: String,
+    pub confidence: f32,
+    /// the words below the threshold.
+    pub words: Vec<OcrWord>,
+    /// path of the exported bitmap, if any.
+    pub image: Option<PathBuf>,
+}
+
+/// Collects the subtitles with a low OCR confidence.
+///
+/// subtitles without a confidence, because the engine does not report one, are never listed.
+#[derive(Debug, Clone)]
+pub struct ReviewReport {
+    min_confidence: f32,
+    image_dir: Option<PathBuf>,
+    file_writer: FileWriter,
+    entries: Vec<ReviewEntry>,
+}
+
+impl ReviewReport {
+    pub const DEFAULT_MIN_CONFIDENCE: f32 = 80.0;
+
+    /// `min_confidence` goes from 0 to 100.
+    pub fn new(min_confidence: f32) -> Self {
+        Self {
+            min_confidence,
+            image_dir: None,
+            file_writer: Default::default(),
+            entries: Vec::new(),
+        }
+    }
+
+    /// export the bitmaps of the listed subtitles as png files into this directory, which is
+    /// created if missing.
+    pub fn image_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.image_dir = Some(dir.into());
+        self
+    }
+
+    /// how the png files are written, defaults to [`FileWriter::default`].
+    pub fn file_writer(mut self, file_writer: FileWriter) -> Self {
+        self.file_writer = file_writer;
+        self
+    }
+
+    pub fn entries(&self) -> &[ReviewEntry] {
+        &self.entries
+    }
+
+    /// check a recognized subtitle, listing it if its confidence is too low.
+    pub fn check(&mut self, subtitle: &TextSubtitle, bitmap: &Bitmap) -> Result<()> {
+        let Some(confidence) = subtitle.confidence else {
+            return Ok(());
+        };
+        if confidence >= self.min_confidence {
+            return Ok(());
+        }
+        tracing::warn!(
+            begin = ?subtitle.range.begin,
+            confidence,
+            "low OCR confidence: {:?}",
+            subtitle.text.trim()
+        );
+
+        let image = match &self.image_dir {
+            Some(dir) => {
+                std::fs::create_dir_all(dir)?;
+                // numbered like the entries of the report
+                let path = dir.join(format!("{:04}.png", self.entries.len() + 1));
+                let mut png = Vec::new();
+                bitmap.write_png(&mut png)?;
+                self.file_writer.write(&path, &png)?;
+                Some(path)
+            }
+            None => None,
+        };
+        self.entries.push(ReviewEntry {
+            begin: srt_duration_display(subtitle.range.begin).to_string(),
+            end: (subtitle.range.end >= subtitle.range.begin)
+                .then(|| srt_duration_display(subtitle.range.end).to_string()),
+            text: subtitle.text.trim().to_string(),
+            confidence,
+            words: subtitle
+                .words
+                .iter()
+                .filter(|word| word.confidence.is_some_and(|c| c < self.min_confidence))
+                .cloned()
+                .collect(),
+            image,
+        });
+        Ok(())
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W, format: ReportFormat) -> std::io::Result<()> {
+        match format {
+            ReportFormat::Json => {
+                serde_json::to_writer_pretty(&mut writer, &self.entries)?;
+                writeln!(writer)?;
+            }
+            ReportFormat::Csv => {
+                writeln!(writer, "begin,end,confidence,text,words,image")?;
+                for entry in &self.entries {
+                    let words = entry
+                        .words
+                        .iter()
+                        .map(|word| word.text.as_str())
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    let image = entry
+                        .image
+                        .as_ref()
+                        .map(|image| image.display().to_string())
+                        .unwrap_or_default();
+                    writeln!(
+                        writer,
+                        "{},{},{:.1},{},{},{}",
+                        csv_field(&entry.begin),
+                        csv_field(entry.end.as_deref().unwrap_or_default()),
+                        entry.confidence,
+                        csv_field(&entry.text),
+                        csv_field(&words),
+                        csv_field(&image)
+                    )?;
+                }
+            }
+        }
+        writer.flush()
+    }
+}
+
+/// quote the field if it has separators, quotes or newlines.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::TimeRange;
+
+    #[test]
+    fn test_review_report() {
+        let subtitle = |text: &str, confidence: Option<f32>| TextSubtitle {
+            range: TimeRange::new(Duration::from_millis(1500), Duration::from_secs(3)),
+            text: format!("{text}\n"),
+            placement: Default::default(),
+            color: None,
+            forced: false,
+            confidence,
+            words: text
+                .split_whitespace()
+                .map(|word| OcrWord {
+                    text: word.to_string(),
+                    confidence: confidence.map(|c| if word == "He" { c } else { 95.0 }),
+                })
+                .collect(),
+        };
+        let bitmap = Bitmap::default();
+
+        let mut report = ReviewReport::new(80.0);
+        report
+            .check(&subtitle("Fine", Some(90.0)), &bitmap)
+            .unwrap();
+        report.check(&subtitle("Unknown", None), &bitmap).unwrap();
+        report
+            .check(&subtitle("He said, \"hi\"", Some(50.0)), &bitmap)
+            .unwrap();
+        // still shown at the end of the stream
+        let open = TextSubtitle {
+            range: TimeRange::new(Duration::from_secs(5), Duration::ZERO),
+            ..subtitle("Open", Some(40.0))
+        };
+        report.check(&open, &bitmap).unwrap();
+        assert_eq!(report.entries().len(), 2);
+
+        let mut csv = Vec::new();
+        report.write(&mut csv, ReportFormat::Csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "begin,end,confidence,text,words,image\n\
+            \"00:00:01,500\",\"00:00:03,000\",50.0,\"He said, \"\"hi\"\"\",He,\n\
+            \"00:00:05,000\",,40.0,Open,,\n"
+        );
+
+        let mut json = Vec::new();
+        report.write(&mut json, ReportFormat::Json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json[0]["begin"], "00:00:01,500");
+        assert_eq!(json[0]["words"][0]["text"], "He");
+        assert!(json[1]["end"].is_null());
+    }
+}
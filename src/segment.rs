@@ -0,0 +1,231 @@
+//! Split multi-line subtitle bitmaps into lines before OCR.
+//!
+//! A subtitle often holds two lines, sometimes with a different alignment or a leading dash for
+//! two speakers. Recognizing each line on its own, as a single line of text, is more reliable
+//! than letting the engine find the layout of the whole bitmap.
+use crate::{Bitmap, OcrEngine, OcrLine, OcrResult, Result, MIN_OPAQUE};
+
+/// bands of rows shorter than this fraction of the tallest band are accents or punctuation
+/// split off a line, not lines of their own.
+const MIN_LINE_FRACTION: f32 = 0.4;
+/// rows of background kept above and below every line.
+const LINE_MARGIN: u32 = 4;
+
+/// Area of a bitmap holding a line of text.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LineRegion {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// find the lines of text by the horizontal projection of the opaque pixels: runs of rows
+/// with opaque pixels, separated by empty rows.
+///
+/// every line is trimmed horizontally to its opaque pixels and keeps a small margin of empty
+/// rows, without reaching into the lines next to it.
+pub fn text_lines(bitmap: &Bitmap) -> Vec<LineRegion> {
+    let width = bitmap.width as usize;
+    if width == 0 {
+        return Vec::new();
+    }
+    let opaque_rows = bitmap
+        .pixels
+        .chunks_exact(width * 4)
+        .map(|row| row.chunks_exact(4).any(|pixel| pixel[3] >= MIN_OPAQUE))
+        .collect::<Vec<_>>();
+
+    // runs of opaque rows, as [begin, end)
+    let mut bands: Vec<(u32, u32)> = Vec::new();
+    for (y, &opaque) in opaque_rows.iter().enumerate() {
+        let y = y as u32;
+        match bands.last_mut() {
+            Some((_, end)) if opaque && *end == y => *end = y + 1,
+            _ if opaque => bands.push((y, y + 1)),
+            _ => {}
+        }
+    }
+
+    // merge the small bands into the closest band
+    let tallest = bands.iter().map(|(begin, end)| end - begin).max();
+    let min_height = tallest.unwrap_or_default() as f32 * MIN_LINE_FRACTION;
+    while bands.len() > 1 {
+        let Some(small) = bands
+            .iter()
+            .position(|(begin, end)| ((end - begin) as f32) < min_height)
+        else {
+            break;
+        };
+        let gap_before = (small > 0).then(|| bands[small].0 - bands[small - 1].1);
+        let gap_after = bands.get(small + 1).map(|next| next.0 - bands[small].1);
+        let other = match (gap_before, gap_after) {
+            (Some(before), Some(after)) if after < before => small + 1,
+            (Some(_), _) => small - 1,
+            (None, _) => small + 1,
+        };
+        let (first, second) = (small.min(other), small.max(other));
+        bands[first].1 = bands[second].1;
+        bands.remove(second);
+    }
+
+    let row = |y: u32| &bitmap.pixels[y as usize * width * 4..(y as usize + 1) * width * 4];
+    bands
+        .iter()
+        .enumerate()
+        .map(|(i, &(begin, end))| {
+            // the gaps between lines are split between them
+            let top_limit = match i {
+                0 => 0,
+                _ => begin - (begin - bands[i - 1].1) / 2,
+            };
+            let bottom_limit = match bands.get(i + 1) {
+                Some(next) => end + (next.0 - end).div_ceil(2),
+                None => bitmap.height,
+            };
+            let top = begin.saturating_sub(LINE_MARGIN).max(top_limit);
+            let bottom = (end + LINE_MARGIN).min(bottom_limit);
+            let (mut left, mut right) = (u32::MAX, 0);
+            for y in begin..end {
+                let opaque = row(y)
+                    .chunks_exact(4)
+                    .enumerate()
+                    .filter(|(_, pixel)| pixel[3] >= MIN_OPAQUE)
+                    .map(|(x, _)| x as u32);
+                for x in opaque {
+                    left = left.min(x);
+                    right = right.max(x + 1);
+                }
+            }
+            LineRegion {
+                x: left,
+                y: top,
+                width: right - left,
+                height: bottom - top,
+            }
+        })
+        .collect()
+}
+
+/// An [`OcrEngine`] that recognizes every line of the bitmaps on its own, with
+/// [`OcrEngine::recognize_line`], and joins their text with newlines.
+///
+/// the lines of the result are the regions found by [`text_lines`].
+pub struct LineSegmented<E> {
+    engine: E,
+}
+
+impl<E> LineSegmented<E> {
+    pub fn new(engine: E) -> Self {
+        Self { engine }
+    }
+}
+
+impl<E: OcrEngine> OcrEngine for LineSegmented<E> {
+    fn recognize(&mut self, bitmap: &Bitmap) -> Result<OcrResult> {
+        let mut lines = Vec::new();
+        for region in text_lines(bitmap) {
+            let crop = bitmap.sub_image(region.x, region.y, region.width, region.height);
+            let result = self.engine.recognize_line(&crop)?;
+            // a single line was asked for, but engines may still break it up
+            let text = result.text.split_whitespace().collect::<Vec<_>>().join(" ");
+            if text.is_empty() {
+                continue;
+            }
+            lines.push(OcrLine {
+                text,
+                x: region.x,
+                y: region.y,
+                width: region.width,
+                height: region.height,
+                confidence: result.confidence,
+                words: result
+                    .lines
+                    .into_iter()
+                    .flat_map(|line| line.words)
+                    .collect(),
+            });
+        }
+        let confidence = crate::ocr::mean_confidence(lines.iter().map(|line| line.confidence));
+        let mut text = String::new();
+        for line in &lines {
+            text.push_str(&line.text);
+            text.push('\n');
+        }
+        Ok(OcrResult {
+            text,
+            lines,
+            confidence,
+        })
+    }
+
+    fn recognize_line(&mut self, bitmap: &Bitmap) -> Result<OcrResult> {
+        self.engine.recognize_line(bitmap)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_text_lines() {
+        // an accent over the first line, which is indented, and a second line
+        let rows = [
+            "..........",
+            "....#.....",
+            "..........",
+            "...####...",
+            "...####...",
+            "...####...",
+            "...####...",
+            "..........",
+            "..........",
+            "..........",
+            ".#######..",
+            ".#######..",
+            ".#######..",
+            ".#######..",
+            "..........",
+        ];
+        let bitmap = Bitmap {
+            width: 10,
+            height: rows.len() as u32,
+            pixels: rows
+                .iter()
+                .flat_map(|row| row.chars())
+                .flat_map(|c| match c {
+                    '#' => [255, 255, 255, 255],
+                    _ => [0, 0, 0, 0],
+                })
+                .collect(),
+        };
+        let lines = text_lines(&bitmap);
+        assert_eq!(
+            lines,
+            [
+                LineRegion {
+                    x: 3,
+                    y: 0,
+                    width: 4,
+                    height: 9,
+                },
+                LineRegion {
+                    x: 1,
+                    y: 9,
+                    width: 7,
+                    height: 6,
+                },
+            ]
+        );
+
+        let result = LineSegmented::new(crate::MockOcr)
+            .recognize(&bitmap)
+            .unwrap();
+        assert_eq!(
+            result.text,
+            "4x9 bitmap, 17 opaque\n7x6 bitmap, 28 opaque\n"
+        );
+        assert_eq!(result.lines.len(), 2);
+    }
+}
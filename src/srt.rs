@@ -0,0 +1,70 @@
+use std::{io::Write, time::Duration};
+
+use crate::{
+    cue::{Cue, CueMerger},
+    SubtitleWriter, TextSubtitle,
+};
+
+pub fn srt_duration_display(duration: Duration) -> impl std::fmt::Display {
+    struct SrtDurationDisplay(Duration);
+
+    impl std::fmt::Display for SrtDurationDisplay {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let total_secs = self.0.as_secs();
+            let hours = total_secs / 3600;
+            let minutes = (total_secs / 60) % 60;
+            let seconds = total_secs % 60;
+            let millis = self.0.subsec_millis();
+            write!(f, "{hours:02}:{minutes:02}:{seconds:02},{millis:03}")
+        }
+    }
+
+    SrtDurationDisplay(duration)
+}
+
+/// Writes text subtitles in the srt format as they are pushed.
+pub struct SrtWriter<W> {
+    writer: W,
+    merger: CueMerger,
+    current_sub_num: usize,
+}
+
+impl<W: Write> SrtWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            merger: Default::default(),
+            current_sub_num: 1,
+        }
+    }
+
+    fn write_cue(&mut self, cue: &Cue) -> std::io::Result<()> {
+        writeln!(self.writer, "{}", self.current_sub_num)?;
+        writeln!(
+            self.writer,
+            "{} --> {}",
+            srt_duration_display(cue.range.begin),
+            srt_duration_display(cue.range.end),
+        )?;
+        self.writer.write_all(cue.text.as_bytes())?;
+        self.writer.write_all(b"\n\n")?;
+        self.current_sub_num += 1;
+        Ok(())
+    }
+}
+
+impl<W: Write> SubtitleWriter for SrtWriter<W> {
+    fn push(&mut self, subtitle: TextSubtitle) -> std::io::Result<()> {
+        for cue in self.merger.push(subtitle) {
+            self.write_cue(&cue)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        for cue in self.merger.finish() {
+            self.write_cue(&cue)?;
+        }
+        self.writer.flush()
+    }
+}
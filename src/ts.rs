@@ -0,0 +1,519 @@
+//! Read pgs streams from MPEG transport streams, like the `.m2ts` files of Blu-ray discs.
+//!
+//! Blu-ray `.m2ts` files use 192 byte packets, a 4 byte `TP_extra_header` followed by a regular
+//! 188 byte transport stream packet. Both packet sizes are detected. Every PES packet of a pgs
+//! stream carries segments without the `PG` header of `.sup` files, the timestamps come from the
+//! PES header instead.
+use std::{
+    collections::HashMap,
+    io::{Cursor, Read},
+};
+
+use crate::{Error, Result};
+
+/// stream type of HDMV presentation graphics streams in the program map table.
+pub const STREAM_TYPE_PGS: u8 = 0x90;
+
+const SYNC_BYTE: u8 = 0x47;
+const TS_PACKET_SIZE: usize = 188;
+const M2TS_PACKET_SIZE: usize = 192;
+
+const PID_PAT: u16 = 0x0000;
+const TABLE_ID_PAT: u8 = 0x00;
+const TABLE_ID_PMT: u8 = 0x02;
+const DESCRIPTOR_LANGUAGE: u8 = 0x0A;
+
+/// whether the data starts like a transport stream, returning its packet size.
+///
+/// the sync bytes of up to the first 4 packets are checked, at least 2 packets are needed.
+pub fn packet_size(data: &[u8]) -> Option<usize> {
+    [M2TS_PACKET_SIZE, TS_PACKET_SIZE]
+        .into_iter()
+        .find(|&size| {
+            let mut syncs = (size - TS_PACKET_SIZE..data.len()).step_by(size).take(4);
+            syncs.len() >= 2 && syncs.all(|offset| data[offset] == SYNC_BYTE)
+        })
+}
+
+/// An elementary stream listed in a program map table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stream {
+    pub pid: u16,
+    pub stream_type: u8,
+    /// ISO 639-2 code of the language descriptor.
+    pub language: Option<String>,
+}
+
+impl Stream {
+    pub fn is_pgs(&self) -> bool {
+        self.stream_type == STREAM_TYPE_PGS
+    }
+}
+
+impl std::fmt::Display for Stream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:#06x}: stream type {:#04x}",
+            self.pid, self.stream_type
+        )?;
+        if self.is_pgs() {
+            write!(f, " (pgs)")?;
+        }
+        if let Some(language) = &self.language {
+            write!(f, " [{language}]")?;
+        }
+        Ok(())
+    }
+}
+
+/// A reassembled PES packet.
+#[derive(Debug, Clone)]
+pub struct Pes {
+    pub pid: u16,
+    /// 33 bit presentation timestamp, in ticks of the 90khz clock.
+    pub pts: Option<u64>,
+    /// 33 bit decoding timestamp, in ticks of the 90khz clock.
+    pub dts: Option<u64>,
+    pub data: Vec<u8>,
+}
+
+/// Reads the program tables and PES packets of a transport stream.
+///
+/// Only reads forward so the stream can come from a pipe.
+#[derive(Debug)]
+pub struct Demuxer<R> {
+    /// the packets read to detect the packet size, followed by the rest of the stream
+    reader: std::io::Chain<Cursor<Vec<u8>>, R>,
+    packet_size: usize,
+    /// sections being reassembled, by pid
+    sections: HashMap<u16, Vec<u8>>,
+    pmt_pids: Vec<u16>,
+    streams: Vec<Stream>,
+    /// PES packet being reassembled for the selected pid
+    pes: Option<Vec<u8>>,
+}
+
+impl<R: Read> Demuxer<R> {
+    /// read the stream up to the first program map table.
+    ///
+    /// packets of elementary streams before that are skipped.
+    pub fn new(mut reader: R) -> std::io::Result<Self> {
+        let mut first = vec![0; M2TS_PACKET_SIZE + TS_PACKET_SIZE + 1];
+        reader.read_exact(&mut first)?;
+        let packet_size =
+            packet_size(&first).ok_or_else(|| invalid_data("not an MPEG transport stream"))?;
+
+        let mut demuxer = Self {
+            reader: Cursor::new(first).chain(reader),
+            packet_size,
+            sections: HashMap::new(),
+            pmt_pids: Vec::new(),
+            streams: Vec::new(),
+            pes: None,
+        };
+        while demuxer.streams.is_empty() {
+            if demuxer.read_packet(None)?.is_none() {
+                return Err(invalid_data("no program map table in the transport stream"));
+            }
+        }
+        Ok(demuxer)
+    }
+
+    /// streams of all the programs found so far.
+    pub fn streams(&self) -> &[Stream] {
+        &self.streams
+    }
+
+    /// the next complete PES packet of the stream with the given pid, `None` at the end of the
+    /// stream.
+    pub fn next_pes(&mut self, pid: u16) -> std::io::Result<Option<Pes>> {
+        loop {
+            match self.read_packet(Some(pid))? {
+                Some(Some(data)) => return parse_pes(pid, &data).map(Some),
+                Some(None) => {}
+                None => {
+                    return match self.pes.take() {
+                        Some(data) if !data.is_empty() => parse_pes(pid, &data).map(Some),
+                        _ => Ok(None),
+                    };
+                }
+            }
+        }
+    }
+
+    /// the segments of the next PES packet of the pgs stream with the given pid, with the 33 bit
+    /// timestamps of the packet.
+    pub fn next_segments(&mut self, pid: u16) -> std::io::Result<Option<Vec<pgs::Segment>>> {
+        let Some(pes) = self.next_pes(pid)? else {
+            return Ok(None);
+        };
+        let header = pgs::Header {
+            pts: pes.pts.unwrap_or_default(),
+            dts: pes.dts.unwrap_or_default(),
+        };
+        let mut sup = Vec::new();
+        pgs::write_sup_segments(&mut sup, &pes.data, header.pts, header.dts)?;
+        let mut reader = sup.as_slice();
+        let mut segments = Vec::new();
+        while !reader.is_empty() {
+            let mut segment = pgs::decode_segment_reader(&mut reader)?;
+            *segment.header_mut() = header;
+            segments.push(segment);
+        }
+        Ok(Some(segments))
+    }
+
+    /// read the next packet, returns `None` at the end of the stream and otherwise the PES
+    /// packet of `pid` completed by the packet, if any.
+    fn read_packet(&mut self, pid: Option<u16>) -> std::io::Result<Option<Option<Vec<u8>>>> {
+        let mut packet = [0u8; M2TS_PACKET_SIZE];
+        let packet = &mut packet[..self.packet_size];
+        match self.reader.read_exact(packet) {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err),
+        }
+        // skip the TP_extra_header of m2ts packets
+        let packet = &packet[self.packet_size - TS_PACKET_SIZE..];
+        if packet[0] != SYNC_BYTE {
+            return Err(invalid_data("lost transport stream sync"));
+        }
+        if packet[1] & 0x80 != 0 {
+            tracing::debug!("skipping transport packet with an error indicator");
+            return Ok(Some(None));
+        }
+        let unit_start = packet[1] & 0x40 != 0;
+        let packet_pid = (u16::from(packet[1] & 0x1F) << 8) | u16::from(packet[2]);
+        let adaptation_field_control = (packet[3] >> 4) & 0x3;
+        let mut payload = &packet[4..];
+        if adaptation_field_control & 0x2 != 0 {
+            let length = usize::from(payload[0]);
+            payload = payload.get(1 + length..).unwrap_or_default();
+        }
+        if adaptation_field_control & 0x1 == 0 {
+            return Ok(Some(None));
+        }
+
+        if packet_pid == PID_PAT || self.pmt_pids.contains(&packet_pid) {
+            self.push_section(packet_pid, unit_start, payload)?;
+            return Ok(Some(None));
+        }
+        if Some(packet_pid) != pid {
+            return Ok(Some(None));
+        }
+        if unit_start {
+            let previous = self.pes.replace(payload.to_vec());
+            return Ok(Some(previous.filter(|data| !data.is_empty())));
+        }
+        // data before the first unit start is the end of a PES packet we missed
+        if let Some(pes) = self.pes.as_mut() {
+            pes.extend(payload);
+        }
+        Ok(Some(None))
+    }
+
+    fn push_section(&mut self, pid: u16, unit_start: bool, payload: &[u8]) -> std::io::Result<()> {
+        let payload = match unit_start {
+            true => {
+                let pointer = usize::from(*payload.first().ok_or_else(truncated)?);
+                let start = payload.get(1 + pointer..).ok_or_else(truncated)?;
+                self.sections.insert(pid, start.to_vec());
+                return self.parse_section(pid);
+            }
+            false => payload,
+        };
+        if let Some(section) = self.sections.get_mut(&pid) {
+            section.extend(payload);
+            self.parse_section(pid)?;
+        }
+        Ok(())
+    }
+
+    /// parse the section of `pid` once it is complete.
+    fn parse_section(&mut self, pid: u16) -> std::io::Result<()> {
+        let Some(section) = self.sections.get(&pid) else {
+            return Ok(());
+        };
+        let [table_id, l0, l1, ..] = section[..] else {
+            return Ok(());
+        };
+        let length = 3 + usize::from(u16::from_be_bytes([l0 & 0x0F, l1]));
+        if section.len() < length {
+            return Ok(());
+        }
+        let section = self.sections.remove(&pid).unwrap_or_default();
+        // skip the header up to the last section number and the CRC at the end
+        let body = section
+            .get(8..length.saturating_sub(4))
+            .ok_or_else(truncated)?;
+        match table_id {
+            TABLE_ID_PAT => {
+                for program in body.chunks_exact(4) {
+                    let number = u16::from_be_bytes([program[0], program[1]]);
+                    let pmt_pid = u16::from_be_bytes([program[2] & 0x1F, program[3]]);
+                    // program 0 points to the network information table
+                    if number != 0 && !self.pmt_pids.contains(&pmt_pid) {
+                        self.pmt_pids.push(pmt_pid);
+                    }
+                }
+            }
+            TABLE_ID_PMT => self.parse_pmt(body)?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn parse_pmt(&mut self, body: &[u8]) -> std::io::Result<()> {
+        let [_, _, i0, i1, ..] = *body else {
+            return Err(truncated());
+        };
+        let info_length = usize::from(u16::from_be_bytes([i0 & 0x0F, i1]));
+        let mut data = body.get(4 + info_length..).ok_or_else(truncated)?;
+        while let [stream_type, p0, p1, e0, e1, ..] = *data {
+            let pid = u16::from_be_bytes([p0 & 0x1F, p1]);
+            let info_length = usize::from(u16::from_be_bytes([e0 & 0x0F, e1]));
+            let descriptors = data.get(5..5 + info_length).ok_or_else(truncated)?;
+            let stream = Stream {
+                pid,
+                stream_type,
+                language: language(descriptors),
+            };
+            match self.streams.iter_mut().find(|stream| stream.pid == pid) {
+                Some(existing) => *existing = stream,
+                None => self.streams.push(stream),
+            }
+            data = &data[5 + info_length..];
+        }
+        Ok(())
+    }
+}
+
+/// the language of an ISO 639 language descriptor.
+fn language(mut descriptors: &[u8]) -> Option<String> {
+    while let [tag, length, ..] = *descriptors {
+        let descriptor = descriptors.get(2..2 + usize::from(length))?;
+        if tag == DESCRIPTOR_LANGUAGE && descriptor.len() >= 3 {
+            return Some(String::from_utf8_lossy(&descriptor[..3]).into_owned());
+        }
+        descriptors = &descriptors[2 + descriptor.len()..];
+    }
+    None
+}
+
+fn parse_pes(pid: u16, data: &[u8]) -> std::io::Result<Pes> {
+    let [0x00, 0x00, 0x01, _stream_id, _, _, _, flags, header_length, ..] = *data else {
+        return Err(invalid_data(format!("invalid PES packet in pid {pid:#x}")));
+    };
+    let header = data
+        .get(9..9 + usize::from(header_length))
+        .ok_or_else(truncated)?;
+    let timestamp = |bytes: &[u8]| -> std::io::Result<u64> {
+        let [b0, b1, b2, b3, b4] = *bytes.get(..5).ok_or_else(truncated)? else {
+            unreachable!();
+        };
+        Ok((u64::from(b0 >> 1) & 0x7) << 30
+            | u64::from(b1) << 22
+            | u64::from(b2 >> 1) << 15
+            | u64::from(b3) << 7
+            | u64::from(b4 >> 1))
+    };
+    let pts = match flags & 0x80 {
+        0 => None,
+        _ => Some(timestamp(header)?),
+    };
+    let dts = match flags & 0xC0 {
+        0xC0 => Some(timestamp(header.get(5..).unwrap_or_default())?),
+        _ => None,
+    };
+    Ok(Pes {
+        pid,
+        pts,
+        dts,
+        data: data[9 + header.len()..].to_vec(),
+    })
+}
+
+/// the streams of a transport stream, as listed by its first program map table.
+pub fn streams<R: Read>(reader: R) -> Result<Vec<Stream>> {
+    let demuxer = Demuxer::new(reader).map_err(Error::from_decode)?;
+    Ok(demuxer.streams)
+}
+
+/// Reads a pgs stream of a transport stream as a `.sup` stream.
+#[derive(Debug)]
+pub struct PgsStreamReader<R> {
+    demuxer: Demuxer<R>,
+    pid: u16,
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read> PgsStreamReader<R> {
+    /// read the stream with the given pid, or the first pgs stream if not specified.
+    pub fn new(reader: R, pid: Option<u16>) -> Result<Self> {
+        let demuxer = Demuxer::new(reader).map_err(Error::from_decode)?;
+        let streams = demuxer.streams();
+        let pid = match pid {
+            Some(pid) => {
+                let found = streams
+                    .iter()
+                    .find(|stream| stream.pid == pid)
+                    .ok_or_else(|| Error::InvalidStream(format!("no stream with pid {pid:#x}")))?;
+                if !found.is_pgs() {
+                    return Err(Error::InvalidStream(format!(
+                        "stream {pid:#x} has stream type {:#04x}, not a pgs stream",
+                        found.stream_type
+                    )));
+                }
+                pid
+            }
+            None => {
+                streams
+                    .iter()
+                    .find(|stream| stream.is_pgs())
+                    .ok_or_else(|| Error::InvalidStream("no pgs stream".to_string()))?
+                    .pid
+            }
+        };
+        Ok(Self {
+            demuxer,
+            pid,
+            buffer: Vec::new(),
+            position: 0,
+        })
+    }
+
+    pub fn pid(&self) -> u16 {
+        self.pid
+    }
+}
+
+impl<R: Read> Read for PgsStreamReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.position == self.buffer.len() {
+            let Some(pes) = self.demuxer.next_pes(self.pid)? else {
+                return Ok(0);
+            };
+            self.buffer.clear();
+            self.position = 0;
+            let (pts, dts) = (pes.pts.unwrap_or_default(), pes.dts.unwrap_or_default());
+            pgs::write_sup_segments(&mut self.buffer, &pes.data, pts, dts)?;
+        }
+        let available = &self.buffer[self.position..];
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+fn invalid_data(message: impl Into<String>) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.into())
+}
+
+fn truncated() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::UnexpectedEof,
+        "truncated transport stream packet",
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PGS: &[u8] = include_bytes!("../subtitle.sup");
+    const PID: u16 = 0x1200;
+
+    /// m2ts packets with the payload split over as many packets as needed, the last one padded
+    /// with adaptation field stuffing.
+    fn packets(pid: u16, payload: &[u8]) -> Vec<u8> {
+        let mut packets = Vec::new();
+        for (i, chunk) in payload.chunks(184).enumerate() {
+            packets.extend([0u8; 4]);
+            let unit_start = if i == 0 { 0x40 } else { 0x00 };
+            packets.extend([SYNC_BYTE, unit_start | (pid >> 8) as u8, pid as u8]);
+            match 184 - chunk.len() {
+                0 => packets.push(0x10),
+                1 => packets.extend([0x30, 0]),
+                stuffing => {
+                    packets.extend([0x30, (stuffing - 1) as u8, 0]);
+                    packets.extend(std::iter::repeat_n(0xFF, stuffing - 2));
+                }
+            }
+            packets.extend(chunk);
+        }
+        packets
+    }
+
+    fn section(table_id: u8, body: &[u8]) -> Vec<u8> {
+        let length = (5 + body.len() + 4) as u16;
+        let mut section = vec![0, table_id, 0xB0 | (length >> 8) as u8, length as u8];
+        section.extend([0, 1, 0xC1, 0, 0]);
+        section.extend(body);
+        section.extend([0; 4]);
+        section
+    }
+
+    fn timestamp(marker: u8, ts: u64) -> [u8; 5] {
+        [
+            (marker << 4) | ((ts >> 29) & 0x0E) as u8 | 1,
+            (ts >> 22) as u8,
+            ((ts >> 14) & 0xFE) as u8 | 1,
+            (ts >> 7) as u8,
+            ((ts << 1) & 0xFE) as u8 | 1,
+        ]
+    }
+
+    /// the test stream as an m2ts file, with its timestamps shifted by `offset`.
+    fn m2ts(offset: u64) -> Vec<u8> {
+        let mut stream = packets(0, &section(TABLE_ID_PAT, &[0, 1, 0xE1, 0x00]));
+        let mut pmt = vec![0xE1, 0x01, 0xF0, 0];
+        pmt.extend([0x1B, 0xE1, 0x01, 0xF0, 0]);
+        pmt.extend([STREAM_TYPE_PGS, 0xF2, 0x00, 0xF0, 6]);
+        pmt.extend([DESCRIPTOR_LANGUAGE, 4, b'f', b'r', b'a', 0]);
+        stream.extend(packets(0x100, &section(TABLE_ID_PMT, &pmt)));
+
+        let mut sup = PGS;
+        while !sup.is_empty() {
+            let size = 13 + usize::from(u16::from_be_bytes([sup[11], sup[12]]));
+            let pts = u64::from(u32::from_be_bytes(sup[2..6].try_into().unwrap()));
+            let dts = u64::from(u32::from_be_bytes(sup[6..10].try_into().unwrap()));
+            let mut pes = vec![0, 0, 1, 0xBD, 0, 0, 0x81, 0xC0, 10];
+            pes.extend(timestamp(0x3, pts + offset));
+            pes.extend(timestamp(0x1, dts + offset));
+            pes.extend(&sup[10..size]);
+            stream.extend(packets(PID, &pes));
+            // a video packet in between
+            stream.extend(packets(0x1011, &[0; 184]));
+            sup = &sup[size..];
+        }
+        stream
+    }
+
+    #[test]
+    fn test_pgs_stream() {
+        let stream = m2ts(0);
+        assert_eq!(packet_size(&stream), Some(M2TS_PACKET_SIZE));
+        let streams = streams(stream.as_slice()).unwrap();
+        assert_eq!(streams.len(), 2);
+        assert_eq!(
+            streams[1].to_string(),
+            "0x1200: stream type 0x90 (pgs) [fra]"
+        );
+
+        let reader = PgsStreamReader::new(stream.as_slice(), None).unwrap();
+        assert_eq!(reader.pid(), PID);
+        let mut sup = Vec::new();
+        std::io::copy(&mut { reader }, &mut sup).unwrap();
+        assert_eq!(sup, PGS);
+
+        // timestamps past 32 bits are kept by the segments
+        let stream = m2ts(1 << 32);
+        let mut demuxer = Demuxer::new(stream.as_slice()).unwrap();
+        let segments = demuxer.next_segments(PID).unwrap().unwrap();
+        let first = pgs::decode_display_sets(PGS).unwrap()[0].pcs.header.pts;
+        assert_eq!(segments[0].header().pts, first + (1 << 32));
+    }
+}
@@ -0,0 +1,631 @@
+//! Check pgs streams against the structure and decoder model limits of the Blu-ray spec.
+//!
+//! [`validate`] reads the whole stream, in [`pgs::DecodeMode::Resilient`] so corrupt data is
+//! reported and skipped instead of stopping the checks, and returns every [`Finding`].
+use std::{
+    collections::{HashMap, HashSet},
+    io::Read,
+    sync::{Arc, Mutex},
+};
+
+use serde::Serialize;
+
+use crate::{Error, Result};
+
+/// windows per epoch.
+pub const MAX_WINDOWS: usize = 2;
+/// composition objects shown by a single display set.
+pub const MAX_COMPOSITION_OBJECTS: usize = 2;
+/// objects defined per epoch.
+pub const MAX_OBJECTS: usize = 64;
+/// palettes defined per epoch.
+pub const MAX_PALETTES: usize = 8;
+/// size in bytes of the decoded object buffer, one byte per pixel.
+pub const OBJECT_BUFFER_SIZE: u64 = 4 * 1024 * 1024;
+pub const MIN_OBJECT_SIZE: u16 = 8;
+pub const MAX_OBJECT_SIZE: u16 = 4096;
+/// rate in bytes per second at which the decoder fills the object buffer.
+pub const PIXEL_DECODING_RATE: u64 = 16_000_000;
+/// rate in bytes per second at which the windows are drawn onto the graphics plane.
+pub const PIXEL_TRANSFER_RATE: u64 = 32_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// players may still show the stream as intended.
+    Warning,
+    /// the stream does not conform to the spec.
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Warning => f.write_str("warning"),
+            Severity::Error => f.write_str("error"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Finding {
+    pub severity: Severity,
+    /// short name of the failed check, like `window-bounds`.
+    pub check: &'static str,
+    /// byte offset of the display set, or of the malformed data, in the stream.
+    pub offset: u64,
+    /// index of the display set, none for malformed data skipped by the decoder.
+    pub display_set: Option<usize>,
+    /// presentation timestamp of the display set, in 90kHz ticks.
+    pub pts: Option<u64>,
+    pub message: String,
+}
+
+impl std::fmt::Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at offset {}", self.severity, self.offset)?;
+        if let Some(display_set) = self.display_set {
+            write!(f, ", display set {display_set}")?;
+        }
+        if let Some(pts) = self.pts {
+            let time = pgs::clock_to_duration(pts);
+            write!(f, " ({})", crate::srt::srt_duration_display(time))?;
+        }
+        write!(f, ": [{}] {}", self.check, self.message)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ObjectSize {
+    width: u16,
+    height: u16,
+}
+
+/// Checks display sets one at a time, keeping the state of the current epoch.
+#[derive(Debug, Default)]
+pub struct Validator {
+    findings: Vec<Finding>,
+    display_sets: usize,
+    display_size: Option<(u16, u16)>,
+    last_pts: Option<u64>,
+    objects: HashMap<u16, ObjectSize>,
+    palettes: HashSet<u8>,
+    windows: HashMap<u8, pgs::Window>,
+    /// rle data of objects whose last fragment has not arrived yet
+    fragments: HashMap<u16, Vec<u8>>,
+}
+
+/// where the finding is reported and the display set it is about.
+struct Location {
+    offset: u64,
+    display_set: usize,
+    pts: u64,
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn report(
+        &mut self,
+        location: &Location,
+        severity: Severity,
+        check: &'static str,
+        message: String,
+    ) {
+        self.findings.push(Finding {
+            severity,
+            check,
+            offset: location.offset,
+            display_set: Some(location.display_set),
+            pts: Some(location.pts),
+            message,
+        });
+    }
+
+    /// malformed data skipped by the decoder.
+    pub fn push_decode_warning(&mut self, warning: pgs::DecodeWarning) {
+        self.findings.push(Finding {
+            severity: Severity::Error,
+            check: "malformed-data",
+            offset: warning.offset,
+            display_set: None,
+            pts: None,
+            message: match warning.skipped {
+                0 => warning.reason,
+                skipped => format!("{}, {skipped} bytes skipped", warning.reason),
+            },
+        });
+    }
+
+    /// check the display set that starts at `offset` in the stream.
+    pub fn push(&mut self, offset: u64, ds: &pgs::DisplaySet) {
+        let location = Location {
+            offset,
+            display_set: self.display_sets,
+            pts: ds.pcs.header.pts,
+        };
+        self.display_sets += 1;
+        let pcs = &ds.pcs;
+
+        let epoch_start = pcs.composition_state == pgs::CompositionState::EpochStart;
+        if location.display_set == 0 && !epoch_start {
+            self.report(
+                &location,
+                Severity::Error,
+                "epoch-start",
+                format!(
+                    "the first display set is {:?} instead of an epoch start",
+                    pcs.composition_state
+                ),
+            );
+        }
+        if epoch_start {
+            self.objects.clear();
+            self.palettes.clear();
+            self.windows.clear();
+            self.fragments.clear();
+        }
+        match self.display_size {
+            Some((width, height)) if (width, height) != (pcs.width, pcs.height) => self.report(
+                &location,
+                Severity::Error,
+                "display-size",
+                format!(
+                    "display size changed from {width}x{height} to {}x{}",
+                    pcs.width, pcs.height
+                ),
+            ),
+            _ => self.display_size = Some((pcs.width, pcs.height)),
+        }
+
+        self.check_timestamps(&location, ds);
+
+        for window in ds.wds.iter().flat_map(|wds| wds.windows.iter()) {
+            let right = u32::from(window.horizontal_position) + u32::from(window.width);
+            let bottom = u32::from(window.vertical_position) + u32::from(window.height);
+            if right > u32::from(pcs.width) || bottom > u32::from(pcs.height) {
+                self.report(
+                    &location,
+                    Severity::Error,
+                    "window-bounds",
+                    format!(
+                        "window {} at {},{} of {}x{} is outside the {}x{} display",
+                        window.window_id,
+                        window.horizontal_position,
+                        window.vertical_position,
+                        window.width,
+                        window.height,
+                        pcs.width,
+                        pcs.height
+                    ),
+                );
+            }
+            self.windows.insert(window.window_id, *window);
+        }
+        if self.windows.len() > MAX_WINDOWS {
+            self.report(
+                &location,
+                Severity::Error,
+                "window-limit",
+                format!(
+                    "{} windows defined in the epoch, at most {MAX_WINDOWS} are allowed",
+                    self.windows.len()
+                ),
+            );
+        }
+
+        for pds in ds.pds.iter() {
+            self.palettes.insert(pds.palette_id);
+        }
+        if self.palettes.len() > MAX_PALETTES {
+            self.report(
+                &location,
+                Severity::Error,
+                "palette-limit",
+                format!(
+                    "{} palettes defined in the epoch, at most {MAX_PALETTES} are allowed",
+                    self.palettes.len()
+                ),
+            );
+        }
+
+        let decoded_pixels = self.check_objects(&location, ds);
+        self.check_composition(&location, ds);
+        self.check_decode_time(&location, ds, decoded_pixels);
+        self.last_pts = Some(location.pts);
+    }
+
+    fn check_timestamps(&mut self, location: &Location, ds: &pgs::DisplaySet) {
+        if let Some(last_pts) = self.last_pts
+            && location.pts < last_pts
+        {
+            self.report(
+                location,
+                Severity::Error,
+                "decreasing-pts",
+                format!("pts {} is before the previous pts {last_pts}", location.pts),
+            );
+        }
+
+        let headers = std::iter::once(("PCS", &ds.pcs.header))
+            .chain(ds.wds.iter().map(|wds| ("WDS", &wds.header)))
+            .chain(ds.pds.iter().map(|pds| ("PDS", &pds.header)))
+            .chain(ds.ods.iter().map(|ods| ("ODS", &ods.header)))
+            .chain(std::iter::once(("END", &ds.end.header)));
+        let mut last_dts = 0;
+        for (segment, header) in headers {
+            // the dts is usually left as zero, in which case there is nothing to check
+            if header.dts == 0 {
+                continue;
+            }
+            if header.dts > header.pts {
+                self.report(
+                    location,
+                    Severity::Error,
+                    "dts-after-pts",
+                    format!(
+                        "{segment} is decoded at {} after being presented at {}",
+                        header.dts, header.pts
+                    ),
+                );
+            }
+            if header.dts < last_dts {
+                self.report(
+                    location,
+                    Severity::Error,
+                    "decreasing-dts",
+                    format!(
+                        "{segment} dts {} is before the dts {last_dts} of the previous segment",
+                        header.dts
+                    ),
+                );
+            }
+            last_dts = header.dts;
+        }
+    }
+
+    /// returns the number of pixels decoded into the object buffer by the display set.
+    fn check_objects(&mut self, location: &Location, ds: &pgs::DisplaySet) -> u64 {
+        let mut decoded_pixels = 0;
+        for ods in ds.ods.iter() {
+            let id = ods.object_id;
+            if ods.last_in_sequence.is_first() {
+                if !(MIN_OBJECT_SIZE..=MAX_OBJECT_SIZE).contains(&ods.width)
+                    || !(MIN_OBJECT_SIZE..=MAX_OBJECT_SIZE).contains(&ods.height)
+                {
+                    self.report(
+                        location,
+                        Severity::Error,
+                        "object-size",
+                        format!(
+                            "object {id} is {}x{}, sizes must be between {MIN_OBJECT_SIZE} and \
+                             {MAX_OBJECT_SIZE}",
+                            ods.width, ods.height
+                        ),
+                    );
+                }
+                self.objects.insert(
+                    id,
+                    ObjectSize {
+                        width: ods.width,
+                        height: ods.height,
+                    },
+                );
+                self.fragments.insert(id, Vec::new());
+            }
+
+            let Some(data) = self.fragments.get_mut(&id) else {
+                self.report(
+                    location,
+                    Severity::Error,
+                    "object-fragments",
+                    format!(
+                        "{:?} fragment of object {id} without a first fragment",
+                        ods.last_in_sequence
+                    ),
+                );
+                continue;
+            };
+            data.extend_from_slice(&ods.data);
+            if !ods.last_in_sequence.is_last() {
+                continue;
+            }
+
+            let data = self.fragments.remove(&id).unwrap_or_default();
+            let size = self.objects[&id];
+            decoded_pixels += u64::from(size.width) * u64::from(size.height);
+            self.check_rle_lines(location, id, size, &data);
+        }
+
+        if self.objects.len() > MAX_OBJECTS {
+            self.report(
+                location,
+                Severity::Error,
+                "object-limit",
+                format!(
+                    "{} objects defined in the epoch, at most {MAX_OBJECTS} are allowed",
+                    self.objects.len()
+                ),
+            );
+        }
+        let buffer_size = self
+            .objects
+            .values()
+            .map(|size| u64::from(size.width) * u64::from(size.height))
+            .sum::<u64>();
+        if buffer_size > OBJECT_BUFFER_SIZE {
+            self.report(
+                location,
+                Severity::Error,
+                "object-buffer",
+                format!(
+                    "the objects of the epoch take {buffer_size} bytes, the object buffer holds \
+                     {OBJECT_BUFFER_SIZE}"
+                ),
+            );
+        }
+        decoded_pixels
+    }
+
+    /// every line of the rle data must be as long as the object is wide.
+    fn check_rle_lines(&mut self, location: &Location, id: u16, size: ObjectSize, data: &[u8]) {
+        let mut lines = 0u32;
+        let mut line_width = 0u32;
+        let mut bad_lines = Vec::new();
+        for code in pgs::wire::decode_image_data(data) {
+            match code {
+                Ok(pgs::wire::ImageDataCode::Color { count, .. }) => {
+                    line_width += u32::from(count);
+                }
+                Ok(pgs::wire::ImageDataCode::EndOfLine) => {
+                    if line_width != u32::from(size.width) {
+                        bad_lines.push((lines, line_width));
+                    }
+                    lines += 1;
+                    line_width = 0;
+                }
+                Err(err) => {
+                    self.report(
+                        location,
+                        Severity::Error,
+                        "rle-data",
+                        format!("object {id} has invalid rle data: {err}"),
+                    );
+                    return;
+                }
+            }
+        }
+
+        if let Some(&(line, width)) = bad_lines.first() {
+            self.report(
+                location,
+                Severity::Error,
+                "rle-line-length",
+                format!(
+                    "object {id} has {} lines not {} pixels wide, the first is line {line} with \
+                     {width} pixels",
+                    bad_lines.len(),
+                    size.width
+                ),
+            );
+        }
+        if line_width != 0 || lines != u32::from(size.height) {
+            self.report(
+                location,
+                Severity::Error,
+                "rle-line-count",
+                format!(
+                    "object {id} has {lines} complete lines of rle data but is {} pixels high",
+                    size.height
+                ),
+            );
+        }
+    }
+
+    fn check_composition(&mut self, location: &Location, ds: &pgs::DisplaySet) {
+        let pcs = &ds.pcs;
+        if !self.palettes.contains(&pcs.palette_id) {
+            self.report(
+                location,
+                Severity::Error,
+                "undefined-palette",
+                format!("palette {} is used before being defined", pcs.palette_id),
+            );
+        }
+        if pcs.composition_objects.len() > MAX_COMPOSITION_OBJECTS {
+            self.report(
+                location,
+                Severity::Error,
+                "composition-object-limit",
+                format!(
+                    "{} composition objects, at most {MAX_COMPOSITION_OBJECTS} are allowed",
+                    pcs.composition_objects.len()
+                ),
+            );
+        }
+
+        for comp in pcs.composition_objects.iter() {
+            let Some(&object) = self.objects.get(&comp.object_id) else {
+                self.report(
+                    location,
+                    Severity::Error,
+                    "undefined-object",
+                    format!("object {} is shown before being defined", comp.object_id),
+                );
+                continue;
+            };
+
+            let (mut width, mut height) = (u32::from(object.width), u32::from(object.height));
+            if let Some(cropping) = comp.cropping {
+                let right = u32::from(cropping.horizontal_position) + u32::from(cropping.width);
+                let bottom = u32::from(cropping.vertical_position) + u32::from(cropping.height);
+                if right > width || bottom > height {
+                    self.report(
+                        location,
+                        Severity::Error,
+                        "cropping-bounds",
+                        format!(
+                            "cropping {},{} of {}x{} is outside the {width}x{height} object {}",
+                            cropping.horizontal_position,
+                            cropping.vertical_position,
+                            cropping.width,
+                            cropping.height,
+                            comp.object_id
+                        ),
+                    );
+                }
+                (width, height) = (u32::from(cropping.width), u32::from(cropping.height));
+            }
+
+            let Some(window) = self.windows.get(&comp.window_id) else {
+                self.report(
+                    location,
+                    Severity::Error,
+                    "undefined-window",
+                    format!(
+                        "object {} is shown in window {} which is not defined",
+                        comp.object_id, comp.window_id
+                    ),
+                );
+                continue;
+            };
+            let (x, y) = (
+                u32::from(comp.horizontal_position),
+                u32::from(comp.vertical_position),
+            );
+            let (window_x, window_y) = (
+                u32::from(window.horizontal_position),
+                u32::from(window.vertical_position),
+            );
+            if x < window_x
+                || y < window_y
+                || x + width > window_x + u32::from(window.width)
+                || y + height > window_y + u32::from(window.height)
+            {
+                let message = format!(
+                    "object {} at {x},{y} of {width}x{height} is outside window {} at \
+                     {window_x},{window_y} of {}x{}",
+                    comp.object_id, comp.window_id, window.width, window.height
+                );
+                self.report(location, Severity::Error, "object-outside-window", message);
+            }
+        }
+    }
+
+    /// the display set must be decoded and drawn between the previous one and its pts.
+    fn check_decode_time(&mut self, location: &Location, ds: &pgs::DisplaySet, decoded: u64) {
+        let Some(last_pts) = self.last_pts else {
+            return;
+        };
+        let drawn = ds
+            .pcs
+            .composition_objects
+            .iter()
+            .filter_map(|comp| self.windows.get(&comp.window_id))
+            .map(|window| u64::from(window.width) * u64::from(window.height))
+            .sum::<u64>();
+        let required = (decoded * pgs::CLOCK_RATE).div_ceil(PIXEL_DECODING_RATE)
+            + (drawn * pgs::CLOCK_RATE).div_ceil(PIXEL_TRANSFER_RATE);
+        let available = location.pts.saturating_sub(last_pts);
+        if required > available {
+            self.report(
+                location,
+                Severity::Warning,
+                "decode-time",
+                format!(
+                    "decoding and drawing takes {required} ticks but the previous display set \
+                     is only {available} ticks earlier"
+                ),
+            );
+        }
+    }
+
+    /// all the findings, ordered by offset.
+    pub fn finish(mut self) -> Vec<Finding> {
+        if self.display_sets == 0 {
+            self.findings.push(Finding {
+                severity: Severity::Warning,
+                check: "empty",
+                offset: 0,
+                display_set: None,
+                pts: None,
+                message: "no display sets in input".to_string(),
+            });
+        }
+        self.findings.sort_by_key(|finding| finding.offset);
+        self.findings
+    }
+}
+
+/// check every display set read from `reader`.
+pub fn validate<R: Read>(reader: R) -> Result<Vec<Finding>> {
+    let warnings = Arc::new(Mutex::new(Vec::new()));
+    let mut display_sets = pgs::DisplaySetReader::resilient(reader).on_warning({
+        let warnings = warnings.clone();
+        move |warning| warnings.lock().unwrap().push(warning)
+    });
+
+    let mut validator = Validator::new();
+    while let Some(ds) = display_sets.next() {
+        let ds = ds.map_err(Error::from_decode)?;
+        validator.push(display_sets.display_set_offset(), &ds);
+    }
+    for warning in warnings.lock().unwrap().drain(..) {
+        validator.push_decode_warning(warning);
+    }
+    Ok(validator.finish())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PGS: &[u8] = include_bytes!("../subtitle.sup");
+
+    fn checks(findings: &[Finding]) -> Vec<&'static str> {
+        findings.iter().map(|finding| finding.check).collect()
+    }
+
+    #[test]
+    fn test_validate() {
+        let findings = validate(PGS).unwrap();
+        assert_eq!(checks(&findings), Vec::<&str>::new());
+
+        let mut display_sets = pgs::decode_display_sets(PGS).unwrap();
+        let ds = &mut display_sets[0];
+        ds.pcs.composition_state = pgs::CompositionState::Normal;
+        ds.wds[0].windows[0].width = ds.pcs.width;
+        let object_id = ds.pcs.composition_objects[0].object_id;
+        ds.ods.retain(|ods| ods.object_id != object_id);
+        let first_pts = ds.pcs.header.pts;
+        display_sets[1].pcs.header.pts = first_pts - 1;
+        display_sets[2].pcs.composition_objects[0].cropping =
+            Some(pgs::CompositionObjectCropping {
+                width: 1,
+                height: 1,
+                horizontal_position: 10_000,
+                vertical_position: 0,
+            });
+
+        let mut stream = pgs::encode_display_sets(&display_sets).unwrap();
+        let garbage_offset = stream.len() as u64;
+        stream.extend_from_slice(b"garbage at the end");
+        let findings = validate(stream.as_slice()).unwrap();
+        assert_eq!(
+            checks(&findings),
+            [
+                "epoch-start",
+                "window-bounds",
+                "undefined-object",
+                "decreasing-pts",
+                "cropping-bounds",
+                "malformed-data"
+            ]
+        );
+        assert_eq!(findings[0].offset, 0);
+        assert_eq!(findings[0].display_set, Some(0));
+        assert_eq!(findings[5].offset, garbage_offset);
+    }
+}
@@ -0,0 +1,732 @@
+//! Convert bitmap subtitles to DVD VobSub subtitles.
+//!
+//! A VobSub subtitle is made of two files:
+//! + the `.sub` file, an MPEG program stream with one subpicture unit (SPU) per subtitle. The
+//!   SPU holds the 2 bit per pixel image, run length encoded, and the control sequences that
+//!   show and hide it.
+//! + the `.idx` file, a text file with the 16 color palette shared by all subtitles and the
+//!   timestamp and position in the `.sub` file of each subtitle.
+//!
+//! DVD subpictures only have 4 colors, so the bitmaps are quantized and their colors added to
+//! the global palette, and the screen is always 720x480 or 720x576 so the bitmaps are scaled
+//! from the size of the pgs display.
+use std::{collections::HashMap, io::Write, time::Duration};
+
+use crate::{Bitmap, BitmapSubtitle, Result};
+
+const PACK_SIZE: usize = 2048;
+const PACK_HEADER_SIZE: usize = 14;
+/// private stream 1 substream of the first subtitle track
+const SUBSTREAM_ID: u8 = 0x20;
+/// program mux rate in units of 50 bytes/s, the usual 10.08 Mbit/s of DVDs
+const MUX_RATE: u32 = 25200;
+/// control sequence delays are counted in units of 1024 ticks of the 90kHz clock
+const DELAY_UNIT: u64 = 1024;
+const PALETTE_SIZE: usize = 16;
+/// pixels more transparent than this are left as background
+const MIN_ALPHA: u8 = 32;
+/// colors closer than this, in squared rgb distance, share the same palette entry
+const MAX_PALETTE_DISTANCE: u32 = 3 * 24 * 24;
+
+/// Screen size of the DVD the subtitles are made for.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DvdStandard {
+    /// 720x480
+    #[default]
+    Ntsc,
+    /// 720x576
+    Pal,
+}
+
+impl DvdStandard {
+    pub fn size(&self) -> (u32, u32) {
+        match self {
+            DvdStandard::Ntsc => (720, 480),
+            DvdStandard::Pal => (720, 576),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VobSubOptions {
+    pub standard: DvdStandard,
+    /// ISO 639-1 language code written to the `.idx` file.
+    pub language: String,
+}
+
+impl Default for VobSubOptions {
+    fn default() -> Self {
+        Self {
+            standard: Default::default(),
+            language: "en".to_string(),
+        }
+    }
+}
+
+/// the ISO 639-1 code used by VobSub for a tesseract, ISO 639-2, language code.
+///
+/// only the first of several languages joined with `+` is used, and the script variants like
+/// `chi_sim` map to their language. languages without a two letter code fall back to `en`.
+pub fn vobsub_language(code: &str) -> String {
+    let language = code.split(['+', '_']).next().unwrap_or(code);
+    let id = match language {
+        "afr" => "af",
+        "amh" => "am",
+        "ara" => "ar",
+        "asm" => "as",
+        "aze" => "az",
+        "bel" => "be",
+        "ben" => "bn",
+        "bod" => "bo",
+        "bos" => "bs",
+        "bre" => "br",
+        "bul" => "bg",
+        "cat" => "ca",
+        "ces" => "cs",
+        "chi" => "zh",
+        "cos" => "co",
+        "cym" => "cy",
+        "dan" => "da",
+        "deu" => "de",
+        "div" => "dv",
+        "dzo" => "dz",
+        "ell" => "el",
+        "eng" => "en",
+        "epo" => "eo",
+        "est" => "et",
+        "eus" => "eu",
+        "fao" => "fo",
+        "fas" => "fa",
+        "fin" => "fi",
+        "fra" => "fr",
+        "fry" => "fy",
+        "gla" => "gd",
+        "gle" => "ga",
+        "glg" => "gl",
+        "guj" => "gu",
+        "hat" => "ht",
+        "heb" => "he",
+        "hin" => "hi",
+        "hrv" => "hr",
+        "hun" => "hu",
+        "hye" => "hy",
+        "iku" => "iu",
+        "ind" => "id",
+        "isl" => "is",
+        "ita" => "it",
+        "jav" => "jv",
+        "jpn" => "ja",
+        "kan" => "kn",
+        "kat" => "ka",
+        "kaz" => "kk",
+        "khm" => "km",
+        "kir" => "ky",
+        "kmr" => "ku",
+        "kor" => "ko",
+        "lao" => "lo",
+        "lat" => "la",
+        "lav" => "lv",
+        "lit" => "lt",
+        "ltz" => "lb",
+        "mal" => "ml",
+        "mar" => "mr",
+        "mkd" => "mk",
+        "mlt" => "mt",
+        "mon" => "mn",
+        "mri" => "mi",
+        "msa" => "ms",
+        "mya" => "my",
+        "nep" => "ne",
+        "nld" => "nl",
+        "nor" => "no",
+        "oci" => "oc",
+        "ori" => "or",
+        "pan" => "pa",
+        "pol" => "pl",
+        "por" => "pt",
+        "pus" => "ps",
+        "que" => "qu",
+        "ron" => "ro",
+        "rus" => "ru",
+        "san" => "sa",
+        "sin" => "si",
+        "slk" => "sk",
+        "slv" => "sl",
+        "snd" => "sd",
+        "spa" => "es",
+        "sqi" => "sq",
+        "srp" => "sr",
+        "sun" => "su",
+        "swa" => "sw",
+        "swe" => "sv",
+        "tam" => "ta",
+        "tat" => "tt",
+        "tel" => "te",
+        "tgk" => "tg",
+        "tgl" => "tl",
+        "tha" => "th",
+        "tir" => "ti",
+        "ton" => "to",
+        "tur" => "tr",
+        "uig" => "ug",
+        "ukr" => "uk",
+        "urd" => "ur",
+        "uzb" => "uz",
+        "vie" => "vi",
+        "yid" => "yi",
+        "yor" => "yo",
+        _ => {
+            tracing::warn!("no two letter code for language {language}, using en in the idx file");
+            "en"
+        }
+    };
+    id.to_string()
+}
+
+/// Writes bitmap subtitles to the `.sub` file as they are pushed and the `.idx` file once
+/// finished, since the palette is only complete after the last subtitle.
+pub struct VobSubWriter<S, I> {
+    sub: S,
+    idx: I,
+    options: VobSubOptions,
+    palette: Vec<(u8, u8, u8)>,
+    /// begin time and `.sub` file offset of each subtitle
+    entries: Vec<(Duration, u64)>,
+    position: u64,
+}
+
+impl<S: Write, I: Write> VobSubWriter<S, I> {
+    pub fn new(sub: S, idx: I, options: VobSubOptions) -> Self {
+        Self {
+            sub,
+            idx,
+            options,
+            palette: Default::default(),
+            entries: Default::default(),
+            position: 0,
+        }
+    }
+
+    /// subtitles must be pushed ordered by their begin time.
+    pub fn push(&mut self, subtitle: &BitmapSubtitle) -> Result<()> {
+        if subtitle.bitmap.width == 0 || subtitle.bitmap.height == 0 {
+            tracing::warn!("skipping empty bitmap at {:?}", subtitle.range.begin);
+            return Ok(());
+        }
+
+        let (screen_width, screen_height) = self.options.standard.size();
+        let placement = &subtitle.placement;
+        // bitmaps without a known placement are assumed to already be in dvd coordinates
+        let (source_width, source_height) = match placement.is_known() {
+            true => (placement.screen_width, placement.screen_height),
+            false => (screen_width, screen_height),
+        };
+        let scale = |value: u32, to: u32, from: u32| {
+            ((u64::from(value) * u64::from(to) + u64::from(from) / 2) / u64::from(from)) as u32
+        };
+
+        let x = scale(placement.x, screen_width, source_width).min(screen_width - 1);
+        let y = scale(placement.y, screen_height, source_height).min(screen_height - 1);
+        let width =
+            scale(subtitle.bitmap.width, screen_width, source_width).clamp(1, screen_width - x);
+        let height =
+            scale(subtitle.bitmap.height, screen_height, source_height).clamp(1, screen_height - y);
+
+        let bitmap = scale_bitmap(&subtitle.bitmap, width, height);
+        let (pixels, classes) = quantize(&bitmap);
+        let mut colors = [0u8; 4];
+        let mut contrast = [0u8; 4];
+        for (class, rgba) in classes.iter().enumerate() {
+            if let Some((rgb, alpha)) = rgba {
+                colors[class] = self.palette_index(*rgb);
+                contrast[class] = ((u32::from(*alpha) * 15 + 127) / 255) as u8;
+            }
+        }
+
+        // a subtitle that ends before it begins stays on screen
+        let duration = subtitle.range.end.checked_sub(subtitle.range.begin);
+        let spu = encode_spu(&SpuPicture {
+            pixels: &pixels,
+            x,
+            y,
+            width,
+            height,
+            colors,
+            contrast,
+            duration,
+            forced: subtitle.forced,
+        })?;
+
+        self.entries.push((subtitle.range.begin, self.position));
+        let pts = pgs::duration_to_clock(subtitle.range.begin);
+        for pack in spu_packs(&spu, pts) {
+            self.sub.write_all(&pack)?;
+            self.position += pack.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// write the `.idx` file and flush both writers.
+    pub fn finish(&mut self) -> Result<()> {
+        self.sub.flush()?;
+
+        let (width, height) = self.options.standard.size();
+        let mut palette = self.palette.clone();
+        palette.resize(PALETTE_SIZE, (0, 0, 0));
+        let palette = palette
+            .iter()
+            .map(|(r, g, b)| format!("{r:02x}{g:02x}{b:02x}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let w = &mut self.idx;
+        writeln!(w, "# VobSub index file, v7 (do not modify this line!)")?;
+        writeln!(w, "# Created by sup-to-srt")?;
+        writeln!(w)?;
+        writeln!(w, "size: {width}x{height}")?;
+        writeln!(w, "org: 0, 0")?;
+        writeln!(w, "scale: 100%, 100%")?;
+        writeln!(w, "alpha: 100%")?;
+        writeln!(w, "smooth: OFF")?;
+        writeln!(w, "fadein/out: 0, 0")?;
+        writeln!(w, "align: OFF at LEFT TOP")?;
+        writeln!(w, "time offset: 0")?;
+        writeln!(w, "forced subs: OFF")?;
+        writeln!(w, "palette: {palette}")?;
+        writeln!(
+            w,
+            "custom colors: OFF, tridx: 0000, colors: 000000, 000000, 000000, 000000"
+        )?;
+        writeln!(w, "langidx: 0")?;
+        writeln!(w)?;
+        writeln!(w, "id: {}, index: 0", self.options.language)?;
+        for (begin, position) in self.entries.iter() {
+            let total_secs = begin.as_secs();
+            writeln!(
+                w,
+                "timestamp: {:02}:{:02}:{:02}:{:03}, filepos: {position:09x}",
+                total_secs / 3600,
+                (total_secs / 60) % 60,
+                total_secs % 60,
+                begin.subsec_millis()
+            )?;
+        }
+        w.flush()?;
+        Ok(())
+    }
+
+    /// the palette entry closest to `color`, adding a new entry if none is close enough.
+    fn palette_index(&mut self, color: (u8, u8, u8)) -> u8 {
+        let distance = |other: &(u8, u8, u8)| {
+            let d = |a: u8, b: u8| (i32::from(a) - i32::from(b)).unsigned_abs();
+            d(color.0, other.0).pow(2) + d(color.1, other.1).pow(2) + d(color.2, other.2).pow(2)
+        };
+        let nearest = self
+            .palette
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, other)| distance(other));
+        match nearest {
+            Some((idx, other)) if distance(other) <= MAX_PALETTE_DISTANCE => idx as u8,
+            _ if self.palette.len() < PALETTE_SIZE => {
+                self.palette.push(color);
+                (self.palette.len() - 1) as u8
+            }
+            Some((idx, _)) => idx as u8,
+            None => unreachable!("palette is never full and empty"),
+        }
+    }
+}
+
+/// resize the bitmap averaging the source pixels covered by each output pixel.
+///
+/// colors are weighted by their alpha so transparent pixels do not darken the edges.
+fn scale_bitmap(bitmap: &Bitmap, width: u32, height: u32) -> Bitmap {
+    if bitmap.width == width && bitmap.height == height {
+        return bitmap.clone();
+    }
+
+    let source_range = |dst: u32, dst_size: u32, src_size: u32| {
+        let begin = u64::from(dst) * u64::from(src_size) / u64::from(dst_size);
+        let end = (u64::from(dst + 1) * u64::from(src_size)).div_ceil(u64::from(dst_size));
+        let end = end.max(begin + 1).min(u64::from(src_size));
+        begin as usize..end as usize
+    };
+
+    let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+    for dy in 0..height {
+        let rows = source_range(dy, height, bitmap.height);
+        for dx in 0..width {
+            let columns = source_range(dx, width, bitmap.width);
+            let mut sum = [0u64; 4];
+            let mut count = 0u64;
+            for sy in rows.clone() {
+                for sx in columns.clone() {
+                    let offset = (sy * bitmap.width as usize + sx) * 4;
+                    let [r, g, b, a] = [0, 1, 2, 3].map(|c| u64::from(bitmap.pixels[offset + c]));
+                    sum[0] += r * a;
+                    sum[1] += g * a;
+                    sum[2] += b * a;
+                    sum[3] += a;
+                    count += 1;
+                }
+            }
+            let alpha = sum[3];
+            let color = |c: u64| c.checked_div(alpha).unwrap_or(0) as u8;
+            pixels.extend([
+                color(sum[0]),
+                color(sum[1]),
+                color(sum[2]),
+                (alpha / count.max(1)) as u8,
+            ]);
+        }
+    }
+
+    Bitmap {
+        width,
+        height,
+        pixels,
+    }
+}
+
+/// reduce the bitmap to the 4 dvd pixel classes: background, pattern, emphasis 1 and
+/// emphasis 2.
+///
+/// opaque pixels are split by luminance, the brightest ones are usually the text fill and go
+/// to the pattern, the darkest ones are usually the outline and go to emphasis 1 and the ones
+/// in between, the anti aliasing, go to emphasis 2.
+/// returns the class of each pixel and the most common color and alpha of each class.
+#[allow(clippy::type_complexity)]
+fn quantize(bitmap: &Bitmap) -> (Vec<u8>, [Option<((u8, u8, u8), u8)>; 4]) {
+    const SINGLE_COLOR_RANGE: u32 = 32;
+
+    let luminance =
+        |p: &[u8]| (299 * u32::from(p[0]) + 587 * u32::from(p[1]) + 114 * u32::from(p[2])) / 1000;
+    let opaque = bitmap.pixels.chunks_exact(4).filter(|p| p[3] >= MIN_ALPHA);
+    let (min, max) = opaque.fold((u32::MAX, 0), |(min, max), p| {
+        let l = luminance(p);
+        (min.min(l), max.max(l))
+    });
+    let range = max.saturating_sub(min);
+
+    let mut counts: [HashMap<[u8; 4], usize>; 4] = Default::default();
+    let classes = bitmap
+        .pixels
+        .chunks_exact(4)
+        .map(|p| {
+            if p[3] < MIN_ALPHA {
+                return 0;
+            }
+            let l = luminance(p);
+            let class = if range < SINGLE_COLOR_RANGE || (l - min) * 3 >= range * 2 {
+                1
+            } else if (l - min) * 3 < range {
+                2
+            } else {
+                3
+            };
+            *counts[class].entry([p[0], p[1], p[2], p[3]]).or_default() += 1;
+            class as u8
+        })
+        .collect();
+
+    let mut colors = [None; 4];
+    for (class, counts) in counts.iter().enumerate().skip(1) {
+        colors[class] = counts
+            .iter()
+            .max_by_key(|&(rgba, count)| (*count, *rgba))
+            .map(|([r, g, b, a], _)| ((*r, *g, *b), *a));
+    }
+    (classes, colors)
+}
+
+struct SpuPicture<'a> {
+    /// one pixel class per byte
+    pixels: &'a [u8],
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    /// palette index of each pixel class
+    colors: [u8; 4],
+    /// alpha, from 0 to 15, of each pixel class
+    contrast: [u8; 4],
+    duration: Option<Duration>,
+    /// shown with the forced start display command.
+    forced: bool,
+}
+
+#[derive(Default)]
+struct NibbleWriter {
+    data: Vec<u8>,
+    half: bool,
+}
+
+impl NibbleWriter {
+    fn push(&mut self, nibble: u8) {
+        if self.half {
+            *self.data.last_mut().unwrap() |= nibble & 0xF;
+        } else {
+            self.data.push(nibble << 4);
+        }
+        self.half = !self.half;
+    }
+
+    fn align(&mut self) {
+        self.half = false;
+    }
+}
+
+/// run length encode every other line, starting at `first_line`, of the picture.
+///
+/// each run is a 2 bit pixel value and a count, written with 1 to 4 nibbles depending on the
+/// count. a count of 0 fills the rest of the line. lines always end on a byte boundary.
+fn encode_rle_field(pixels: &[u8], width: usize, first_line: usize) -> Vec<u8> {
+    let mut writer = NibbleWriter::default();
+    for line in pixels.chunks_exact(width).skip(first_line).step_by(2) {
+        let mut x = 0;
+        while x < line.len() {
+            let value = line[x];
+            let run = line[x..].iter().take_while(|&&v| v == value).count();
+            let (count, nibbles) = match run {
+                _ if x + run == line.len() && run > 255 => (0, 4),
+                1..=3 => (run, 1),
+                4..=15 => (run, 2),
+                16..=63 => (run, 3),
+                _ => (run.min(255), 4),
+            };
+            let code = (count << 2) | usize::from(value & 0x3);
+            for i in (0..nibbles).rev() {
+                writer.push((code >> (i * 4)) as u8);
+            }
+            x += if count == 0 { run } else { count };
+        }
+        writer.align();
+    }
+    writer.data
+}
+
+/// build the subpicture unit: the header, both fields of the picture and the control
+/// sequences that show it and, if the duration is known, hide it.
+fn encode_spu(picture: &SpuPicture) -> std::io::Result<Vec<u8>> {
+    let width = picture.width as usize;
+    let top_field = encode_rle_field(picture.pixels, width, 0);
+    let bottom_field = encode_rle_field(picture.pixels, width, 1);
+
+    let top_offset = 4;
+    let bottom_offset = top_offset + top_field.len();
+    let show_offset = bottom_offset + bottom_field.len();
+    let hide_offset = show_offset + 24;
+
+    let mut spu = vec![0u8; 4];
+    spu.extend(top_field);
+    spu.extend(bottom_field);
+
+    let nibbles = |v: [u8; 4]| [(v[3] << 4) | (v[2] & 0xF), (v[1] << 4) | (v[0] & 0xF)];
+    let (x1, x2) = (picture.x, picture.x + picture.width - 1);
+    let (y1, y2) = (picture.y, picture.y + picture.height - 1);
+    let next_offset = match picture.duration {
+        Some(_) => hide_offset,
+        None => show_offset,
+    };
+    spu.extend((0u16).to_be_bytes());
+    spu.extend((next_offset as u16).to_be_bytes());
+    spu.push(0x03);
+    spu.extend(nibbles(picture.colors));
+    spu.push(0x04);
+    spu.extend(nibbles(picture.contrast));
+    spu.push(0x05);
+    spu.extend([
+        (x1 >> 4) as u8,
+        ((x1 << 4) | (x2 >> 8)) as u8,
+        x2 as u8,
+        (y1 >> 4) as u8,
+        ((y1 << 4) | (y2 >> 8)) as u8,
+        y2 as u8,
+    ]);
+    spu.push(0x06);
+    spu.extend((top_offset as u16).to_be_bytes());
+    spu.extend((bottom_offset as u16).to_be_bytes());
+    // forced start display or start display
+    spu.push(if picture.forced { 0x00 } else { 0x01 });
+    spu.push(0xFF);
+    debug_assert_eq!(spu.len(), hide_offset);
+
+    if let Some(duration) = picture.duration {
+        let delay = (pgs::duration_to_clock(duration) + DELAY_UNIT / 2) / DELAY_UNIT;
+        spu.extend((delay.min(u64::from(u16::MAX)) as u16).to_be_bytes());
+        spu.extend((hide_offset as u16).to_be_bytes());
+        spu.push(0x02);
+        spu.push(0xFF);
+    }
+
+    if spu.len() > usize::from(u16::MAX) || show_offset > usize::from(u16::MAX) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "subtitle picture too large for a subpicture unit: {} bytes",
+                spu.len()
+            ),
+        ));
+    }
+    let size = spu.len() as u16;
+    spu[0..2].copy_from_slice(&size.to_be_bytes());
+    spu[2..4].copy_from_slice(&(show_offset as u16).to_be_bytes());
+    Ok(spu)
+}
+
+/// MPEG-2 pack header with the system clock reference set to `scr`.
+fn pack_header(scr: u64) -> [u8; PACK_HEADER_SIZE] {
+    let mux_rate = (MUX_RATE << 2) | 0x3;
+    [
+        0x00,
+        0x00,
+        0x01,
+        0xBA,
+        0x44 | (((scr >> 30) & 0x07) << 3) as u8 | ((scr >> 28) & 0x03) as u8,
+        (scr >> 20) as u8,
+        0x04 | (((scr >> 15) & 0x1F) << 3) as u8 | ((scr >> 13) & 0x03) as u8,
+        (scr >> 5) as u8,
+        0x04 | ((scr & 0x1F) << 3) as u8,
+        0x01,
+        (mux_rate >> 16) as u8,
+        (mux_rate >> 8) as u8,
+        mux_rate as u8,
+        0xF8,
+    ]
+}
+
+fn pes_pts(pts: u64) -> [u8; 5] {
+    [
+        0x21 | ((pts >> 29) & 0x0E) as u8,
+        (pts >> 22) as u8,
+        0x01 | ((pts >> 14) & 0xFE) as u8,
+        (pts >> 7) as u8,
+        0x01 | ((pts << 1) & 0xFE) as u8,
+    ]
+}
+
+/// split the subpicture unit into 2048 byte packs of private stream 1 packets.
+///
+/// only the first packet carries the pts. the last pack is filled with stuffing bytes in the
+/// packet header or, if there is enough space left, a padding packet.
+fn spu_packs(spu: &[u8], pts: u64) -> Vec<Vec<u8>> {
+    const PADDING_HEADER_SIZE: usize = 6;
+
+    let mut packs = Vec::new();
+    let mut remaining = spu;
+    while !remaining.is_empty() || packs.is_empty() {
+        let header_data = match packs.is_empty() {
+            true => pes_pts(pts).to_vec(),
+            false => Vec::new(),
+        };
+        // start code, length, flags, header data length, header data and substream id
+        let overhead = 9 + header_data.len() + 1;
+        let available = PACK_SIZE - PACK_HEADER_SIZE - overhead;
+        let payload_len = remaining.len().min(available);
+        let (payload, rest) = remaining.split_at(payload_len);
+        remaining = rest;
+
+        let gap = available - payload_len;
+        let (stuffing, padding) = match gap {
+            0..PADDING_HEADER_SIZE => (gap, 0),
+            _ => (0, gap),
+        };
+
+        let mut pack = Vec::with_capacity(PACK_SIZE);
+        pack.extend(pack_header(pts));
+        pack.extend([0x00, 0x00, 0x01, 0xBD]);
+        pack.extend(((3 + header_data.len() + stuffing + 1 + payload_len) as u16).to_be_bytes());
+        pack.push(0x81);
+        pack.push(if header_data.is_empty() { 0x00 } else { 0x80 });
+        pack.push((header_data.len() + stuffing) as u8);
+        pack.extend(header_data);
+        pack.extend(std::iter::repeat_n(0xFF, stuffing));
+        pack.push(SUBSTREAM_ID);
+        pack.extend(payload);
+        if padding > 0 {
+            pack.extend([0x00, 0x00, 0x01, 0xBE]);
+            pack.extend(((padding - PADDING_HEADER_SIZE) as u16).to_be_bytes());
+            pack.extend(std::iter::repeat_n(0xFF, padding - PADDING_HEADER_SIZE));
+        }
+        debug_assert_eq!(pack.len(), PACK_SIZE);
+        packs.push(pack);
+    }
+    packs
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Placement, TimeRange};
+
+    #[test]
+    fn test_vobsub_writer() {
+        let subtitle = |begin: u64, end: u64| BitmapSubtitle {
+            range: TimeRange::new(Duration::from_millis(begin), Duration::from_millis(end)),
+            bitmap: Bitmap {
+                width: 8,
+                height: 4,
+                pixels: [255, 255, 255, 255].repeat(32),
+            },
+            placement: Placement {
+                x: 960,
+                y: 540,
+                width: 8,
+                height: 4,
+                screen_width: 1920,
+                screen_height: 1080,
+            },
+            color: None,
+            forced: false,
+        };
+
+        let mut sub = Vec::new();
+        let mut idx = Vec::new();
+        let mut writer = VobSubWriter::new(&mut sub, &mut idx, VobSubOptions::default());
+        writer.push(&subtitle(1000, 2500)).unwrap();
+        writer.push(&subtitle(3_723_004, 0)).unwrap();
+        writer.finish().unwrap();
+
+        let idx = String::from_utf8(idx).unwrap();
+        assert!(idx.contains("size: 720x480\n"));
+        assert!(idx.contains("palette: ffffff, 000000, 000000,"));
+        assert!(idx.contains("id: en, index: 0\n"));
+        assert!(idx.ends_with(
+            "timestamp: 00:00:01:000, filepos: 000000000\n\
+             timestamp: 01:02:03:004, filepos: 000000800\n"
+        ));
+
+        assert_eq!(sub.len(), 2 * PACK_SIZE);
+        assert_eq!(&sub[0..4], &[0x00, 0x00, 0x01, 0xBA]);
+        assert_eq!(&sub[14..18], &[0x00, 0x00, 0x01, 0xBD]);
+        assert_eq!(sub[23], 0x21);
+        assert_eq!(sub[28], SUBSTREAM_ID);
+
+        // 3x2 picture at 360x240, two lines of 3 pattern pixels, one per field
+        let spu = &sub[29..];
+        assert_eq!(&spu[4..6], &[0xD0, 0xD0]);
+        let show = usize::from(u16::from_be_bytes([spu[2], spu[3]]));
+        assert_eq!(&spu[show + 4..show + 7], &[0x03, 0x00, 0x00]);
+        assert_eq!(&spu[show + 7..show + 10], &[0x04, 0x00, 0xF0]);
+        assert_eq!(
+            &spu[show + 10..show + 17],
+            &[0x05, 0x16, 0x81, 0x6A, 0x0F, 0x00, 0xF1]
+        );
+        // hidden after 1.5s, in units of 1024/90000s
+        let hide = usize::from(u16::from_be_bytes([spu[show + 2], spu[show + 3]]));
+        assert_eq!(
+            &spu[hide..hide + 6],
+            &[0x00, 132, spu[show + 2], spu[show + 3], 0x02, 0xFF]
+        );
+    }
+
+    #[test]
+    fn test_vobsub_language() {
+        assert_eq!(vobsub_language("est"), "et");
+        assert_eq!(vobsub_language("slk"), "sk");
+        assert_eq!(vobsub_language("chi_sim"), "zh");
+        assert_eq!(vobsub_language("lav+eng"), "lv");
+        assert_eq!(vobsub_language("enm"), "en");
+    }
+}
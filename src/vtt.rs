@@ -0,0 +1,190 @@
+use std::{io::Write, time::Duration};
+
+use crate::{
+    cue::{Cue, CueMerger},
+    italic::{ITALIC_CLOSE, ITALIC_OPEN},
+    Placement, SubtitleWriter, TextSubtitle,
+};
+
+pub fn vtt_duration_display(duration: Duration) -> impl std::fmt::Display {
+    struct VttDurationDisplay(Duration);
+
+    impl std::fmt::Display for VttDurationDisplay {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let total_secs = self.0.as_secs();
+            let hours = total_secs / 3600;
+            let minutes = (total_secs / 60) % 60;
+            let seconds = total_secs % 60;
+            let millis = self.0.subsec_millis();
+            write!(f, "{hours:02}:{minutes:02}:{seconds:02}.{millis:03}")
+        }
+    }
+
+    VttDurationDisplay(duration)
+}
+
+/// cue settings that place the cue box over the area where the bitmap was shown.
+///
+/// the text is centered horizontally on the bitmap and anchored vertically as told by
+/// [`Placement::anchored_at_bottom`].
+pub fn vtt_cue_settings(placement: &Placement) -> Option<String> {
+    if !placement.is_known() {
+        return None;
+    }
+
+    let percent = |value: u32, total: u32| f64::from(value) * 100.0 / f64::from(total);
+    let center_x = percent(
+        placement.x * 2 + placement.width,
+        placement.screen_width * 2,
+    );
+    let top = percent(placement.y, placement.screen_height);
+    let bottom = percent(placement.y + placement.height, placement.screen_height);
+
+    let line = if placement.anchored_at_bottom() {
+        format!("line:{:.2}%,end", bottom.min(100.0))
+    } else {
+        format!("line:{top:.2}%")
+    };
+    Some(format!(
+        "{line} position:{:.2}% align:center",
+        center_x.min(100.0)
+    ))
+}
+
+/// escape the characters that have a special meaning in cue text.
+/// empty lines are removed since they would end the cue.
+pub(crate) fn vtt_escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        if !escaped.is_empty() {
+            escaped.push('\n');
+        }
+        let mut rest = line;
+        while let Some(c) = rest.chars().next() {
+            // italic tags are WebVTT markup already
+            if let Some(tag) = [ITALIC_OPEN, ITALIC_CLOSE]
+                .into_iter()
+                .find(|tag| rest.starts_with(tag))
+            {
+                escaped.push_str(tag);
+                rest = &rest[tag.len()..];
+                continue;
+            }
+            rest = &rest[c.len_utf8()..];
+            match c {
+                '&' => escaped.push_str("&amp;"),
+                '<' => escaped.push_str("&lt;"),
+                '>' => escaped.push_str("&gt;"),
+                c => escaped.push(c),
+            }
+        }
+    }
+    escaped
+}
+
+/// Writes text subtitles in the WebVTT format as they are pushed.
+pub struct VttWriter<W> {
+    writer: W,
+    merger: CueMerger,
+    header_written: bool,
+}
+
+impl<W: Write> VttWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            merger: Default::default(),
+            header_written: false,
+        }
+    }
+
+    fn write_header(&mut self) -> std::io::Result<()> {
+        if !self.header_written {
+            self.writer.write_all(b"WEBVTT\n\n")?;
+            self.header_written = true;
+        }
+        Ok(())
+    }
+
+    fn write_cue(&mut self, cue: &Cue) -> std::io::Result<()> {
+        write!(
+            self.writer,
+            "{} --> {}",
+            vtt_duration_display(cue.range.begin),
+            vtt_duration_display(cue.range.end),
+        )?;
+        if let Some(settings) = vtt_cue_settings(&cue.placement()) {
+            write!(self.writer, " {settings}")?;
+        }
+        self.writer.write_all(b"\n")?;
+        self.writer
+            .write_all(vtt_escape_text(&cue.text).as_bytes())?;
+        self.writer.write_all(b"\n\n")?;
+        Ok(())
+    }
+}
+
+impl<W: Write> SubtitleWriter for VttWriter<W> {
+    fn push(&mut self, subtitle: TextSubtitle) -> std::io::Result<()> {
+        self.write_header()?;
+        for cue in self.merger.push(subtitle) {
+            self.write_cue(&cue)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.write_header()?;
+        for cue in self.merger.finish() {
+            self.write_cue(&cue)?;
+        }
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::TimeRange;
+
+    #[test]
+    fn test_vtt_writer() {
+        let subtitle = |begin: u64, end: u64, text: &str, y: u32| TextSubtitle {
+            range: TimeRange::new(Duration::from_millis(begin), Duration::from_millis(end)),
+            text: text.to_string(),
+            placement: Placement {
+                x: 760,
+                y,
+                width: 400,
+                height: 60,
+                screen_width: 1920,
+                screen_height: 1080,
+            },
+            color: None,
+            forced: false,
+            confidence: None,
+            words: Vec::new(),
+        };
+
+        let mut vtt = Vec::new();
+        let mut writer = VttWriter::new(&mut vtt);
+        writer
+            .push(subtitle(1000, 2500, "<Sign> & text", 100))
+            .unwrap();
+        writer
+            .push(subtitle(2000, 3000, "<i>Hello</i>\n\nthere\n", 900))
+            .unwrap();
+        writer.finish().unwrap();
+
+        assert_eq!(
+            String::from_utf8(vtt).unwrap(),
+            "WEBVTT\n\n\
+            00:00:01.000 --> 00:00:02.000 line:9.26% position:50.00% align:center\n\
+            &lt;Sign&gt; &amp; text\n\n\
+            00:00:02.000 --> 00:00:02.500 line:9.26% position:50.00% align:center\n\
+            &lt;Sign&gt; &amp; text\n<i>Hello</i>\nthere\n\n\
+            00:00:02.500 --> 00:00:03.000 line:88.89%,end position:50.00% align:center\n\
+            <i>Hello</i>\nthere\n\n"
+        );
+    }
+}